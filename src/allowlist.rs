@@ -0,0 +1,154 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use chrono::{NaiveDate, Utc};
+
+use crate::{log, Error, Result};
+
+/// A known regression that's accepted until its expiry date
+#[derive(Debug)]
+pub(crate) struct AllowlistEntry {
+    pub pattern: String,
+    pub expires: NaiveDate,
+}
+
+/// Path patterns whose regressions don't fail the baseline check
+#[derive(Debug, Default)]
+pub(crate) struct Allowlist {
+    entries: Vec<AllowlistEntry>,
+}
+
+/// Match a path against a glob pattern, where `*` matches anything except
+/// `/` and `**` matches anything including `/`
+fn glob_match(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((b'*', rest)) if rest.first() == Some(&b'*') => {
+            let rest = &rest[1..];
+            (0..=path.len()).any(|i| glob_match(rest, &path[i..]))
+        }
+        Some((b'*', rest)) => {
+            let segment_len = path.iter().position(|x| *x == b'/').unwrap_or(path.len());
+            (0..=segment_len).any(|i| glob_match(rest, &path[i..]))
+        }
+        Some((c, rest)) => path.first() == Some(c) && glob_match(rest, &path[1..]),
+    }
+}
+
+impl Allowlist {
+    /// Read an allowlist file. Each line has a path pattern and an expiry date
+    /// in YYYY-MM-DD format, separated by whitespace; anything after a # is a
+    /// comment.
+    pub(crate) fn load(path: &Path) -> Result<Allowlist> {
+        let f = File::open(path)?;
+        let mut entries = Vec::new();
+        for (i, line) in BufReader::new(f).lines().enumerate() {
+            let line = line?;
+            let line = match line.find('#') {
+                Some(idx) => &line[..idx],
+                None => &line
+            }.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let parts = line.split_whitespace().collect::<Vec<_>>();
+            let (pattern, expires) = match parts.as_slice() {
+                [pattern, expires] => (pattern, expires),
+                _ => return Err(Error::Data(format!("{}:{}: expected a pattern and an expiry date",
+                                                    path.display(), i + 1)))
+            };
+            let expires = NaiveDate::parse_from_str(expires, "%Y-%m-%d")
+                .map_err(|e| Error::Data(format!("{}:{}: invalid date {}: {}", path.display(), i + 1, expires, e)))?;
+            entries.push(AllowlistEntry {
+                pattern: pattern.to_string(),
+                expires,
+            });
+        }
+        let today = Utc::now().date_naive();
+        for entry in entries.iter().filter(|x| x.expires < today) {
+            log::warning(format_args!("allowlist entry {} expired on {}", entry.pattern, entry.expires));
+        }
+        Ok(Allowlist { entries })
+    }
+
+    /// Get the unexpired entry that covers a path, if any. The root
+    /// directory is matched by the pattern `/`.
+    pub(crate) fn find(&self, path: &str) -> Option<&AllowlistEntry> {
+        let today = Utc::now().date_naive();
+        self.entries
+            .iter()
+            .filter(|x| x.expires >= today)
+            .find(|x| glob_match(x.pattern.trim_end_matches('/').as_bytes(), path.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::{Duration, Utc};
+
+    use super::{glob_match, Allowlist, AllowlistEntry};
+    use crate::baseline::Baseline;
+    use crate::report::directory_totals;
+    use crate::report::tests::{file, report, temp_path};
+
+    #[test]
+    fn glob() {
+        assert!(glob_match(b"dom/media", b"dom/media"));
+        assert!(!glob_match(b"dom/media", b"dom/media/webrtc"));
+        assert!(glob_match(b"dom/*", b"dom/media"));
+        assert!(!glob_match(b"dom/*", b"dom/media/webrtc"));
+        assert!(glob_match(b"dom/**", b"dom/media/webrtc"));
+        assert!(glob_match(b"**/*.cpp", b"dom/media/MediaDecoder.cpp"));
+        assert!(!glob_match(b"*.cpp", b"dom/media/MediaDecoder.cpp"));
+        assert!(glob_match(b"", b""));
+    }
+
+    #[test]
+    fn load_and_find() {
+        let path = temp_path("allowlist");
+        let today = Utc::now().date_naive();
+        let data = format!("# Known regressions\n\ndom/media/** {}  # bug 1\nlayout/* {}\n/ {}\n",
+                           today + Duration::days(1), today - Duration::days(1), today);
+        fs::write(&path, data).unwrap();
+        let allowlist = Allowlist::load(&path);
+        fs::remove_file(&path).unwrap();
+        let allowlist = allowlist.unwrap();
+        assert_eq!(allowlist.entries.len(), 3);
+        assert_eq!(allowlist.find("dom/media/webrtc").map(|x| x.pattern.as_str()), Some("dom/media/**"));
+        // Expired
+        assert!(allowlist.find("layout/base").is_none());
+        assert_eq!(allowlist.find("").map(|x| x.pattern.as_str()), Some("/"));
+        assert!(allowlist.find("js").is_none());
+    }
+
+    #[test]
+    fn load_errors() {
+        for (idx, data) in ["dom/media\n", "dom/media 2020-13-01\n", "dom media 2020-01-01\n"].iter().enumerate() {
+            let path = temp_path(&format!("allowlist-{}", idx));
+            fs::write(&path, data).unwrap();
+            let allowlist = Allowlist::load(&path);
+            fs::remove_file(&path).unwrap();
+            assert!(allowlist.is_err(), "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn allowed_regressions() {
+        let before = report("abc", vec![file("dom/a.cpp", 0, 10, 100)]);
+        let baseline = Baseline::new(&before.metadata, &directory_totals(before.files.iter()));
+        let after = report("def", vec![file("dom/a.cpp", 0, 20, 100)]);
+        let directories = directory_totals(after.files.iter());
+        let allowlist = |pattern: &str| Allowlist {
+            entries: vec![AllowlistEntry {
+                pattern: pattern.to_owned(),
+                expires: Utc::now().date_naive() + Duration::days(1),
+            }],
+        };
+        // The root regressed too
+        assert!(baseline.check(&after.metadata, &directories, 1.0, &allowlist("dom")).is_err());
+        assert!(baseline.check(&after.metadata, &directories, 1.0, &allowlist("**")).is_ok());
+    }
+}
@@ -0,0 +1,157 @@
+use std::io::Write;
+
+use crate::export::LineRow;
+use crate::output::html::escape;
+use crate::report::RunMetadata;
+use crate::{CoverageDifference, CoverageType, Result};
+
+const RESET: &str = "\x1b[0m";
+
+fn terminal_style(coverage_type: CoverageType) -> (&'static str, &'static str) {
+    match coverage_type {
+        CoverageType::NotRun => (" ", ""),
+        CoverageType::NotCovered => ("-", "\x1b[31m"),
+        CoverageType::Suite1Only => ("1", "\x1b[34m"),
+        CoverageType::Suite2Only => ("2", "\x1b[32m"),
+        CoverageType::Both => ("B", "\x1b[2m"),
+    }
+}
+
+/// Write a source file with a marker for the coverage category of each
+/// line, colored when `color` is set.
+pub(crate) fn write_terminal<W: Write>(out: &mut W,
+                                       path: &str,
+                                       source: &str,
+                                       difference: &CoverageDifference,
+                                       color: bool) -> Result<()> {
+    writeln!(out, "{}", path)?;
+    let width = source.lines().count().to_string().len();
+    for (i, line) in source.lines().enumerate() {
+        let coverage_type = difference.line_differences.get(i).cloned().unwrap_or(CoverageType::NotRun);
+        let (marker, style) = terminal_style(coverage_type);
+        if color && !style.is_empty() {
+            writeln!(out, "{}{:>width$} {} {}{}", style, i + 1, marker, line, RESET, width = width)?;
+        } else {
+            writeln!(out, "{:>width$} {} {}", i + 1, marker, line, width = width)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write aligned columns of the line number, the hit counts in each suite and
+/// the coverage category for every line of a file. Lines that aren't coverable
+/// have no hit counts.
+pub(crate) fn write_side_by_side<W: Write + ?Sized>(out: &mut W,
+                                                    metadata: &RunMetadata,
+                                                    rows: &[LineRow],
+                                                    color: bool) -> Result<()> {
+    let hits = |count: i64| if count < 0 { "-".to_owned() } else { count.to_string() };
+    let line_width = rows.len().to_string().len().max("line".len());
+    let suite_1_width = rows
+        .iter()
+        .map(|x| hits(x.suite_1_hits).len())
+        .chain(Some(metadata.suite_1.len()))
+        .max()
+        .unwrap_or(0);
+    let suite_2_width = rows
+        .iter()
+        .map(|x| hits(x.suite_2_hits).len())
+        .chain(Some(metadata.suite_2.len()))
+        .max()
+        .unwrap_or(0);
+    writeln!(out, "{:>line_width$}  {:>suite_1_width$}  {:>suite_2_width$}  category",
+             "line",
+             metadata.suite_1,
+             metadata.suite_2,
+             line_width = line_width,
+             suite_1_width = suite_1_width,
+             suite_2_width = suite_2_width)?;
+    for row in rows {
+        let (_, style) = terminal_style(row.category);
+        let text = format!("{:>line_width$}  {:>suite_1_width$}  {:>suite_2_width$}  {}",
+                           row.line,
+                           hits(row.suite_1_hits),
+                           hits(row.suite_2_hits),
+                           row.category.name(),
+                           line_width = line_width,
+                           suite_1_width = suite_1_width,
+                           suite_2_width = suite_2_width);
+        if color && !style.is_empty() {
+            writeln!(out, "{}{}{}", style, text, RESET)?;
+        } else {
+            writeln!(out, "{}", text)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write a key to the markers used by `write_terminal`, with the number of
+/// lines in each category
+pub(crate) fn write_terminal_legend<W: Write>(out: &mut W,
+                                              metadata: &RunMetadata,
+                                              difference: &CoverageDifference,
+                                              color: bool) -> Result<()> {
+    let not_covered = difference.coverable_count - difference.covered_count;
+    let entries = [
+        (CoverageType::Suite1Only, format!("only {}", metadata.suite_1), difference.suite_1_only_count),
+        (CoverageType::Suite2Only, format!("only {}", metadata.suite_2), difference.suite_2_only_count),
+        (CoverageType::Both, "both".to_owned(), difference.both_count),
+        (CoverageType::NotCovered, "not covered".to_owned(), not_covered),
+    ];
+    for (coverage_type, description, count) in entries.iter() {
+        let (marker, style) = terminal_style(*coverage_type);
+        if color {
+            writeln!(out, "{}{} {}{}: {} lines", style, marker, description, RESET, count)?;
+        } else {
+            writeln!(out, "{} {}: {} lines", marker, description, count)?;
+        }
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+const STYLE: &str = r##"
+body { font-family: sans-serif; margin: 2em; }
+pre { font-size: 0.9em; }
+.line { display: block; }
+.lineno { display: inline-block; width: 5em; color: #888; text-align: right; margin-right: 1em; user-select: none; }
+.not-covered { background: #fdd; }
+.suite1-only { background: #ddf; }
+.suite2-only { background: #dfd; }
+.both { background: #f4f4f4; }
+.legend span { padding: 0 0.5em; margin-right: 0.5em; }
+"##;
+
+/// Write a source file as an HTML page with each line highlighted by its
+/// coverage category.
+pub(crate) fn write_html<W: Write>(out: &mut W,
+                                   metadata: &RunMetadata,
+                                   path: &str,
+                                   source: &str,
+                                   difference: &CoverageDifference) -> Result<()> {
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html lang=\"en\">")?;
+    writeln!(out, "<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>{}</title>", escape(path))?;
+    writeln!(out, "<style>{}</style>", STYLE)?;
+    writeln!(out, "<h1>{}</h1>", escape(path))?;
+    writeln!(out, "<p>{} vs {} at changeset {}</p>",
+             escape(&metadata.suite_1),
+             escape(&metadata.suite_2),
+             escape(&metadata.changeset))?;
+    writeln!(out, "<p class=\"legend\"><span class=\"suite1-only\">only {}</span><span class=\"suite2-only\">only {}</span><span class=\"both\">both</span><span class=\"not-covered\">not covered</span></p>",
+             escape(&metadata.suite_1),
+             escape(&metadata.suite_2))?;
+    write!(out, "<pre>")?;
+    for (i, line) in source.lines().enumerate() {
+        let coverage_type = difference.line_differences.get(i).cloned().unwrap_or(CoverageType::NotRun);
+        write!(out, "<span class=\"line {}\" id=\"L{}\"><span class=\"lineno\">{}</span>{}</span>",
+               coverage_type.name(),
+               i + 1,
+               i + 1,
+               escape(line))?;
+    }
+    writeln!(out, "</pre>")?;
+    writeln!(out, "</html>")?;
+    Ok(())
+}
@@ -0,0 +1,223 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use crate::{cache, log, parse, timings, Error, Result};
+
+/// Root of the coverage API; each version is served under its own prefix
+static API_ROOT: &str = "https://api.coverage.testing.moz.tools";
+
+/// Version of the coverage API to use
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ApiVersion {
+    /// Use v3 if the server supports it, and v2 otherwise
+    Auto,
+    V2,
+    /// Has the same endpoints and parameters as v2, but wraps responses in
+    /// an envelope that `compat` removes
+    V3,
+}
+
+impl ApiVersion {
+    fn prefix(self) -> &'static str {
+        match self {
+            ApiVersion::Auto | ApiVersion::V2 => "v2",
+            ApiVersion::V3 => "v3",
+        }
+    }
+}
+
+impl FromStr for ApiVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<ApiVersion, String> {
+        match s {
+            "auto" => Ok(ApiVersion::Auto),
+            "v2" | "2" => Ok(ApiVersion::V2),
+            "v3" | "3" => Ok(ApiVersion::V3),
+            _ => Err(format!("Unknown API version {}; expected auto, v2 or v3", s))
+        }
+    }
+}
+
+/// The version asked for on the command line
+static REQUESTED: OnceLock<ApiVersion> = OnceLock::new();
+
+/// The version in use, once it's known. With auto this is only probed on the
+/// first request, so that runs using cached data don't need the network.
+static VERSION: OnceLock<ApiVersion> = OnceLock::new();
+
+pub(crate) fn init(version: ApiVersion) {
+    let _ = REQUESTED.set(version);
+}
+
+/// Whether responses come from a fixture dataset in the cache directory
+/// instead of the network
+static FIXTURES: AtomicBool = AtomicBool::new(false);
+
+/// Answer requests from the fixture dataset in the cache directory, and
+/// refuse any other network access
+pub(crate) fn use_fixtures() {
+    FIXTURES.store(true, Ordering::Relaxed);
+}
+
+pub(crate) fn fixtures() -> bool {
+    FIXTURES.load(Ordering::Relaxed)
+}
+
+/// Use v3 if it answers, and otherwise v2, which is the version that's
+/// known to exist; a failure of the probe shouldn't stop the run, since
+/// the v2 requests will report any real problem with the network
+fn probe(client: &reqwest::Client) -> ApiVersion {
+    match crate::get(client, &format!("{}/{}/filters", API_ROOT, ApiVersion::V3.prefix()), None) {
+        Ok(_) => ApiVersion::V3,
+        Err(e) => {
+            log::debug(format_args!("Probing for coverage API v3 failed, so using v2: {:?}", e));
+            ApiVersion::V2
+        }
+    }
+}
+
+fn version(client: &reqwest::Client) -> Result<ApiVersion> {
+    if let Some(version) = VERSION.get() {
+        return Ok(*version);
+    }
+    let version = match REQUESTED.get().cloned().unwrap_or(ApiVersion::Auto) {
+        ApiVersion::Auto => {
+            let version = probe(client);
+            log::info(format_args!("Using coverage API {}", version.prefix()));
+            version
+        }
+        version => version
+    };
+    Ok(*VERSION.get_or_init(|| version))
+}
+
+/// A request to the coverage API
+pub(crate) enum Endpoint<'a> {
+    /// Coverage for a directory or file, by default for all suites and the
+    /// latest changeset
+    Path {
+        path: &'a str,
+        suite: Option<&'a str>,
+        changeset: Option<&'a str>,
+    },
+    /// The changesets with recent coverage data
+    Latest,
+    /// The suites and platforms that data can be filtered by
+    Filters,
+}
+
+fn url(version: ApiVersion, endpoint: &Endpoint) -> String {
+    let root = format!("{}/{}", API_ROOT, version.prefix());
+    match endpoint {
+        Endpoint::Path { path, suite, changeset } => {
+            let mut url = format!("{}/path?path={}", root, path);
+            if let Some(suite) = suite {
+                url.push_str(&format!("&suite={}", suite));
+            }
+            if let Some(changeset) = changeset {
+                url.push_str(&format!("&changeset={}", changeset));
+            }
+            url
+        }
+        Endpoint::Latest => format!("{}/latest", root),
+        Endpoint::Filters => format!("{}/filters", root),
+    }
+}
+
+/// File in a fixture dataset holding the response for a request. Entries
+/// for a suite are stored the same way as in the cache; other path queries
+/// are under an all/ directory for the changeset.
+pub(crate) fn fixture_path(endpoint: &Endpoint) -> Result<PathBuf> {
+    let root = cache::root();
+    Ok(match endpoint {
+        Endpoint::Latest => root.join("latest.json"),
+        Endpoint::Filters => root.join("filters.json"),
+        Endpoint::Path { path, suite, changeset } => {
+            let changeset = match changeset {
+                Some(changeset) => (*changeset).to_owned(),
+                None => {
+                    let latest: serde_json::Value = parse::from_file(&root.join("latest.json"))?;
+                    latest[0]["changeset"]
+                        .as_str()
+                        .ok_or_else(|| Error::Data("No changesets listed in the fixture latest.json".into()))?
+                        .to_owned()
+                }
+            };
+            let suite_root = cache::changeset_root(&changeset).join(cache::component(suite.unwrap_or("all")));
+            cache::file_path(&suite_root, if path.is_empty() { "root" } else { path }, "json")
+        }
+    })
+}
+
+/// Where the response to a request comes from, for error messages
+pub(crate) fn origin(endpoint: &Endpoint) -> String {
+    if fixtures() {
+        if let Ok(path) = fixture_path(endpoint) {
+            return path.display().to_string();
+        }
+    }
+    url(VERSION.get().cloned().unwrap_or(ApiVersion::V2), endpoint)
+}
+
+/// Make a request to the coverage API. Responses from any version should be
+/// parsed with `compat::from_str`.
+pub(crate) fn get(client: &reqwest::Client, endpoint: &Endpoint) -> Result<String> {
+    if fixtures() {
+        let path = fixture_path(endpoint)?;
+        log::debug(format_args!("Reading fixture {}", path.display()));
+        return timings::time(timings::Stage::CacheRead, || fs::read_to_string(&path)).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::Data(format!("No fixture for {}", url(ApiVersion::V2, endpoint))),
+            _ => Error::Io(e)
+        });
+    }
+    crate::get(client, &url(version(client)?, endpoint), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::{probe, url, ApiVersion, Endpoint};
+    use crate::compat;
+    use crate::fixtures::tests::init;
+
+    #[test]
+    fn versions() {
+        assert_eq!("auto".parse(), Ok(ApiVersion::Auto));
+        assert_eq!("2".parse(), Ok(ApiVersion::V2));
+        assert_eq!("v3".parse(), Ok(ApiVersion::V3));
+        assert!("v4".parse::<ApiVersion>().is_err());
+    }
+
+    #[test]
+    fn urls() {
+        let endpoint = Endpoint::Path {
+            path: "dom/base",
+            suite: Some("mochitest-plain"),
+            changeset: Some("abc"),
+        };
+        assert_eq!(url(ApiVersion::V2, &endpoint),
+                   "https://api.coverage.testing.moz.tools/v2/path?path=dom/base&suite=mochitest-plain&changeset=abc");
+        assert_eq!(url(ApiVersion::V3, &Endpoint::Latest), "https://api.coverage.testing.moz.tools/v3/latest");
+        assert_eq!(url(ApiVersion::Auto, &Endpoint::Filters), "https://api.coverage.testing.moz.tools/v2/filters");
+    }
+
+    #[test]
+    fn probe_fallback() {
+        // The probe can't reach the network with fixtures, so v2 is used
+        init();
+        assert_eq!(probe(&reqwest::Client::new()), ApiVersion::V2);
+    }
+
+    #[test]
+    fn v3_envelope() {
+        let v2 = r#"[{"changeset": "abc", "push": 1, "date": 2}]"#;
+        let v3 = r#"{"data": [{"changeset": "abc", "push": 1, "date": 2}], "meta": {"version": 3}}"#;
+        assert_eq!(compat::from_str::<Value>(v3).unwrap(), compat::from_str::<Value>(v2).unwrap());
+    }
+}
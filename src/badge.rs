@@ -0,0 +1,87 @@
+use std::io::Write;
+
+use crate::output::html::escape;
+use crate::output::ColorThresholds;
+use crate::report::{Report, Totals};
+use crate::{Error, Result};
+
+const RED: &str = "#e05d44";
+const YELLOW: &str = "#dfb317";
+const GREEN: &str = "#4c1";
+const GREY: &str = "#555";
+const BLUE: &str = "#007ec6";
+
+/// Approximate width of text in the 11px Verdana used by shields.io badges
+fn text_width(text: &str) -> usize {
+    text.chars()
+        .map(|c| match c {
+            'i' | 'l' | 'j' | '.' | ',' | ':' | '|' | '!' | '\'' => 4,
+            'm' | 'w' | 'M' | 'W' | '%' => 10,
+            c if c.is_ascii_uppercase() => 8,
+            _ => 7
+        })
+        .sum()
+}
+
+/// Write a shields.io style SVG badge showing one metric from the report
+/// totals. Percentages are colored using the same thresholds as the table
+/// output; counts are always blue.
+pub(crate) fn write_badge<W: Write>(out: &mut W,
+                                    report: &Report,
+                                    metric: &str,
+                                    label: Option<&str>,
+                                    thresholds: &ColorThresholds) -> Result<()> {
+    let totals = report.totals();
+    let value = totals.metric(metric).ok_or_else(|| {
+        Error::Usage(format!("Unknown metric {}; expected one of {}", metric, Totals::METRICS.join(", ")))
+    })?;
+    let (text, color) = if metric.ends_with("_percent") {
+        let color = if value >= thresholds.green {
+            GREEN
+        } else if value >= thresholds.yellow {
+            YELLOW
+        } else {
+            RED
+        };
+        (format!("{:.1}%", value), color)
+    } else {
+        (format!("{}", value), BLUE)
+    };
+    let metadata = &report.metadata;
+    let default_label;
+    let label = match label {
+        Some(label) => label,
+        None => {
+            default_label = match metric {
+                "suite_1_only_percent" | "suite_1_only" => format!("{} only", metadata.suite_1),
+                "suite_2_only_percent" | "suite_2_only" => format!("{} only", metadata.suite_2),
+                _ => metric.replace('_', " ")
+            };
+            &default_label
+        }
+    };
+
+    let label_width = text_width(label) + 10;
+    let value_width = text_width(&text) + 10;
+    let width = label_width + value_width;
+
+    writeln!(out, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"20\" role=\"img\" aria-label=\"{}: {}\">",
+             width, escape(label), escape(&text))?;
+    writeln!(out, "<title>{}: {}</title>", escape(label), escape(&text))?;
+    writeln!(out, "<linearGradient id=\"s\" x2=\"0\" y2=\"100%\"><stop offset=\"0\" stop-color=\"#bbb\" stop-opacity=\".1\"/>\
+                   <stop offset=\"1\" stop-opacity=\".1\"/></linearGradient>")?;
+    writeln!(out, "<clipPath id=\"r\"><rect width=\"{}\" height=\"20\" rx=\"3\" fill=\"#fff\"/></clipPath>", width)?;
+    writeln!(out, "<g clip-path=\"url(#r)\">")?;
+    writeln!(out, "<rect width=\"{}\" height=\"20\" fill=\"{}\"/>", label_width, GREY)?;
+    writeln!(out, "<rect x=\"{}\" width=\"{}\" height=\"20\" fill=\"{}\"/>", label_width, value_width, color)?;
+    writeln!(out, "<rect width=\"{}\" height=\"20\" fill=\"url(#s)\"/>", width)?;
+    writeln!(out, "</g>")?;
+    writeln!(out, "<g fill=\"#fff\" text-anchor=\"middle\" font-family=\"Verdana,Geneva,DejaVu Sans,sans-serif\" font-size=\"11\">")?;
+    for (x, value) in [(label_width / 2, label), (label_width + value_width / 2, text.as_str())] {
+        writeln!(out, "<text x=\"{}\" y=\"15\" fill=\"#010101\" fill-opacity=\".3\">{}</text>", x, escape(value))?;
+        writeln!(out, "<text x=\"{}\" y=\"14\">{}</text>", x, escape(value))?;
+    }
+    writeln!(out, "</g>")?;
+    writeln!(out, "</svg>")?;
+    Ok(())
+}
@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::allowlist::Allowlist;
+use crate::report::{RunMetadata, Totals};
+use crate::{log, parse, Error, PathFilter, Result};
+
+/// Version of the baseline file format
+const BASELINE_VERSION: u32 = 1;
+
+/// Expected unique coverage for a directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct BaselineEntry {
+    pub suite_2_only: i64,
+    pub suite_2_only_percent: f64,
+}
+
+impl BaselineEntry {
+    fn from_totals(totals: &Totals) -> BaselineEntry {
+        BaselineEntry {
+            suite_2_only: totals.suite_2_only,
+            suite_2_only_percent: totals.suite_2_only_percent(),
+        }
+    }
+}
+
+/// Expected per-directory unique coverage that later runs are checked
+/// against. The root directory is stored as "".
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Baseline {
+    pub version: u32,
+    pub suite_1: String,
+    pub suite_2: String,
+    pub changeset: String,
+    /// Path filters of the run the baseline came from, which later runs
+    /// need to match for their directory totals to be comparable
+    #[serde(default, skip_serializing_if = "PathFilter::is_empty")]
+    pub filters: PathFilter,
+    pub directories: BTreeMap<String, BaselineEntry>,
+}
+
+impl Baseline {
+    pub(crate) fn new(metadata: &RunMetadata, directories: &BTreeMap<String, Totals>) -> Baseline {
+        Baseline {
+            version: BASELINE_VERSION,
+            suite_1: metadata.suite_1.clone(),
+            suite_2: metadata.suite_2.clone(),
+            changeset: metadata.changeset.clone(),
+            filters: metadata.filters.clone(),
+            directories: directories
+                .iter()
+                .map(|(dir, totals)| (dir.clone(), BaselineEntry::from_totals(totals)))
+                .collect(),
+        }
+    }
+
+    /// Refuse to compare with a run whose path filters differ, since its
+    /// directories would only have the totals of some of their files
+    fn check_filters(&self, filters: &PathFilter) -> Result<()> {
+        if self.filters != *filters {
+            return Err(Error::Usage(format!("The baseline from {} was made with {}, but this run has {}",
+                                            self.changeset,
+                                            self.filters,
+                                            filters)));
+        }
+        Ok(())
+    }
+
+    /// Combine with a baseline from a newer run, only taking entries from the
+    /// newer run where they improve on this baseline. Directories that are
+    /// only in one of the baselines are kept.
+    pub(crate) fn ratchet(mut self, newer: Baseline) -> Result<Baseline> {
+        self.check_filters(&newer.filters)?;
+        let mut improved = 0;
+        for (dir, entry) in newer.directories.into_iter() {
+            match self.directories.get(&dir) {
+                Some(existing) if existing.suite_2_only_percent <= entry.suite_2_only_percent => {}
+                Some(_) => {
+                    improved += 1;
+                    self.directories.insert(dir, entry);
+                }
+                None => {
+                    self.directories.insert(dir, entry);
+                }
+            }
+        }
+        log::info(format_args!("Ratcheted {} improved directories", improved));
+        self.changeset = newer.changeset;
+        Ok(self)
+    }
+
+    pub(crate) fn load(path: &Path) -> Result<Baseline> {
+        let baseline: Baseline = parse::from_file(path)?;
+        if baseline.version > BASELINE_VERSION {
+            return Err(Error::Data(format!("Baseline {} has version {}, but only versions up to {} are supported",
+                                           path.display(),
+                                           baseline.version,
+                                           BASELINE_VERSION)));
+        }
+        Ok(baseline)
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        let mut f = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut f, self)?;
+        writeln!(f)?;
+        f.flush()?;
+        Ok(())
+    }
+
+    /// Check the current directory totals against the baseline. A directory
+    /// regresses when the percentage of its lines that are only covered by
+    /// suite 2 grows by more than `tolerance` percentage points. Each
+    /// regression is logged, and the check fails if there are any that
+    /// aren't covered by the allowlist.
+    pub(crate) fn check(&self,
+                        metadata: &RunMetadata,
+                        directories: &BTreeMap<String, Totals>,
+                        tolerance: f64,
+                        allowlist: &Allowlist) -> Result<()> {
+        if self.suite_1 != metadata.suite_1 || self.suite_2 != metadata.suite_2 {
+            log::warning(format_args!("baseline compares different suites ({} vs {})", self.suite_1, self.suite_2));
+        }
+        self.check_filters(&metadata.filters)?;
+        let mut regressions = 0;
+        for (dir, expected) in self.directories.iter() {
+            let current = match directories.get(dir) {
+                Some(totals) => BaselineEntry::from_totals(totals),
+                None => continue
+            };
+            let change = current.suite_2_only_percent - expected.suite_2_only_percent;
+            if change > tolerance {
+                let message = format!("{}: {} only {:.2}% -> {:.2}% ({:+.2}, {} -> {} lines)",
+                                      if dir.is_empty() { "/" } else { dir },
+                                      metadata.suite_2,
+                                      expected.suite_2_only_percent,
+                                      current.suite_2_only_percent,
+                                      change,
+                                      expected.suite_2_only,
+                                      current.suite_2_only);
+                match allowlist.find(dir) {
+                    Some(entry) => {
+                        log::verdict("ALLOWED",
+                                     format_args!("{} (matches {} until {})", message, entry.pattern, entry.expires))
+                    }
+                    None => {
+                        log::verdict("REGRESSION", format_args!("{}", message));
+                        regressions += 1;
+                    }
+                }
+            }
+        }
+        if regressions > 0 {
+            return Err(Error::Threshold(format!("{} directories regressed relative to the baseline from {}",
+                                                regressions,
+                                                self.changeset)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::Baseline;
+    use crate::allowlist::Allowlist;
+    use crate::report::tests::{file, report, temp_path};
+    use crate::report::{directory_totals, Report};
+    use crate::Error;
+
+    fn baseline(report: &Report) -> Baseline {
+        Baseline::new(&report.metadata, &directory_totals(report.files.iter()))
+    }
+
+    #[test]
+    fn regressions() {
+        let before = report("abc", vec![file("dom/a.cpp", 0, 10, 100), file("js/b.cpp", 0, 10, 100)]);
+        let baseline = baseline(&before);
+        assert_eq!(baseline.directories.keys().collect::<Vec<_>>(), vec!["", "dom", "js"]);
+
+        let after = report("def", vec![file("dom/a.cpp", 0, 11, 100), file("js/b.cpp", 0, 9, 100)]);
+        let directories = directory_totals(after.files.iter());
+        assert!(baseline.check(&after.metadata, &directories, 1.0, &Allowlist::default()).is_ok());
+        assert!(matches!(baseline.check(&after.metadata, &directories, 0.5, &Allowlist::default()),
+                         Err(Error::Threshold(_))));
+
+        // Directories that are gone aren't regressions
+        let after = report("def", vec![file("dom/a.cpp", 0, 10, 100)]);
+        let directories = directory_totals(after.files.iter());
+        assert!(baseline.check(&after.metadata, &directories, 0.0, &Allowlist::default()).is_ok());
+    }
+
+    #[test]
+    fn filters() {
+        let before = report("abc", vec![file("dom/a.cpp", 0, 10, 100)]);
+        let baseline = baseline(&before);
+        let mut after = report("def", vec![file("dom/a.cpp", 0, 10, 100)]);
+        after.metadata.filters = serde_json::from_str(r#"{"include": ["dom"]}"#).unwrap();
+        let directories = directory_totals(after.files.iter());
+        assert!(matches!(baseline.check(&after.metadata, &directories, 0.0, &Allowlist::default()),
+                         Err(Error::Usage(_))));
+    }
+
+    #[test]
+    fn ratchet() {
+        let older = baseline(&report("abc", vec![file("dom/a.cpp", 0, 10, 100), file("js/b.cpp", 0, 10, 100)]));
+        let newer = baseline(&report("def", vec![file("dom/a.cpp", 0, 20, 100),
+                                                 file("js/b.cpp", 0, 5, 100),
+                                                 file("layout/c.cpp", 0, 1, 100)]));
+        let ratcheted = older.ratchet(newer).unwrap();
+        assert_eq!(ratcheted.changeset, "def");
+        // Only the improving direction is taken
+        assert_eq!(ratcheted.directories["dom"].suite_2_only, 10);
+        assert_eq!(ratcheted.directories["js"].suite_2_only, 5);
+        assert_eq!(ratcheted.directories["layout"].suite_2_only, 1);
+    }
+
+    #[test]
+    fn ratchet_filters() {
+        let older = baseline(&report("abc", vec![file("dom/a.cpp", 0, 10, 100)]));
+        let mut newer = report("def", vec![file("dom/a.cpp", 0, 10, 100)]);
+        newer.metadata.filters = serde_json::from_str(r#"{"max_depth": 1}"#).unwrap();
+        assert!(matches!(older.ratchet(baseline(&newer)), Err(Error::Usage(_))));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let baseline = baseline(&report("abc", vec![file("dom/a.cpp", 0, 10, 40)]));
+        let path = temp_path("baseline.json");
+        baseline.save(&path).unwrap();
+        let loaded = Baseline::load(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.changeset, "abc");
+        assert_eq!(loaded.directories["dom"].suite_2_only, 10);
+        assert_eq!(loaded.directories["dom"].suite_2_only_percent, 25f64);
+    }
+}
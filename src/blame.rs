@@ -0,0 +1,465 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+
+use chrono::{DateTime, Months, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::output::{self, Format, OutputOptions};
+use crate::report::{self, RunMetadata};
+use crate::source::HG_URL;
+use crate::{cache, export, get, log, parse, CoverageDifference, CoverageType, Error, Result};
+
+/// The commit that last touched a line
+#[derive(Debug, Clone)]
+pub(crate) struct LineBlame {
+    pub author: String,
+    /// First line of the commit message
+    pub summary: String,
+    /// When the commit was authored, if the source gives it
+    pub time: Option<DateTime<Utc>>,
+}
+
+impl LineBlame {
+    /// The bug number in the commit message, which in gecko starts "Bug NNN"
+    fn bug(&self) -> Option<u64> {
+        let lower = self.summary.to_lowercase();
+        lower.match_indices("bug").find_map(|(idx, _)| {
+            lower[idx + 3..]
+                .trim_start_matches([' ', '-', ':'])
+                .chars()
+                .take_while(|x| x.is_ascii_digit())
+                .collect::<String>()
+                .parse()
+                .ok()
+        })
+    }
+}
+
+/// A time in seconds since the epoch, as given by git and mercurial
+fn timestamp(seconds: f64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(seconds as i64, 0)
+}
+
+#[derive(Debug, Deserialize)]
+struct HgAnnotateResponse {
+    annotate: Vec<HgAnnotateLine>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct HgAnnotateLine {
+    #[serde(alias = "user")]
+    author: String,
+    desc: String,
+    /// Seconds since the epoch and timezone offset of the commit
+    #[serde(alias = "date")]
+    revdate: Option<(f64, i64)>,
+}
+
+/// Blame for each line of a gecko file at a changeset from hg.mozilla.org's
+/// annotate API, caching it under the changeset data directory
+fn hg_annotate(client: &reqwest::Client, root_path: &Path, changeset: &str, path: &str) -> Result<Vec<LineBlame>> {
+    let local_path = cache::file_path(&root_path.join("annotate"), path, "json");
+    let body = if local_path.exists() {
+        fs::read_to_string(&local_path)?
+    } else {
+        if let Some(parent) = local_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let url = format!("{}/json-annotate/{}/{}", HG_URL, changeset, path);
+        let resp_str = get(client, &url, None)?;
+        cache::write(&local_path, resp_str.as_bytes())?;
+        resp_str
+    };
+    let data: HgAnnotateResponse = parse::from_cache(&local_path, &body)?;
+    Ok(data.annotate
+        .into_iter()
+        .map(|x| LineBlame {
+            author: x.author,
+            summary: x.desc.lines().next().unwrap_or("").to_owned(),
+            time: x.revdate.and_then(|(seconds, _)| timestamp(seconds)),
+        })
+        .collect())
+}
+
+fn run(srcdir: &Path, program: &str, args: &[&str]) -> Result<String> {
+    log::debug(format_args!("Running {} {}", program, args.join(" ")));
+    let output = Command::new(program).args(args).current_dir(srcdir).output()?;
+    if !output.status.success() {
+        return Err(Error::String(format!("{} {} failed: {}",
+                                         program,
+                                         args.join(" "),
+                                         String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Blame from `git blame --line-porcelain`, which repeats the commit headers
+/// for every line, each of which ends with the line's content after a tab
+fn git_blame(srcdir: &Path, path: &str) -> Result<Vec<LineBlame>> {
+    let output = run(srcdir, "git", &["blame", "--line-porcelain", "--", path])?;
+    let mut rv = Vec::new();
+    let mut author = String::new();
+    let mut summary = String::new();
+    let mut time = None;
+    for line in output.lines() {
+        if line.starts_with('\t') {
+            rv.push(LineBlame {
+                author: std::mem::take(&mut author),
+                summary: std::mem::take(&mut summary),
+                time: time.take(),
+            });
+        } else if let Some(value) = line.strip_prefix("author ") {
+            author = value.to_owned();
+        } else if let Some(value) = line.strip_prefix("author-time ") {
+            time = value.parse().ok().and_then(timestamp);
+        } else if let Some(value) = line.strip_prefix("summary ") {
+            summary = value.to_owned();
+        }
+    }
+    Ok(rv)
+}
+
+#[derive(Debug, Deserialize)]
+struct HgAnnotateFile {
+    lines: Vec<HgAnnotateFileLine>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HgAnnotateFileLine {
+    node: String,
+    user: String,
+    #[serde(default)]
+    date: Option<(f64, i64)>,
+}
+
+/// Blame from `hg annotate`, which doesn't have the commit messages, so
+/// those are looked up with a single `hg log` of every commit it lists
+fn hg_blame(srcdir: &Path, path: &str) -> Result<Vec<LineBlame>> {
+    let output = run(srcdir, "hg", &["annotate", "--template", "json", "--user", "--changeset", "--date", "--", path])?;
+    let files: Vec<HgAnnotateFile> = parse::from_str(&format!("hg annotate {}", path), &output)?;
+    let lines = files.into_iter().flat_map(|x| x.lines).collect::<Vec<_>>();
+    let mut nodes = lines.iter().map(|x| x.node.as_str()).collect::<Vec<_>>();
+    nodes.sort_unstable();
+    nodes.dedup();
+    let mut summaries = BTreeMap::new();
+    if !nodes.is_empty() {
+        let revset = nodes.join(" + ");
+        let log = run(srcdir, "hg", &["log", "--rev", &revset, "--template", "{node|short}\\t{desc|firstline}\\n"])?;
+        for line in log.lines() {
+            if let Some((node, summary)) = line.split_once('\t') {
+                summaries.insert(node.to_owned(), summary.to_owned());
+            }
+        }
+    }
+    Ok(lines
+        .into_iter()
+        .map(|x| LineBlame {
+            summary: summaries
+                .iter()
+                .find(|(node, _)| x.node.starts_with(node.as_str()))
+                .map(|(_, summary)| summary.clone())
+                .unwrap_or_default(),
+            author: x.user,
+            time: x.date.and_then(|(seconds, _)| timestamp(seconds)),
+        })
+        .collect())
+}
+
+/// Where line blame comes from
+pub(crate) enum BlameSource<'a> {
+    /// A local checkout, which should be at the changeset being compared
+    Checkout(&'a Path),
+    /// hg.mozilla.org's annotate API at the changeset being compared
+    Hg {
+        client: &'a reqwest::Client,
+        root_path: &'a Path,
+        changeset: &'a str,
+    },
+}
+
+impl BlameSource<'_> {
+    fn blame(&self, path: &str) -> Result<Vec<LineBlame>> {
+        match *self {
+            BlameSource::Checkout(srcdir) if srcdir.join(".git").exists() => git_blame(srcdir, path),
+            BlameSource::Checkout(srcdir) if srcdir.join(".hg").exists() => hg_blame(srcdir, path),
+            BlameSource::Checkout(srcdir) => {
+                Err(Error::Usage(format!("{} isn't a git or mercurial checkout", srcdir.display())))
+            }
+            BlameSource::Hg { client, root_path, changeset } => hg_annotate(client, root_path, changeset, path),
+        }
+    }
+}
+
+/// Number of lines in a category attributed to each author and bug
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct Attribution {
+    pub lines: usize,
+    /// Lines that couldn't be blamed, e.g. because the file couldn't be
+    /// annotated or has changed since the changeset
+    pub unattributed: usize,
+    /// Lines whose commit message doesn't name a bug
+    pub no_bug: usize,
+    pub authors: BTreeMap<String, usize>,
+    pub bugs: BTreeMap<u64, usize>,
+}
+
+impl Attribution {
+    fn add(&mut self, blame: Option<&LineBlame>) {
+        self.lines += 1;
+        let blame = match blame {
+            Some(blame) => blame,
+            None => {
+                self.unattributed += 1;
+                return;
+            }
+        };
+        *self.authors.entry(blame.author.clone()).or_default() += 1;
+        match blame.bug() {
+            Some(bug) => *self.bugs.entry(bug).or_default() += 1,
+            None => self.no_bug += 1,
+        }
+    }
+
+    /// The `limit` entries of `counts` with the most lines
+    fn top<K: Clone + Ord>(counts: &BTreeMap<K, usize>, limit: usize) -> Vec<(K, usize)> {
+        let mut rv = counts.iter().map(|(k, v)| (k.clone(), *v)).collect::<Vec<_>>();
+        rv.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        rv.truncate(limit);
+        rv
+    }
+}
+
+/// Blame every file with lines in `categories`, and call `f` with the
+/// category and blame of each of those lines. Lines in files that can't be
+/// blamed have no blame.
+fn blame_lines<F>(source: &BlameSource,
+                  differences: &BTreeMap<String, CoverageDifference>,
+                  categories: &[CoverageType],
+                  mut f: F) -> Result<()>
+    where F: FnMut(CoverageType, Option<&LineBlame>) {
+    for (path, difference) in differences.iter() {
+        let lines = difference.line_differences
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| categories.contains(x))
+            .map(|(i, x)| (i, *x))
+            .collect::<Vec<_>>();
+        if lines.is_empty() {
+            continue;
+        }
+        let blame = match source.blame(path) {
+            Ok(blame) => blame,
+            Err(e @ Error::Usage(_)) => return Err(e),
+            Err(e) => {
+                log::warning(format_args!("Couldn't blame {}: {:?}", path, e));
+                Vec::new()
+            }
+        };
+        for (line, category) in lines {
+            f(category, blame.get(line));
+        }
+    }
+    Ok(())
+}
+
+/// Blame the lines in `category` of every file with any, and count them by
+/// author and bug. Files that can't be blamed are counted as unattributed.
+pub(crate) fn attribute(source: &BlameSource,
+                        differences: &BTreeMap<String, CoverageDifference>,
+                        category: CoverageType) -> Result<Attribution> {
+    let mut rv = Attribution::default();
+    blame_lines(source, differences, &[category], |_, blame| rv.add(blame))?;
+    Ok(rv)
+}
+
+pub(crate) fn write_attribution<W: Write>(out: &mut W,
+                                          format: Format,
+                                          metadata: &RunMetadata,
+                                          attribution: &Attribution,
+                                          category: CoverageType,
+                                          top: usize,
+                                          options: &OutputOptions) -> Result<()> {
+    let authors = Attribution::top(&attribution.authors, top);
+    let bugs = Attribution::top(&attribution.bugs, top);
+    match format {
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, attribution)?;
+            writeln!(out)?;
+        }
+        Format::Csv => {
+            let mut writer = output::csv_writer(out, options);
+            if options.header {
+                writer.write_record(["kind", "name", "lines"])?;
+            }
+            for (author, lines) in authors {
+                writer.write_record(&["author".to_owned(), author, lines.to_string()])?;
+            }
+            for (bug, lines) in bugs {
+                writer.write_record(&["bug".to_owned(), bug.to_string(), lines.to_string()])?;
+            }
+            writer.flush()?;
+        }
+        Format::Table => {
+            writeln!(out, "Lines {} at {}", export::describe_category(category, metadata), metadata.changeset)?;
+            writeln!(out, "  lines: {}", attribution.lines)?;
+            writeln!(out, "  unattributed: {}", attribution.unattributed)?;
+            writeln!(out, "  without a bug: {}", attribution.no_bug)?;
+            writeln!(out)?;
+            writeln!(out, "Top authors")?;
+            for (author, lines) in authors {
+                writeln!(out, "  {:>7}  {}", lines, author)?;
+            }
+            writeln!(out)?;
+            writeln!(out, "Top bugs")?;
+            for (bug, lines) in bugs {
+                writeln!(out, "  {:>7}  https://bugzilla.mozilla.org/show_bug.cgi?id={}", lines, bug)?;
+            }
+        }
+        _ => return Err(Error::Usage("blame can only be written as a table, csv or json".into()))
+    }
+    Ok(())
+}
+
+/// Number of lines in a category last changed in each age band
+#[derive(Debug, Serialize)]
+pub(crate) struct CategoryAges {
+    #[serde(skip)]
+    kind: CoverageType,
+    pub category: &'static str,
+    pub lines: usize,
+    /// Lines whose commit time isn't known, e.g. because the file couldn't
+    /// be annotated or has changed since the changeset
+    pub unattributed: usize,
+    pub ages: Vec<AgeCount>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct AgeCount {
+    pub age: String,
+    pub lines: usize,
+}
+
+/// Lines in each category by the age of the commit that last changed them
+#[derive(Debug, Serialize)]
+pub(crate) struct LineAges {
+    /// The time ages are measured back from
+    pub reference: DateTime<Utc>,
+    pub categories: Vec<CategoryAges>,
+}
+
+/// Labels for the bands between each of the `months` boundaries, which
+/// are sorted
+fn age_labels(months: &[u32]) -> Vec<String> {
+    let mut rv = vec![format!("under {} months", months[0])];
+    rv.extend(months.windows(2).map(|x| format!("{} to {} months", x[0], x[1])));
+    rv.push(format!("{} months or more", months[months.len() - 1]));
+    rv
+}
+
+/// Blame the lines in each of `categories`, and count them by how many
+/// months before `reference` they were last changed, split at each of
+/// `months`. This shows whether lines are in new code or older code.
+pub(crate) fn line_ages(source: &BlameSource,
+                        differences: &BTreeMap<String, CoverageDifference>,
+                        categories: &[CoverageType],
+                        months: &[u32],
+                        reference: DateTime<Utc>) -> Result<LineAges> {
+    let mut months = months.to_vec();
+    months.sort_unstable();
+    months.dedup();
+    if months.is_empty() {
+        return Err(Error::Usage("--age-months needs at least one number of months".into()));
+    }
+    let cutoffs = months
+        .iter()
+        .map(|x| reference.checked_sub_months(Months::new(*x)).unwrap_or(DateTime::<Utc>::MIN_UTC))
+        .collect::<Vec<_>>();
+    let labels = age_labels(&months);
+    let mut rv = categories
+        .iter()
+        .map(|x| CategoryAges {
+            kind: *x,
+            category: x.name(),
+            lines: 0,
+            unattributed: 0,
+            ages: labels.iter().map(|age| AgeCount { age: age.clone(), lines: 0 }).collect(),
+        })
+        .collect::<Vec<_>>();
+    blame_lines(source, differences, categories, |category, blame| {
+        let entry = match rv.iter_mut().find(|x| x.kind == category) {
+            Some(entry) => entry,
+            None => return,
+        };
+        entry.lines += 1;
+        match blame.and_then(|x| x.time) {
+            Some(time) => {
+                let idx = cutoffs.iter().position(|x| time > *x).unwrap_or(cutoffs.len());
+                entry.ages[idx].lines += 1;
+            }
+            None => entry.unattributed += 1,
+        }
+    })?;
+    Ok(LineAges { reference, categories: rv })
+}
+
+pub(crate) fn write_line_ages<W: Write>(out: &mut W,
+                                        format: Format,
+                                        metadata: &RunMetadata,
+                                        ages: &LineAges,
+                                        options: &OutputOptions) -> Result<()> {
+    match format {
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, ages)?;
+            writeln!(out)?;
+        }
+        Format::Csv => {
+            let mut writer = output::csv_writer(out, options);
+            if options.header {
+                writer.write_record(["category", "age", "lines", "percent"])?;
+            }
+            for category in ages.categories.iter() {
+                let rows = category.ages
+                    .iter()
+                    .map(|x| (x.age.as_str(), x.lines))
+                    .chain(Some(("unknown", category.unattributed)));
+                for (age, lines) in rows {
+                    writer.write_record(&[category.category.to_owned(),
+                                          age.to_owned(),
+                                          lines.to_string(),
+                                          report::percent(lines as i64, category.lines as i64).to_string()])?;
+                }
+            }
+            writer.flush()?;
+        }
+        Format::Table => {
+            writeln!(out,
+                     "Age of lines at {}, as of {}",
+                     metadata.changeset,
+                     ages.reference.format("%Y-%m-%d"))?;
+            for category in ages.categories.iter() {
+                writeln!(out)?;
+                writeln!(out,
+                         "Lines {}: {}",
+                         export::describe_category(category.kind, metadata),
+                         category.lines)?;
+                let rows = category.ages
+                    .iter()
+                    .map(|x| (x.age.as_str(), x.lines))
+                    .chain(Some(("unknown age", category.unattributed)));
+                for (age, lines) in rows {
+                    writeln!(out,
+                             "  {:<20} {:>7}  {:>5.1}%",
+                             age,
+                             lines,
+                             report::percent(lines as i64, category.lines as i64))?;
+                }
+            }
+        }
+        _ => return Err(Error::Usage("line-age can only be written as a table, csv or json".into()))
+    }
+    Ok(())
+}
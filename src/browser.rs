@@ -0,0 +1,29 @@
+use std::env;
+use std::process::{Command, Stdio};
+
+use crate::log;
+
+/// Open a file or URL in the system browser, or the one in $BROWSER. The
+/// browser is left running; failures to launch it are only logged.
+pub(crate) fn open(target: &str) {
+    let mut command = match env::var("BROWSER") {
+        Ok(browser) if !browser.is_empty() => Command::new(browser),
+        _ if cfg!(target_os = "macos") => Command::new("open"),
+        _ if cfg!(windows) => {
+            let mut command = Command::new("cmd");
+            command.args(["/C", "start", ""]);
+            command
+        }
+        _ => Command::new("xdg-open"),
+    };
+    log::info(format_args!("Opening {}", target));
+    let result = command
+        .arg(target)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    if let Err(e) = result {
+        log::warning(format_args!("Failed to open {} in a browser: {}", target, e));
+    }
+}
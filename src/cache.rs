@@ -0,0 +1,148 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+/// Directory that data is cached in, when it isn't the default of data/ in
+/// the working directory
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+pub(crate) fn set_root(root: &Path) {
+    let _ = ROOT.set(root.to_owned());
+}
+
+pub(crate) fn root() -> PathBuf {
+    ROOT.get().cloned().unwrap_or_else(|| PathBuf::from("data"))
+}
+
+/// Directory for the cached data for a changeset
+pub(crate) fn changeset_root(changeset: &str) -> PathBuf {
+    root().join(component(changeset))
+}
+
+/// Count of temporary files written, to give each a unique name
+static TEMPORARY: AtomicUsize = AtomicUsize::new(0);
+
+/// Write a cache file so that it only appears once it's complete, since
+/// another thread may be reading the same file
+pub(crate) fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(format!(".{}-{}.tmp", process::id(), TEMPORARY.fetch_add(1, Ordering::Relaxed)));
+    let temporary = path.with_file_name(name);
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temporary);
+    })
+}
+
+/// Names that Windows reserves for devices, whatever the extension
+const RESERVED_NAMES: &[&str] = &[
+    "con", "prn", "aux", "nul",
+    "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8", "com9",
+    "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// Longest file name that's used as is; longer ones are shortened and given
+/// a hash suffix, to stay clear of the 260 character path limit on Windows
+const MAX_NAME_LENGTH: usize = 100;
+
+/// 64-bit FNV-1a, which unlike the std hashers is stable between releases
+pub(crate) fn fnv1a<I: IntoIterator<Item = u8>>(data: I) -> u64 {
+    data.into_iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Make one path component safe to use on any platform. Names that are
+/// already safe are unchanged, so existing caches stay valid.
+pub(crate) fn component(name: &str) -> String {
+    let mut rv = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    if rv.ends_with('.') || rv.ends_with(' ') {
+        rv.push('_');
+    }
+    let stem = rv.split('.').next().unwrap_or("").trim_end().to_lowercase();
+    if RESERVED_NAMES.contains(&stem.as_str()) {
+        rv.insert(0, '_');
+    }
+    if rv.len() > MAX_NAME_LENGTH {
+        let mut end = MAX_NAME_LENGTH - 17;
+        while !rv.is_char_boundary(end) {
+            end -= 1;
+        }
+        rv = format!("{}-{:016x}", &rv[..end], fnv1a(name.bytes()));
+    }
+    rv
+}
+
+/// Path of the cache file for a gecko path or other key containing slashes,
+/// flattened into a single file name in `root`
+pub(crate) fn file_path(root: &Path, key: &str, extension: &str) -> PathBuf {
+    let name = format!("{}.{}", key.replace('/', "-"), extension);
+    long_path(&root.join(component(&name)))
+}
+
+/// Path of a cached copy of a file under `root` that mirrors the file's
+/// path in the source tree
+pub(crate) fn tree_path(root: &Path, path: &str) -> PathBuf {
+    let mut rv = root.to_owned();
+    for part in path.split('/').filter(|x| !x.is_empty()) {
+        rv.push(component(part));
+    }
+    long_path(&rv)
+}
+
+/// On Windows, turn a path that's too long for the usual APIs into an
+/// extended-length \\?\ path. Elsewhere paths are returned as they are.
+pub(crate) fn long_path(path: &Path) -> PathBuf {
+    if cfg!(windows) && path.as_os_str().len() > 200 {
+        if let Ok(absolute) = std::path::absolute(path) {
+            let absolute = absolute.to_string_lossy().into_owned();
+            if let Some(unc) = absolute.strip_prefix(r"\\") {
+                if !unc.starts_with(r"?\") {
+                    return PathBuf::from(format!(r"\\?\UNC\{}", unc));
+                }
+                return PathBuf::from(absolute);
+            }
+            return PathBuf::from(format!(r"\\?\{}", absolute));
+        }
+    }
+    path.to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{component, MAX_NAME_LENGTH};
+
+    #[test]
+    fn safe_names() {
+        assert_eq!(component("mochitest-plain"), "mochitest-plain");
+        assert_eq!(component("web-platform-tests.json"), "web-platform-tests.json");
+    }
+
+    #[test]
+    fn unsafe_names() {
+        assert_eq!(component("a/b:c*d"), "a_b_c_d");
+        assert_eq!(component("tab\there"), "tab_here");
+        assert_eq!(component("suite."), "suite._");
+        assert_eq!(component("suite "), "suite _");
+        assert_eq!(component("con"), "_con");
+        assert_eq!(component("COM1.json"), "_COM1.json");
+        assert_eq!(component("console"), "console");
+    }
+
+    #[test]
+    fn long_names() {
+        let name = "é".repeat(80);
+        let rv = component(&name);
+        assert!(rv.len() <= MAX_NAME_LENGTH);
+        assert_ne!(rv, component(&"é".repeat(81)));
+        assert_eq!(rv, component(&name));
+    }
+}
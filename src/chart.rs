@@ -0,0 +1,170 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use plotters::coord::Shift;
+use plotters::prelude::*;
+
+use crate::report::{Report, Totals};
+use crate::{Error, Result};
+
+/// The charts that can be rendered
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum ChartKind {
+    /// Bar chart of the directories with the most unique coverage
+    Directories,
+    /// Histogram of the per-file suite 2 only percentages
+    Histogram,
+}
+
+impl FromStr for ChartKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<ChartKind, String> {
+        match s {
+            "directories" => Ok(ChartKind::Directories),
+            "histogram" => Ok(ChartKind::Histogram),
+            _ => Err(format!("Unknown chart {}", s))
+        }
+    }
+}
+
+const SUITE_1_COLOR: RGBColor = RGBColor(70, 110, 200);
+const SUITE_2_COLOR: RGBColor = RGBColor(60, 160, 80);
+
+fn chart_error<E: std::fmt::Display>(error: E) -> Error {
+    Error::String(format!("Failed to draw chart: {}", error))
+}
+
+fn draw_directories<DB: DrawingBackend>(root: &DrawingArea<DB, Shift>,
+                                        report: &Report,
+                                        limit: usize) -> Result<()> {
+    let metadata = &report.metadata;
+    let directories = report.top_directories(limit);
+    let max = directories
+        .iter()
+        .map(|(_, x)| x.suite_1_only.max(x.suite_2_only))
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let count = directories.len().max(1);
+    let label = |index: usize| directories
+        .get(index)
+        .map(|(dir, _)| if dir.is_empty() { "/" } else { *dir })
+        .unwrap_or("");
+
+    let mut chart = ChartBuilder::on(root)
+        .caption("Directories with the most unique coverage", ("sans-serif", 24))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(250)
+        .build_cartesian_2d(0f64..max as f64 * 1.05, -0.5f64..count as f64 - 0.5)
+        .map_err(chart_error)?;
+    chart
+        .configure_mesh()
+        .disable_y_mesh()
+        .y_labels(count)
+        .y_label_formatter(&|y| {
+            let index = y.round();
+            if index >= 0.0 && (y - index).abs() < 1e-6 {
+                label(index as usize).to_owned()
+            } else {
+                String::new()
+            }
+        })
+        .x_desc("lines")
+        .draw()
+        .map_err(chart_error)?;
+
+    // Draw from the top so that the directory with the most unique coverage
+    // is first
+    let bars = |value: fn(&Totals) -> i64, offset: f64| {
+        directories
+            .iter()
+            .enumerate()
+            .map(move |(i, (_, totals))| {
+                let y = (count - 1 - i) as f64;
+                (value(totals) as f64, y + offset)
+            })
+            .collect::<Vec<_>>()
+    };
+    for (values, color, name) in [
+        (bars(|x| x.suite_1_only, 0.0), SUITE_1_COLOR, format!("{} only", metadata.suite_1)),
+        (bars(|x| x.suite_2_only, -0.4), SUITE_2_COLOR, format!("{} only", metadata.suite_2)),
+    ] {
+        chart
+            .draw_series(values.into_iter().map(|(x, y)| {
+                Rectangle::new([(0.0, y), (x, y + 0.4)], color.filled())
+            }))
+            .map_err(chart_error)?
+            .label(name)
+            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled()));
+    }
+    chart
+        .configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .position(SeriesLabelPosition::LowerRight)
+        .draw()
+        .map_err(chart_error)?;
+    Ok(())
+}
+
+fn draw_histogram<DB: DrawingBackend>(root: &DrawingArea<DB, Shift>, report: &Report) -> Result<()> {
+    const BIN_WIDTH: f64 = 5.0;
+    let mut bins = [0usize; 20];
+    for file in report.files.iter().filter(|x| x.coverable > 0) {
+        let bin = ((file.suite_2_only_percent / BIN_WIDTH) as usize).min(bins.len() - 1);
+        bins[bin] += 1;
+    }
+    let max = bins.iter().cloned().max().unwrap_or(0).max(1);
+
+    let mut chart = ChartBuilder::on(root)
+        .caption(format!("Files by percentage of lines only covered by {}", report.metadata.suite_2),
+                 ("sans-serif", 24))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0f64..100f64, 0f64..max as f64 * 1.05)
+        .map_err(chart_error)?;
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_desc(format!("{} only %", report.metadata.suite_2))
+        .y_desc("files")
+        .draw()
+        .map_err(chart_error)?;
+    chart
+        .draw_series(bins.iter().enumerate().map(|(i, count)| {
+            let x = i as f64 * BIN_WIDTH;
+            Rectangle::new([(x, 0.0), (x + BIN_WIDTH, *count as f64)], SUITE_2_COLOR.filled())
+        }))
+        .map_err(chart_error)?;
+    Ok(())
+}
+
+fn draw<DB: DrawingBackend>(root: DrawingArea<DB, Shift>,
+                            report: &Report,
+                            kind: ChartKind,
+                            limit: usize) -> Result<()> {
+    root.fill(&WHITE).map_err(chart_error)?;
+    match kind {
+        ChartKind::Directories => draw_directories(&root, report, limit)?,
+        ChartKind::Histogram => draw_histogram(&root, report)?,
+    }
+    root.present().map_err(chart_error)?;
+    Ok(())
+}
+
+/// Render a chart of the report to a PNG or SVG file, depending on the
+/// extension of `path`
+pub(crate) fn write_chart(path: &Path,
+                          report: &Report,
+                          kind: ChartKind,
+                          size: (u32, u32),
+                          limit: usize) -> Result<()> {
+    match path.extension().and_then(|x| x.to_str()) {
+        Some("svg") => draw(SVGBackend::new(path, size).into_drawing_area(), report, kind, limit),
+        Some("png") => draw(BitMapBackend::new(path, size).into_drawing_area(), report, kind, limit),
+        _ => Err(Error::Usage(format!("Chart file {} must have a .png or .svg extension", path.display())))
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::time::Duration;
+
+use crate::report::{Report, Totals};
+
+/// A change in the unique coverage of one suite in a directory between two
+/// runs
+#[derive(Debug, Clone)]
+pub(crate) struct Movement {
+    /// Top-level directory, or "" for the totals
+    pub directory: String,
+    pub suite: String,
+    pub old_percent: f64,
+    pub new_percent: f64,
+    /// Whether the directory is only in the earlier run; its percentage in
+    /// the new run is then 0
+    pub removed: bool,
+}
+
+impl Movement {
+    pub(crate) fn change(&self) -> f64 {
+        self.new_percent - self.old_percent
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        if self.directory.is_empty() {
+            "total"
+        } else {
+            &self.directory
+        }
+    }
+}
+
+impl fmt::Display for Movement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} only {:.2}% -> {:.2}% ({:+.2})",
+               self.name(),
+               self.suite,
+               self.old_percent,
+               self.new_percent,
+               self.change())?;
+        if self.removed {
+            write!(f, " (removed)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare the unique coverage percentages of the totals and top-level
+/// directories in two runs, returning those that moved by more than
+/// `threshold` percentage points in either direction. Directories that are
+/// only in the previous run are always returned, since their coverage is
+/// gone whatever it was.
+pub(crate) fn movements(previous: &Report, current: &Report, threshold: f64) -> Vec<Movement> {
+    let previous_dirs = previous.top_level_totals();
+    let current_dirs = current.top_level_totals();
+    let metadata = &current.metadata;
+    let empty = Totals::default();
+
+    let mut rv = Vec::new();
+    for dir in current_dirs.keys().chain(previous_dirs.keys()).collect::<BTreeSet<_>>() {
+        let old = previous_dirs.get(dir).unwrap_or(&empty);
+        let (new, removed) = match current_dirs.get(dir) {
+            Some(totals) => (totals, false),
+            None => (&empty, true)
+        };
+        for (suite, old_percent, new_percent) in [
+            (&metadata.suite_1, old.suite_1_only_percent(), new.suite_1_only_percent()),
+            (&metadata.suite_2, old.suite_2_only_percent(), new.suite_2_only_percent()),
+        ] {
+            if removed || (new_percent - old_percent).abs() > threshold {
+                rv.push(Movement {
+                    directory: dir.clone(),
+                    suite: suite.clone(),
+                    old_percent,
+                    new_percent,
+                    removed,
+                });
+            }
+        }
+    }
+    rv
+}
+
+/// Result of checking for a new changeset. Reports are boxed, since they're
+/// much larger than a changeset.
+pub(crate) enum Outcome {
+    /// The latest changeset was already checked
+    Unchanged(String),
+    /// There was no previous run to compare with
+    Initial(Box<Report>),
+    /// The latest changeset was compared with the previous run
+    Compared {
+        previous: Box<Report>,
+        current: Box<Report>,
+        movements: Vec<Movement>,
+    },
+}
+
+/// Parse a duration like 90s, 30m, 6h or 1d; a plain number is in seconds
+pub(crate) fn parse_duration(value: &str) -> std::result::Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => value.split_at(idx),
+        None => (value, "s")
+    };
+    let number = number
+        .parse::<u64>()
+        .map_err(|e| format!("Invalid duration {}: {}", value, e))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("Invalid duration {}: expected a unit of s, m, h or d", value))
+    };
+    Ok(Duration::from_secs(number * seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{movements, parse_duration};
+    use crate::report::tests::{file, report};
+
+    #[test]
+    fn moved() {
+        let previous = report("abc", vec![file("dom/a.cpp", 10, 10, 100), file("js/b.cpp", 0, 10, 100)]);
+        let current = report("def", vec![file("dom/a.cpp", 10, 20, 100), file("js/b.cpp", 0, 11, 100)]);
+        let moved = movements(&previous, &current, 2.0)
+            .iter()
+            .map(|x| (x.name().to_owned(), x.suite.clone(), x.change()))
+            .collect::<Vec<_>>();
+        assert_eq!(moved, vec![("total".to_owned(), "mochitest-plain".to_owned(), 5.5),
+                               ("dom".to_owned(), "mochitest-plain".to_owned(), 10.0)]);
+        assert!(movements(&previous, &current, 10.0).is_empty());
+    }
+
+    #[test]
+    fn removed() {
+        let previous = report("abc", vec![file("dom/a.cpp", 10, 10, 100), file("js/b.cpp", 0, 0, 100)]);
+        let current = report("def", vec![file("dom/a.cpp", 10, 10, 100)]);
+        let moved = movements(&previous, &current, 50.0);
+        let moved = moved.iter().map(|x| (x.name(), x.removed, x.to_string())).collect::<Vec<_>>();
+        assert_eq!(moved, vec![("js", true, "js: web-platform-tests only 0.00% -> 0.00% (+0.00) (removed)".to_owned()),
+                               ("js", true, "js: mochitest-plain only 0.00% -> 0.00% (+0.00) (removed)".to_owned())]);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration(" 6h "), Ok(Duration::from_secs(6 * 60 * 60)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(24 * 60 * 60)));
+        assert!(parse_duration("6w").is_err());
+        assert!(parse_duration("h").is_err());
+    }
+}
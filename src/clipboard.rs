@@ -0,0 +1,71 @@
+use std::env;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use crate::{Error, Result};
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(data: &[u8]) -> String {
+    let mut rv = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                rv.push(BASE64[(value >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                rv.push('=');
+            }
+        }
+    }
+    rv
+}
+
+/// Commands that read text to put on the clipboard from stdin, in order of
+/// preference for the platform
+fn commands() -> Vec<Vec<&'static str>> {
+    if cfg!(target_os = "macos") {
+        vec![vec!["pbcopy"]]
+    } else if cfg!(windows) {
+        vec![vec!["clip"]]
+    } else {
+        let mut commands = Vec::new();
+        if env::var_os("WAYLAND_DISPLAY").is_some() {
+            commands.push(vec!["wl-copy"]);
+        }
+        commands.push(vec!["xclip", "-selection", "clipboard"]);
+        commands.push(vec!["xsel", "--clipboard", "--input"]);
+        commands
+    }
+}
+
+fn copy_with(command: &[&str], text: &str) -> Result<()> {
+    let mut child = Command::new(command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(Error::String(format!("{} exited with {}", command[0], status)));
+    }
+    Ok(())
+}
+
+/// Put `text` on the system clipboard. If none of the platform's clipboard
+/// commands work, e.g. over ssh, the terminal is asked to set the clipboard
+/// with an OSC 52 escape sequence instead.
+pub(crate) fn copy(text: &str) -> Result<()> {
+    if commands().iter().any(|command| copy_with(command, text).is_ok()) {
+        return Ok(());
+    }
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", base64(text.as_bytes()))?;
+    stdout.flush()?;
+    Ok(())
+}
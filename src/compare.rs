@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+use crate::output::{self, OutputOptions};
+use crate::report::{self, FileRecord, Report};
+use crate::{log, Result};
+
+/// Change in the unique coverage of a single file between two reports
+struct FileChange<'a> {
+    path: &'a str,
+    old: Option<&'a FileRecord>,
+    new: Option<&'a FileRecord>,
+}
+
+impl<'a> FileChange<'a> {
+    fn suite_1_only(record: Option<&FileRecord>) -> i64 {
+        record.map(|x| x.suite_1_only).unwrap_or(0)
+    }
+
+    fn suite_2_only(record: Option<&FileRecord>) -> i64 {
+        record.map(|x| x.suite_2_only).unwrap_or(0)
+    }
+
+    fn suite_1_delta(&self) -> i64 {
+        FileChange::suite_1_only(self.new) - FileChange::suite_1_only(self.old)
+    }
+
+    fn suite_2_delta(&self) -> i64 {
+        FileChange::suite_2_only(self.new) - FileChange::suite_2_only(self.old)
+    }
+
+    fn status(&self) -> &'static str {
+        match (self.old, self.new) {
+            (None, Some(_)) => "added",
+            (Some(_), None) => "removed",
+            _ => "changed"
+        }
+    }
+}
+
+/// Write the files whose unique coverage counts moved by more than
+/// `threshold` lines between two previously generated JSON reports.
+pub(crate) fn compare_reports<W: Write>(out: &mut W,
+                                        old_path: &Path,
+                                        new_path: &Path,
+                                        threshold: i64,
+                                        options: &OutputOptions) -> Result<()> {
+    let old = Report::load(old_path)?;
+    let new = Report::load(new_path)?;
+
+    let (old_meta, new_meta) = (&old.metadata, &new.metadata);
+    if old_meta.suite_1 != new_meta.suite_1 || old_meta.suite_2 != new_meta.suite_2 {
+        log::warning(format_args!("reports compare different suites ({} vs {} and {} vs {})",
+                                  old_meta.suite_1, old_meta.suite_2, new_meta.suite_1, new_meta.suite_2));
+    }
+    report::warn_filters_differ(&format!("Report {}", old_path.display()), &old_meta.filters, &new_meta.filters);
+
+    let mut records: BTreeMap<&str, (Option<&FileRecord>, Option<&FileRecord>)> = BTreeMap::new();
+    for record in old.files.iter() {
+        records.entry(&record.path).or_default().0 = Some(record);
+    }
+    for record in new.files.iter() {
+        records.entry(&record.path).or_default().1 = Some(record);
+    }
+
+    let mut writer = output::csv_writer(out, options);
+    if options.header {
+        writer.write_record(&[
+            "path".to_owned(),
+            "status".to_owned(),
+            format!("old {} only", new_meta.suite_1),
+            format!("new {} only", new_meta.suite_1),
+            format!("{} only change", new_meta.suite_1),
+            format!("old {} only", new_meta.suite_2),
+            format!("new {} only", new_meta.suite_2),
+            format!("{} only change", new_meta.suite_2),
+        ])?;
+    }
+
+    for (path, (old_record, new_record)) in records.into_iter() {
+        let change = FileChange {
+            path,
+            old: old_record,
+            new: new_record,
+        };
+        if change.suite_1_delta().abs() <= threshold && change.suite_2_delta().abs() <= threshold {
+            continue;
+        }
+        writer.write_record(&[
+            change.path.to_owned(),
+            change.status().to_owned(),
+            FileChange::suite_1_only(change.old).to_string(),
+            FileChange::suite_1_only(change.new).to_string(),
+            change.suite_1_delta().to_string(),
+            FileChange::suite_2_only(change.old).to_string(),
+            FileChange::suite_2_only(change.new).to_string(),
+            change.suite_2_delta().to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::compare_reports;
+    use crate::output::OutputOptions;
+    use crate::report::tests::{file, report, temp_path};
+
+    #[test]
+    fn changes() {
+        let old = report("abc", vec![file("a.cpp", 1, 10, 20),
+                                     file("b.cpp", 0, 5, 20),
+                                     file("c.cpp", 0, 3, 20)]);
+        let new = report("def", vec![file("a.cpp", 1, 4, 20),
+                                     file("b.cpp", 0, 6, 20),
+                                     file("d.cpp", 2, 0, 20)]);
+        let (old_path, new_path) = (temp_path("compare-old.json"), temp_path("compare-new.json"));
+        fs::write(&old_path, serde_json::to_vec(&old).unwrap()).unwrap();
+        fs::write(&new_path, serde_json::to_vec(&new).unwrap()).unwrap();
+        let mut out = Vec::new();
+        let rv = compare_reports(&mut out, &old_path, &new_path, 1, &OutputOptions::default());
+        fs::remove_file(&old_path).unwrap();
+        fs::remove_file(&new_path).unwrap();
+        rv.unwrap();
+        // b.cpp moved by less than the threshold
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "path,status,old web-platform-tests only,new web-platform-tests only,web-platform-tests only change,\
+                    old mochitest-plain only,new mochitest-plain only,mochitest-plain only change\n\
+                    a.cpp,changed,1,1,0,10,4,-6\n\
+                    c.cpp,removed,0,0,0,3,0,-3\n\
+                    d.cpp,added,0,2,2,0,0,0\n");
+    }
+}
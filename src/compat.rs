@@ -0,0 +1,241 @@
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use crate::log;
+
+/// Fields that other versions of the coverage API have used for ones that
+/// are read under a different name, after converting to camel case
+const RENAMED_FIELDS: &[(&str, &str)] = &[
+    ("kind", "type"),
+    ("pathType", "type"),
+    ("percent", "coveragePercent"),
+    ("pushId", "push"),
+];
+
+/// Unexpected fields that have already been logged, so each is only logged once
+static REPORTED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+fn camel_case(key: &str) -> String {
+    let mut rv = String::with_capacity(key.len());
+    let mut upper = false;
+    for c in key.chars() {
+        if c == '_' || c == '-' {
+            upper = !rv.is_empty();
+        } else if upper {
+            rv.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            rv.push(c);
+        }
+    }
+    rv
+}
+
+fn field_name(key: &str) -> String {
+    let key = camel_case(key);
+    RENAMED_FIELDS
+        .iter()
+        .find(|(old, _)| *old == key)
+        .map(|(_, new)| (*new).to_owned())
+        .unwrap_or(key)
+}
+
+/// Children given as an object keyed by name rather than as a list
+fn children_list(children: Map<String, Value>) -> Value {
+    Value::Array(children
+        .into_iter()
+        .map(|(name, child)| {
+            let mut child = normalize(child);
+            if let Value::Object(ref mut fields) = child {
+                fields.entry("name").or_insert(Value::String(name));
+            }
+            child
+        })
+        .collect())
+}
+
+/// Rewrite a response into the shape the current structs expect
+fn normalize(value: Value) -> Value {
+    match value {
+        Value::Object(fields) => {
+            let mut rv = Map::new();
+            for (key, value) in fields {
+                let key = field_name(&key);
+                let value = match (key.as_str(), value) {
+                    // Treat null as missing, so that defaults are used
+                    (_, Value::Null) => continue,
+                    ("children", Value::Object(children)) => children_list(children),
+                    // Lines that can't be covered as null instead of -1
+                    ("coverage", Value::Array(lines)) => {
+                        Value::Array(lines.into_iter().map(|x| if x.is_null() { Value::from(-1) } else { x }).collect())
+                    }
+                    (_, value) => normalize(value)
+                };
+                // If both an old and a new name are present, the first one wins
+                rv.entry(key).or_insert(value);
+            }
+            if rv.contains_key("path") && !rv.contains_key("type") {
+                let path_type = if rv.contains_key("children") { "directory" } else { "file" };
+                rv.insert("type".into(), Value::from(path_type));
+            }
+            Value::Object(rv)
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(normalize).collect()),
+        value => value
+    }
+}
+
+/// Fields that may appear alongside the payload in a v3 response envelope
+const ENVELOPE_FIELDS: &[&str] = &["data", "meta", "links", "version"];
+
+/// The payload of a response, without the envelope that v3 wraps it in
+fn unwrap_envelope(value: Value) -> Value {
+    match value {
+        Value::Object(mut fields)
+            if fields.contains_key("data") && fields.keys().all(|x| ENVELOPE_FIELDS.contains(&x.as_str())) => {
+            fields.remove("data").unwrap_or(Value::Null)
+        }
+        value => value
+    }
+}
+
+/// Parse a coverage API response, accepting the field names and shapes
+/// that other versions of the API have used
+pub(crate) fn from_str<T: DeserializeOwned>(body: &str) -> serde_json::Result<T> {
+    serde_json::from_value(normalize(unwrap_envelope(serde_json::from_str(body)?)))
+}
+
+/// Parse a coverage API response held as bytes, e.g. read from the cache.
+/// Most responses are already in the current shape, and reading those
+/// straight into `T` is much faster than building the whole document as a
+/// `Value` to normalize it, so that's tried first. `T` should refuse fields
+/// it doesn't know, and `is_current` checks that nothing needed a default
+/// from `normalize`. Otherwise the response is parsed as in `from_str`,
+/// after `remove_unexpected` takes out the fields `T` doesn't know.
+pub(crate) fn from_slice<T: DeserializeOwned>(body: &[u8],
+                                              is_current: impl Fn(&T) -> bool,
+                                              remove_unexpected: impl Fn(&mut Value)) -> serde_json::Result<T> {
+    if let Ok(rv) = serde_json::from_slice::<T>(body) {
+        if is_current(&rv) {
+            return Ok(rv);
+        }
+    }
+    let mut value = normalize(unwrap_envelope(serde_json::from_slice(body)?));
+    remove_unexpected(&mut value);
+    serde_json::from_value(value)
+}
+
+/// Remove the fields of an object in a `kind` of response that aren't in
+/// `fields`, and log each the first time it's seen, since they may mean
+/// the API has changed
+pub(crate) fn remove_unexpected(kind: &str, value: &mut Value, fields: &[&str]) {
+    let object = match value {
+        Value::Object(object) => object,
+        _ => return
+    };
+    let unexpected = object
+        .keys()
+        .filter(|x| !fields.contains(&x.as_str()))
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut reported = REPORTED.lock().unwrap();
+    for key in unexpected {
+        object.remove(&key);
+        if reported.insert(format!("{}.{}", kind, key)) {
+            log::info(format_args!("Ignoring unexpected field {} in {}", key, kind));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::{json, Value};
+
+    use super::{camel_case, from_slice, from_str, normalize, remove_unexpected, unwrap_envelope};
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    struct Entry {
+        name: String,
+        #[serde(rename = "type")]
+        path_type: String,
+        #[serde(default)]
+        children: Vec<Entry>,
+        #[serde(default)]
+        coverage: Vec<i64>,
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(camel_case("coverage_percent"), "coveragePercent");
+        assert_eq!(camel_case("path-type"), "pathType");
+        assert_eq!(camel_case("_private"), "private");
+        assert_eq!(camel_case("push"), "push");
+    }
+
+    #[test]
+    fn normalize_shapes() {
+        let value = normalize(json!({
+            "path": "dom",
+            "coverage_percent": 50,
+            "percent": 10,
+            "push_id": null,
+            "children": {"a.cpp": {"path": "dom/a.cpp", "coverage": [1, null, 0]}}
+        }));
+        assert_eq!(value, json!({
+            "path": "dom",
+            "type": "directory",
+            "coveragePercent": 50,
+            "children": [{"name": "a.cpp", "path": "dom/a.cpp", "type": "file", "coverage": [1, -1, 0]}]
+        }));
+    }
+
+    #[test]
+    fn envelope() {
+        assert_eq!(unwrap_envelope(json!({"data": [1], "meta": {}})), json!([1]));
+        assert_eq!(unwrap_envelope(json!({"data": [1], "path": "dom"})), json!({"data": [1], "path": "dom"}));
+    }
+
+    #[test]
+    fn parse() {
+        let body = r#"{"data": {"name": "dom", "kind": "directory", "children": {"a.cpp": {"type": "file"}}}}"#;
+        let entry: Entry = from_str(body).unwrap();
+        assert_eq!(entry, Entry {
+            name: "dom".into(),
+            path_type: "directory".into(),
+            children: vec![Entry {
+                name: "a.cpp".into(),
+                path_type: "file".into(),
+                children: vec![],
+                coverage: vec![],
+            }],
+            coverage: vec![],
+        });
+    }
+
+    #[test]
+    fn parse_slice() {
+        let fields = ["name", "type", "children", "coverage"];
+        let remove = |value: &mut Value| remove_unexpected("entry", value, &fields);
+        let current = br#"{"name": "a.cpp", "type": "file", "coverage": [1, -1]}"#;
+        let entry: Entry = from_slice(current, |_| true, remove).unwrap();
+        assert_eq!(entry.coverage, vec![1, -1]);
+
+        let old = br#"{"name": "a.cpp", "path_type": "file", "coverage": [1, null], "extra": 1}"#;
+        let entry: Entry = from_slice(old, |_| true, remove).unwrap();
+        assert_eq!(entry.path_type, "file");
+        assert_eq!(entry.coverage, vec![1, -1]);
+
+        // Parses strictly, but isn't accepted as current
+        let entry: Entry = from_slice(current, |x: &Entry| x.coverage.is_empty(), |value: &mut Value| {
+            if let Value::Object(fields) = value {
+                fields.remove("coverage");
+            }
+        }).unwrap();
+        assert!(entry.coverage.is_empty());
+    }
+}
@@ -0,0 +1,112 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use structopt::clap::{App, Shell};
+
+use crate::Result;
+
+/// Name of the installed binary, which the completions are registered for
+const BIN_NAME: &str = env!("CARGO_PKG_NAME");
+
+const BASH_DYNAMIC: &str = r#"
+_wptcoverage_dynamic() {
+    local cur prev
+    cur="${COMP_WORDS[COMP_CWORD]}"
+    prev="${COMP_WORDS[COMP_CWORD-1]}"
+    if [[ "${prev}" == "--changeset" ]]; then
+        COMPREPLY=( $(compgen -W "$(wptcoverage complete-values changesets 2>/dev/null)" -- "${cur}") )
+        return 0
+    fi
+    _wptcoverage "$@"
+    if [[ "${cur}" != -* ]]; then
+        COMPREPLY+=( $(compgen -W "$(wptcoverage complete-values suites 2>/dev/null)" -- "${cur}") )
+    fi
+}
+
+complete -F _wptcoverage_dynamic -o bashdefault -o default wptcoverage
+"#;
+
+const ZSH_DYNAMIC: &str = r#"
+_wptcoverage_changesets() {
+    local -a changesets
+    changesets=(${(f)"$(wptcoverage complete-values changesets 2>/dev/null)"})
+    _describe 'changeset' changesets
+}
+
+_wptcoverage_suites() {
+    local -a suites
+    suites=(${(f)"$(wptcoverage complete-values suites 2>/dev/null)"})
+    _describe 'suite' suites
+}
+"#;
+
+const FISH_DYNAMIC: &str = r#"
+complete -c wptcoverage -l changeset -x -a "(wptcoverage complete-values changesets 2>/dev/null)"
+complete -c wptcoverage -f -a "(wptcoverage complete-values suites 2>/dev/null)"
+"#;
+
+/// Write a completion script for `shell`. As well as the static options and
+/// subcommands, changesets and suite names are completed from the cache.
+pub(crate) fn write_completions<W: Write>(out: &mut W, mut app: App, shell: Shell) -> Result<()> {
+    let mut script = Vec::new();
+    app.gen_completions_to(BIN_NAME, shell, &mut script);
+    let script = String::from_utf8_lossy(&script);
+    match shell {
+        Shell::Bash => {
+            out.write_all(script.as_bytes())?;
+            out.write_all(BASH_DYNAMIC.as_bytes())?;
+        }
+        Shell::Zsh => {
+            // Hook the dynamic completions into the generated argument specs
+            out.write_all(ZSH_DYNAMIC.as_bytes())?;
+            for line in script.lines() {
+                let line = if line == "'--changeset=[]' \\" {
+                    "'--changeset=[]:changeset:_wptcoverage_changesets' \\".into()
+                } else if line.starts_with("'::suite_1") || line.starts_with("'::suite_2") {
+                    line.replace(":_files' \\", ":_wptcoverage_suites' \\")
+                } else {
+                    line.to_owned()
+                };
+                writeln!(out, "{}", line)?;
+            }
+        }
+        Shell::Fish => {
+            out.write_all(script.as_bytes())?;
+            out.write_all(FISH_DYNAMIC.as_bytes())?;
+        }
+        _ => out.write_all(script.as_bytes())?,
+    }
+    Ok(())
+}
+
+fn dir_names(path: &Path) -> Vec<String> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new()
+    };
+    let mut names = entries
+        .filter_map(|x| x.ok())
+        .filter(|x| x.file_type().map(|x| x.is_dir()).unwrap_or(false))
+        .filter_map(|x| x.file_name().into_string().ok())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+/// Changesets that have data in the cache
+pub(crate) fn cached_changesets(root: &Path) -> Vec<String> {
+    dir_names(root)
+}
+
+/// Names of the API suites that have data in the cache for any changeset
+pub(crate) fn cached_suites(root: &Path) -> Vec<String> {
+    let mut suites = cached_changesets(root)
+        .iter()
+        .flat_map(|changeset| dir_names(&root.join(changeset)))
+        .filter(|x| x != "source")
+        .collect::<Vec<_>>();
+    suites.sort();
+    suites.dedup();
+    suites
+}
@@ -0,0 +1,40 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::output::ColorThresholds;
+use crate::{Error, Result};
+
+/// Config file read from the current directory when --config isn't given
+const DEFAULT_CONFIG: &str = "wptcoverage.yml";
+
+/// Settings read from a YAML config file. Command line options take
+/// precedence over these.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Config {
+    /// Unique coverage percentages at which table output turns yellow and green
+    pub color_thresholds: Option<ColorThresholds>,
+}
+
+impl Config {
+    /// Load the config from `path`, or from wptcoverage.yml if that exists
+    pub(crate) fn load(path: Option<&Path>) -> Result<Config> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_CONFIG).exists() => Path::new(DEFAULT_CONFIG),
+            None => return Ok(Config::default())
+        };
+        let f = File::open(path)?;
+        let config: Config = serde_yaml::from_reader(BufReader::new(f))?;
+        if let Some(thresholds) = config.color_thresholds {
+            if thresholds.yellow > thresholds.green {
+                return Err(Error::Data(format!("Config {} has a yellow color threshold above the green one",
+                                               path.display())));
+            }
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,206 @@
+use std::fmt;
+use std::iter::{self, FromIterator};
+
+use serde::de::{Deserialize, Deserializer, SeqAccess, Visitor};
+
+use crate::cache;
+
+/// Hit counts for each line of a file, with -1 for lines that can't be
+/// covered. Most lines in most files can't be, so only the runs of other
+/// lines are stored, which keeps whole-tree comparisons to a fraction of the
+/// memory that a vector per file would need.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct LineCoverage {
+    len: usize,
+    /// The first line of each run of coverable lines, and the offset of its
+    /// first hit count in `hits`
+    runs: Vec<(usize, usize)>,
+    hits: Vec<i64>,
+}
+
+impl LineCoverage {
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn push(&mut self, hits: i64) {
+        if hits != -1 {
+            let continues_run = self.runs
+                .last()
+                .map(|(start, offset)| start + self.hits.len() - offset == self.len)
+                .unwrap_or(false);
+            if !continues_run {
+                self.runs.push((self.len, self.hits.len()));
+            }
+            self.hits.push(hits);
+        }
+        self.len += 1;
+    }
+
+    /// The hit count for a line, counting from 0
+    pub(crate) fn get(&self, line: usize) -> i64 {
+        let idx = match self.runs.binary_search_by_key(&line, |(start, _)| *start) {
+            Ok(idx) => idx,
+            Err(0) => return -1,
+            Err(idx) => idx - 1
+        };
+        let (start, offset) = self.runs[idx];
+        let end = self.runs.get(idx + 1).map(|(_, next)| *next).unwrap_or(self.hits.len());
+        if line < self.len && offset + line - start < end {
+            self.hits[offset + line - start]
+        } else {
+            -1
+        }
+    }
+
+    pub(crate) fn iter(&self) -> Iter<'_> {
+        Iter {
+            coverage: self,
+            line: 0,
+            run: 0,
+        }
+    }
+
+    pub(crate) fn to_vec(&self) -> Vec<i64> {
+        self.iter().collect()
+    }
+
+    /// A hash of the hit counts that's stable between runs, so that it can be
+    /// stored to tell whether a file's coverage has changed since
+    pub(crate) fn content_hash(&self) -> u64 {
+        let words = iter::once(self.len as u64)
+            .chain(self.runs.iter().flat_map(|(start, offset)| [*start as u64, *offset as u64]))
+            .chain(self.hits.iter().map(|x| *x as u64));
+        cache::fnv1a(words.flat_map(u64::to_le_bytes))
+    }
+
+    /// Coverage with the same coverable lines, none of which were hit
+    pub(crate) fn zeroed(&self) -> LineCoverage {
+        LineCoverage {
+            len: self.len,
+            runs: self.runs.clone(),
+            hits: vec![0; self.hits.len()],
+        }
+    }
+}
+
+/// Iterator over the hit count of every line, including those that can't be
+/// covered
+pub(crate) struct Iter<'a> {
+    coverage: &'a LineCoverage,
+    line: usize,
+    run: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = i64;
+
+    fn next(&mut self) -> Option<i64> {
+        let coverage = self.coverage;
+        if self.line >= coverage.len {
+            return None;
+        }
+        let line = self.line;
+        self.line += 1;
+        while let Some((start, offset)) = coverage.runs.get(self.run).cloned() {
+            if line < start {
+                return Some(-1);
+            }
+            let end = coverage.runs.get(self.run + 1).map(|(_, next)| *next).unwrap_or(coverage.hits.len());
+            if offset + line - start < end {
+                return Some(coverage.hits[offset + line - start]);
+            }
+            self.run += 1;
+        }
+        Some(-1)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.coverage.len - self.line;
+        (remaining, Some(remaining))
+    }
+}
+
+impl FromIterator<i64> for LineCoverage {
+    fn from_iter<I: IntoIterator<Item=i64>>(iter: I) -> LineCoverage {
+        let mut rv = LineCoverage::default();
+        for hits in iter {
+            rv.push(hits);
+        }
+        rv
+    }
+}
+
+impl From<Vec<i64>> for LineCoverage {
+    fn from(coverage: Vec<i64>) -> LineCoverage {
+        coverage.into_iter().collect()
+    }
+}
+
+struct LineCoverageVisitor;
+
+impl<'de> Visitor<'de> for LineCoverageVisitor {
+    type Value = LineCoverage;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of hit counts")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<LineCoverage, A::Error> {
+        let mut rv = LineCoverage::default();
+        while let Some(hits) = seq.next_element::<i64>()? {
+            rv.push(hits);
+        }
+        Ok(rv)
+    }
+}
+
+/// Read directly from a list of hit counts, without building the full vector
+impl<'de> Deserialize<'de> for LineCoverage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<LineCoverage, D::Error> {
+        deserializer.deserialize_seq(LineCoverageVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LineCoverage;
+
+    #[test]
+    fn round_trip() {
+        let lines = vec![-1, -1, 3, 0, -1, 5, -1, -1, 0, 1, 2, -1];
+        let coverage = LineCoverage::from(lines.clone());
+        assert_eq!(coverage.len(), lines.len());
+        assert_eq!(coverage.to_vec(), lines);
+        for (line, hits) in lines.iter().enumerate() {
+            assert_eq!(coverage.get(line), *hits);
+        }
+        assert_eq!(coverage.get(lines.len()), -1);
+        assert_eq!(coverage.runs, vec![(2, 0), (5, 2), (8, 3)]);
+    }
+
+    #[test]
+    fn uncoverable() {
+        let coverage = LineCoverage::from(vec![-1, -1, -1]);
+        assert_eq!(coverage.len(), 3);
+        assert!(coverage.runs.is_empty());
+        assert_eq!(coverage.to_vec(), vec![-1, -1, -1]);
+        assert_eq!(LineCoverage::default().to_vec(), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn deserialize() {
+        let coverage: LineCoverage = serde_json::from_str("[-1, 2, 0, -1, 4]").unwrap();
+        assert_eq!(coverage, LineCoverage::from(vec![-1, 2, 0, -1, 4]));
+        assert!(serde_json::from_str::<LineCoverage>("[1, \"a\"]").is_err());
+    }
+
+    #[test]
+    fn zeroed_and_hash() {
+        let coverage = LineCoverage::from(vec![-1, 2, 0, -1, 4]);
+        let zeroed = coverage.zeroed();
+        assert_eq!(zeroed.to_vec(), vec![-1, 0, 0, -1, 0]);
+        assert_ne!(coverage.content_hash(), zeroed.content_hash());
+        assert_eq!(coverage.content_hash(), LineCoverage::from(vec![-1, 2, 0, -1, 4]).content_hash());
+    }
+}
@@ -0,0 +1,134 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::local::MergedCoverage;
+use crate::{cache, get, normalize_path, parse, timings, CoverageMap, Error, PathFilter, Result};
+
+static COVERALLS_ROOT: &str = "https://coveralls.io";
+
+#[derive(Debug, Deserialize)]
+struct SourceFile {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SourceFilesPage {
+    total_pages: Option<u64>,
+    source_files: Value,
+}
+
+/// Get the body of `url` from the cache at `local_path`, fetching and
+/// caching it if it isn't there yet
+fn get_cached(client: &reqwest::Client, url: &str, local_path: &Path) -> Result<String> {
+    if !local_path.exists() {
+        let resp_str = get(client, url, None)?;
+        cache::write(local_path, resp_str.as_bytes())?;
+        Ok(resp_str)
+    } else {
+        Ok(timings::time(timings::Stage::CacheRead, || fs::read_to_string(local_path))?)
+    }
+}
+
+/// List the names of all the source files in a build. Builds don't change
+/// once they're finished, so the pages of the listing are cached in
+/// `build_root` like the files themselves.
+fn source_files(client: &reqwest::Client, build_root: &Path, build: &str) -> Result<Vec<String>> {
+    let listing_root = cache::long_path(&build_root.join("source_files"));
+    if !listing_root.exists() {
+        fs::create_dir_all(&listing_root)?;
+    }
+    let mut rv = Vec::new();
+    let mut page = 1;
+    loop {
+        let url = format!("{}/builds/{}/source_files.json?page={}", COVERALLS_ROOT, build, page);
+        let local_path = listing_root.join(format!("{}.json", page));
+        let body = get_cached(client, &url, &local_path)?;
+        let data: SourceFilesPage = parse::from_cache(&local_path, &body)?;
+        // Depending on the endpoint version the list is either inline or a JSON-encoded string
+        let files: Vec<SourceFile> = match data.source_files {
+            Value::String(ref encoded) => parse::from_cache(&local_path, encoded)?,
+            value => serde_json::from_value(value)?
+        };
+        rv.extend(files.into_iter().map(|x| x.name));
+        if page >= data.total_pages.unwrap_or(1) {
+            break;
+        }
+        page += 1;
+    }
+    Ok(rv)
+}
+
+/// Convert a Coveralls line array, where uncoverable lines are null,
+/// into a coverage vector.
+fn coverage_from_lines(lines: &[Option<i64>]) -> Vec<i64> {
+    lines.iter().map(|x| x.unwrap_or(-1)).collect()
+}
+
+/// Build suite data from the per-file line coverage of a Coveralls build
+pub(crate) fn get_suite_data(client: &reqwest::Client,
+                             root_path: &Path,
+                             build: &str,
+                             gecko_roots: &[&str],
+                             filter: &PathFilter) -> Result<CoverageMap> {
+    let build_root = cache::long_path(&root_path.join(cache::component(&format!("coveralls-{}", build))));
+    if !build_root.exists() {
+        fs::create_dir_all(&build_root)?;
+    }
+
+    let mut merged = MergedCoverage::new(gecko_roots);
+    let names = source_files(client, &build_root, build)?
+        .into_iter()
+        .filter(|x| merged.includes(x) && filter.includes(&normalize_path(x)))
+        .collect::<Vec<_>>();
+    if names.is_empty() {
+        return Err(Error::Data(format!("No source files found in Coveralls build {}", build)));
+    }
+
+    for name in names.into_iter() {
+        let local_path = cache::file_path(&build_root, &name, "json");
+
+        let url = reqwest::Url::parse_with_params(&format!("{}/builds/{}/source.json", COVERALLS_ROOT, build),
+                                                  &[("filename", &name)])
+            .map_err(|e| Error::Data(format!("Invalid Coveralls URL: {}", e)))?;
+        let body = get_cached(client, url.as_str(), &local_path)?;
+
+        let lines: Vec<Option<i64>> = parse::from_cache(&local_path, &body)?;
+        merged.add(vec![(name, coverage_from_lines(&lines))]);
+    }
+    Ok(merged.into_coverage_map())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::get_suite_data;
+    use crate::report::tests::temp_path;
+    use crate::{api, cache, PathFilter};
+
+    /// Read a build from a cache filled in advance, which must work without
+    /// the network
+    #[test]
+    fn cached_build() {
+        api::use_fixtures();
+        let root = temp_path("coveralls");
+        let build_root = root.join("coveralls-1234");
+        fs::create_dir_all(build_root.join("source_files")).unwrap();
+        fs::write(build_root.join("source_files").join("1.json"),
+                  r#"{"total_pages": 2, "source_files": "[{\"name\": \"dom/base/a.cpp\"}]"}"#).unwrap();
+        fs::write(build_root.join("source_files").join("2.json"),
+                  r#"{"total_pages": 2, "source_files": [{"name": "js/src/b.cpp"}, {"name": "dom/c.cpp"}]}"#).unwrap();
+        fs::write(cache::file_path(&build_root, "dom/base/a.cpp", "json"), "[null, 2, 0]").unwrap();
+        fs::write(cache::file_path(&build_root, "dom/c.cpp", "json"), "[1]").unwrap();
+
+        let data = get_suite_data(&reqwest::Client::new(), &root, "1234", &["dom"], &PathFilter::default());
+        fs::remove_dir_all(&root).unwrap();
+        let data = data.unwrap();
+        assert_eq!(data.keys().map(|x| &**x).collect::<Vec<_>>(), vec!["dom/base/a.cpp", "dom/c.cpp"]);
+        let coverage = data["dom/base/a.cpp"].coverage.as_ref().unwrap();
+        assert_eq!(coverage.to_vec(), vec![-1, 2, 0]);
+    }
+}
@@ -0,0 +1,41 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+use crate::{log, CoverageDifference, CoverageType, Error, Result};
+
+/// Line number of the first line that's only covered by suite 2
+pub(crate) fn first_suite_2_only_line(difference: &CoverageDifference) -> Option<usize> {
+    difference.line_differences
+        .iter()
+        .position(|x| *x == CoverageType::Suite2Only)
+        .map(|x| x + 1)
+}
+
+/// Open `path` from the checkout at `srcdir` in $VISUAL or $EDITOR, at `line`
+/// if there is one, and wait for the editor to exit. The editor setting may
+/// include arguments, e.g. "code --wait".
+pub(crate) fn open(srcdir: &Path, path: &str, line: Option<usize>) -> Result<()> {
+    let local_path = srcdir.join(path);
+    if !local_path.is_file() {
+        return Err(Error::Usage(format!("{} not found in {}", path, srcdir.display())));
+    }
+    let editor = env::var("VISUAL")
+        .ok()
+        .filter(|x| !x.trim().is_empty())
+        .or_else(|| env::var("EDITOR").ok().filter(|x| !x.trim().is_empty()))
+        .unwrap_or_else(|| "vi".into());
+    let mut words = editor.split_whitespace();
+    let mut command = Command::new(words.next().unwrap_or("vi"));
+    command.args(words);
+    if let Some(line) = line {
+        command.arg(format!("+{}", line));
+    }
+    command.arg(&local_path);
+    log::info(format_args!("Opening {} in {}", local_path.display(), editor));
+    let status = command.status()?;
+    if !status.success() {
+        return Err(Error::String(format!("{} exited with {}", editor, status)));
+    }
+    Ok(())
+}
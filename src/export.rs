@@ -0,0 +1,421 @@
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+use crate::coverage::LineCoverage;
+use crate::output::html::escape;
+use crate::output::{self, OutputOptions};
+use crate::report::{self, RunMetadata};
+use crate::{coverage_difference, warn_line_count_mismatch, zero_coverage, CoverageDifference, CoverageMap,
+            CoverageType, Result};
+
+/// Write the differences as an LCOV tracefile in which lines in `category`
+/// have a hit count of 1 and all other instrumented lines have a count of 0.
+/// Lines that weren't instrumented by either suite are omitted.
+pub(crate) fn write_lcov<W: Write>(out: &mut W,
+                                   differences: &BTreeMap<String, CoverageDifference>,
+                                   category: CoverageType) -> Result<()> {
+    for (path, difference) in differences.iter() {
+        writeln!(out, "TN:{}", category.name())?;
+        writeln!(out, "SF:{}", path)?;
+        let mut found = 0;
+        let mut hit = 0;
+        for (i, coverage_type) in difference.line_differences.iter().enumerate() {
+            if *coverage_type == CoverageType::NotRun {
+                continue;
+            }
+            let count = if *coverage_type == category { 1 } else { 0 };
+            writeln!(out, "DA:{},{}", i + 1, count)?;
+            found += 1;
+            hit += count;
+        }
+        writeln!(out, "LH:{}", hit)?;
+        writeln!(out, "LF:{}", found)?;
+        writeln!(out, "end_of_record")?;
+    }
+    Ok(())
+}
+
+/// Hit counts for the lines in a difference, counting lines in `category`
+/// as hit once and leaving lines that weren't instrumented as -1
+pub(crate) fn category_hits(difference: &CoverageDifference, category: CoverageType) -> Vec<i64> {
+    difference.line_differences
+        .iter()
+        .map(|x| match *x {
+            CoverageType::NotRun => -1,
+            x if x == category => 1,
+            _ => 0
+        })
+        .collect()
+}
+
+fn line_rate(covered: usize, valid: usize) -> f64 {
+    if valid > 0 {
+        covered as f64 / valid as f64
+    } else {
+        0f64
+    }
+}
+
+fn line_counts(hits: &[i64]) -> (usize, usize) {
+    let valid = hits.iter().filter(|x| **x >= 0).count();
+    let covered = hits.iter().filter(|x| **x > 0).count();
+    (covered, valid)
+}
+
+/// Write per-file line hit counts as a Cobertura XML report, with a package
+/// for each directory and a class for each file. Negative counts mark lines
+/// that aren't instrumented and are omitted.
+pub(crate) fn write_cobertura<W: Write>(out: &mut W,
+                                        metadata: &RunMetadata,
+                                        files: &BTreeMap<String, Vec<i64>>) -> Result<()> {
+    let mut packages: BTreeMap<&str, Vec<(&str, &[i64])>> = BTreeMap::new();
+    for (path, hits) in files.iter() {
+        packages.entry(report::parent_dir(path)).or_default().push((path, hits));
+    }
+    let (covered, valid) = files
+        .values()
+        .map(|x| line_counts(x))
+        .fold((0, 0), |acc, x| (acc.0 + x.0, acc.1 + x.1));
+
+    writeln!(out, "<?xml version=\"1.0\" ?>")?;
+    writeln!(out, "<!DOCTYPE coverage SYSTEM \"http://cobertura.sourceforge.net/xml/coverage-04.dtd\">")?;
+    writeln!(out, "<coverage line-rate=\"{:.4}\" branch-rate=\"0\" lines-covered=\"{}\" lines-valid=\"{}\" \
+                   branches-covered=\"0\" branches-valid=\"0\" complexity=\"0\" version=\"{}\" timestamp=\"{}\">",
+             line_rate(covered, valid),
+             covered,
+             valid,
+             escape(&metadata.changeset),
+             metadata.date.timestamp_millis())?;
+    writeln!(out, "<sources><source>.</source></sources>")?;
+    writeln!(out, "<packages>")?;
+    for (package, classes) in packages.iter() {
+        let (covered, valid) = classes
+            .iter()
+            .map(|(_, hits)| line_counts(hits))
+            .fold((0, 0), |acc, x| (acc.0 + x.0, acc.1 + x.1));
+        writeln!(out, "<package name=\"{}\" line-rate=\"{:.4}\" branch-rate=\"0\" complexity=\"0\">",
+                 escape(&package.replace('/', ".")),
+                 line_rate(covered, valid))?;
+        writeln!(out, "<classes>")?;
+        for (path, hits) in classes.iter() {
+            let (covered, valid) = line_counts(hits);
+            let name = path.rsplit('/').next().unwrap_or(path);
+            writeln!(out, "<class name=\"{}\" filename=\"{}\" line-rate=\"{:.4}\" branch-rate=\"0\" complexity=\"0\">",
+                     escape(name),
+                     escape(path),
+                     line_rate(covered, valid))?;
+            writeln!(out, "<methods/>")?;
+            writeln!(out, "<lines>")?;
+            for (i, count) in hits.iter().enumerate() {
+                if *count >= 0 {
+                    writeln!(out, "<line number=\"{}\" hits=\"{}\" branch=\"false\"/>", i + 1, count)?;
+                }
+            }
+            writeln!(out, "</lines>")?;
+            writeln!(out, "</class>")?;
+        }
+        writeln!(out, "</classes>")?;
+        writeln!(out, "</package>")?;
+    }
+    writeln!(out, "</packages>")?;
+    writeln!(out, "</coverage>")?;
+    Ok(())
+}
+
+/// Runs of consecutive lines in `category`, as 1-based inclusive ranges
+fn category_regions(difference: &CoverageDifference, category: CoverageType) -> Vec<(usize, usize)> {
+    let mut rv: Vec<(usize, usize)> = Vec::new();
+    for (i, coverage_type) in difference.line_differences.iter().enumerate() {
+        if *coverage_type != category {
+            continue;
+        }
+        match rv.last_mut() {
+            Some(last) if last.1 == i => last.1 = i + 1,
+            _ => rv.push((i + 1, i + 1))
+        }
+    }
+    rv
+}
+
+/// Write the numbers of the lines in each of `categories`, one per line, or
+/// with `ranges` one run of consecutive lines per line as START-END. Each
+/// entry is preceded by its category when there's more than one.
+pub(crate) fn write_lines<W: Write>(out: &mut W,
+                                    difference: &CoverageDifference,
+                                    categories: &[CoverageType],
+                                    ranges: bool) -> Result<()> {
+    for category in categories.iter() {
+        let prefix = if categories.len() > 1 {
+            format!("{} ", category.name())
+        } else {
+            String::new()
+        };
+        for (start, end) in category_regions(difference, *category) {
+            if ranges {
+                if start == end {
+                    writeln!(out, "{}{}", prefix, start)?;
+                } else {
+                    writeln!(out, "{}{}-{}", prefix, start, end)?;
+                }
+            } else {
+                for line in start..=end {
+                    writeln!(out, "{}{}", prefix, line)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The longest run of lines, preferring earlier runs when there's a tie
+fn largest_region(regions: &[(usize, usize)]) -> (usize, usize) {
+    regions
+        .iter()
+        .cloned()
+        .max_by_key(|(start, end)| (end - start, cmp::Reverse(*start)))
+        .unwrap_or((1, 1))
+}
+
+/// Write a SARIF log with a result for each file that has at least
+/// `min_lines` lines only covered by suite 2. The primary location of each
+/// result is the largest run of such lines, and every run is listed as a
+/// related location.
+pub(crate) fn write_sarif<W: Write>(out: &mut W,
+                                    metadata: &RunMetadata,
+                                    differences: &BTreeMap<String, CoverageDifference>,
+                                    min_lines: i64) -> Result<()> {
+    let region = |(start, end): (usize, usize)| serde_json::json!({
+        "startLine": start,
+        "endLine": end,
+    });
+
+    let mut results = Vec::new();
+    for (path, difference) in differences.iter() {
+        if difference.suite_2_only_count < min_lines || difference.suite_2_only_count == 0 {
+            continue;
+        }
+        let regions = category_regions(difference, CoverageType::Suite2Only);
+        let largest = largest_region(&regions);
+        let percent = report::percent(difference.suite_2_only_count, difference.coverable_count);
+        let related = regions
+            .iter()
+            .enumerate()
+            .map(|(i, x)| serde_json::json!({
+                "id": i,
+                "physicalLocation": {
+                    "artifactLocation": { "uri": path, "uriBaseId": "SRCROOT" },
+                    "region": region(*x),
+                },
+            }))
+            .collect::<Vec<_>>();
+        results.push(serde_json::json!({
+            "ruleId": "suite-2-only-coverage",
+            "level": "note",
+            "message": {
+                "text": format!("{} lines ({:.1}% of coverable lines) are only covered by {}",
+                                difference.suite_2_only_count,
+                                percent,
+                                metadata.suite_2),
+            },
+            "locations": [{
+                "physicalLocation": {
+                    "artifactLocation": { "uri": path, "uriBaseId": "SRCROOT" },
+                    "region": region(largest),
+                },
+            }],
+            "relatedLocations": related,
+            "properties": {
+                "suite2OnlyLines": difference.suite_2_only_count,
+                "coverableLines": difference.coverable_count,
+            },
+        }));
+    }
+
+    let log = serde_json::json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "wptcoverage",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": [{
+                        "id": "suite-2-only-coverage",
+                        "shortDescription": {
+                            "text": format!("Code only covered by {}", metadata.suite_2),
+                        },
+                        "fullDescription": {
+                            "text": format!("Lines that are covered by {} but not by {} at changeset {}",
+                                            metadata.suite_2,
+                                            metadata.suite_1,
+                                            metadata.changeset),
+                        },
+                    }],
+                },
+            },
+            "versionControlProvenance": [{
+                "repositoryUri": "https://hg.mozilla.org/mozilla-central",
+                "revisionId": metadata.changeset,
+            }],
+            "results": results,
+        }],
+    });
+    serde_json::to_writer_pretty(&mut *out, &log)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Escape a value for use in a GitHub Actions workflow command; property
+/// values additionally need `:` and `,` escaping
+fn gha_escape(value: &str, property: bool) -> String {
+    let rv = value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A");
+    if property {
+        rv.replace(':', "%3A").replace(',', "%2C")
+    } else {
+        rv
+    }
+}
+
+/// Write GitHub Actions warning annotations for the `limit` files with the
+/// most lines only covered by suite 2, each pointing at the largest run of
+/// such lines in the file.
+pub(crate) fn write_gha<W: Write>(out: &mut W,
+                                  metadata: &RunMetadata,
+                                  differences: &BTreeMap<String, CoverageDifference>,
+                                  limit: usize) -> Result<()> {
+    let mut files = differences
+        .iter()
+        .filter(|(_, x)| x.suite_2_only_count > 0)
+        .collect::<Vec<_>>();
+    files.sort_by(|a, b| b.1.suite_2_only_count.cmp(&a.1.suite_2_only_count).then_with(|| a.0.cmp(b.0)));
+    files.truncate(limit);
+
+    for (path, difference) in files {
+        let (start, end) = largest_region(&category_regions(difference, CoverageType::Suite2Only));
+        let title = format!("Only covered by {}", metadata.suite_2);
+        let message = format!("{} of {} coverable lines in this file are covered by {} but not by {}",
+                              difference.suite_2_only_count,
+                              difference.coverable_count,
+                              metadata.suite_2,
+                              metadata.suite_1);
+        writeln!(out, "::warning file={},line={},endLine={},title={}::{}",
+                 gha_escape(path, true),
+                 start,
+                 end,
+                 gha_escape(&title, true),
+                 gha_escape(&message, false))?;
+    }
+    Ok(())
+}
+
+pub(crate) fn describe_category(category: CoverageType, metadata: &RunMetadata) -> String {
+    match category {
+        CoverageType::NotRun => "not instrumented".into(),
+        CoverageType::NotCovered => "not covered by either suite".into(),
+        CoverageType::Suite1Only => format!("only covered by {}", metadata.suite_1),
+        CoverageType::Suite2Only => format!("only covered by {}", metadata.suite_2),
+        CoverageType::Both => "covered by both suites".into(),
+    }
+}
+
+/// Write a `path:line: message` entry for each run of lines in `category`,
+/// in the format understood by the Vim and Emacs quickfix/compilation modes
+pub(crate) fn write_quickfix<W: Write>(out: &mut W,
+                                       metadata: &RunMetadata,
+                                       differences: &BTreeMap<String, CoverageDifference>,
+                                       category: CoverageType) -> Result<()> {
+    let description = describe_category(category, metadata);
+    for (path, difference) in differences.iter() {
+        for (start, end) in category_regions(difference, category) {
+            if start == end {
+                writeln!(out, "{}:{}: line {}", path, start, description)?;
+            } else {
+                writeln!(out, "{}:{}: lines {}-{} {}", path, start, start, end, description)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Hit counts for a single line in both suites
+pub(crate) struct LineRow<'a> {
+    pub path: &'a str,
+    pub line: usize,
+    pub suite_1_hits: i64,
+    pub suite_2_hits: i64,
+    pub category: CoverageType,
+}
+
+fn file_coverage(data: &CoverageMap, path: &str) -> Option<LineCoverage> {
+    data.get(path)
+        .filter(|x| &*x.path_type != "directory")
+        .and_then(|x| x.coverage.clone())
+}
+
+/// Get a row for each line of the files in either suite that are under one
+/// of `paths`. Files that only appear in one suite get zero hits for every
+/// instrumented line in the other.
+pub(crate) fn line_matrix<'a>(suite_1_data: &'a CoverageMap,
+                              suite_2_data: &'a CoverageMap,
+                              paths: &[&str]) -> Vec<LineRow<'a>> {
+    let selected = |path: &str| {
+        paths.iter().any(|prefix| {
+            path == *prefix || prefix.is_empty() || path.starts_with(&format!("{}/", prefix.trim_end_matches('/')))
+        })
+    };
+    let files = suite_1_data
+        .keys()
+        .chain(suite_2_data.keys())
+        .filter(|x| selected(x))
+        .collect::<BTreeSet<_>>();
+
+    let mut rv = Vec::new();
+    for path in files {
+        let (suite_1, suite_2) = match (file_coverage(suite_1_data, path), file_coverage(suite_2_data, path)) {
+            (Some(suite_1), Some(suite_2)) => (suite_1, suite_2),
+            (Some(suite_1), None) => {
+                let suite_2 = zero_coverage(&suite_1);
+                (suite_1, suite_2)
+            }
+            (None, Some(suite_2)) => (zero_coverage(&suite_2), suite_2),
+            (None, None) => continue
+        };
+        let difference = coverage_difference(&suite_1, &suite_2, true);
+        warn_line_count_mismatch(path, &difference);
+        for (i, category) in difference.line_differences.iter().enumerate() {
+            rv.push(LineRow {
+                path,
+                line: i + 1,
+                suite_1_hits: suite_1.get(i),
+                suite_2_hits: suite_2.get(i),
+                category: *category,
+            });
+        }
+    }
+    rv
+}
+
+/// Write line rows as long-format CSV
+pub(crate) fn write_line_matrix_csv<W: Write>(out: &mut W,
+                                              rows: &[LineRow],
+                                              options: &OutputOptions) -> Result<()> {
+    let mut writer = output::csv_writer(out, options);
+    if options.header {
+        writer.write_record(["path", "line", "suite1_hits", "suite2_hits", "category"])?;
+    }
+    for row in rows {
+        writer.write_record(&[
+            row.path.to_owned(),
+            row.line.to_string(),
+            row.suite_1_hits.to_string(),
+            row.suite_2_hits.to_string(),
+            row.category.name().to_owned(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
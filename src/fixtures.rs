@@ -0,0 +1,264 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::api::{self, Endpoint};
+use crate::report::parent_dir;
+use crate::{cache, source, DiffArgs, Result};
+
+/// The only changeset in the fixture dataset
+pub(crate) const CHANGESET: &str = "c0ffeec0ffeec0ffeec0ffeec0ffeec0ffeec0ff";
+
+/// The suites in the fixture dataset, which are compared by default
+pub(crate) const SUITE_1: &str = "web-platform-tests";
+pub(crate) const SUITE_2: &str = "mochitest-plain";
+
+/// A file in the fixture dataset, with the hit counts of each line in each
+/// suite. Between them the files have lines in every coverage category.
+struct FixtureFile {
+    path: &'static str,
+    suite_1: &'static [i64],
+    suite_2: &'static [i64],
+}
+
+impl FixtureFile {
+    /// Coverage in a suite; the all suites root uses suite 1's
+    fn coverage(&self, suite: Option<&str>) -> &'static [i64] {
+        if suite == Some(SUITE_2) {
+            self.suite_2
+        } else {
+            self.suite_1
+        }
+    }
+}
+
+const FILES: &[FixtureFile] = &[
+    FixtureFile {
+        path: "dom/base/Element.cpp",
+        suite_1: &[-1, -1, 12, 12, 0, 3, -1, 1, 0, -1],
+        suite_2: &[-1, -1, 4, 0, 0, 2, -1, 0, 5, -1],
+    },
+    FixtureFile {
+        path: "dom/base/Node.cpp",
+        suite_1: &[-1, 7, 7, -1, 0, 0, 1, -1],
+        suite_2: &[-1, 9, 0, -1, 0, 6, 1, -1],
+    },
+    FixtureFile {
+        path: "dom/media/HTMLMediaElement.cpp",
+        suite_1: &[-1, 0, 0, 0, -1, 0, 0, -1],
+        suite_2: &[-1, 3, 3, 1, -1, 0, 2, -1],
+    },
+    FixtureFile {
+        path: "layout/base/PresShell.cpp",
+        suite_1: &[-1, 20, 20, 2, 0, -1],
+        suite_2: &[-1, 0, 0, 0, 0, -1],
+    },
+];
+
+/// Compare the suites in the dataset, starting from all its top-level
+/// directories unless base paths were given
+pub(crate) fn default_args(args: &DiffArgs) -> DiffArgs {
+    let mut args = args.clone();
+    args.suite_1 = Some(SUITE_1.into());
+    args.suite_2 = Some(SUITE_2.into());
+    if args.base_paths.is_none() && args.base_paths_file.is_none() {
+        args.base_paths = Some("auto".into());
+    }
+    args
+}
+
+fn write_json(path: &Path, value: &Value) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut f = File::create(path)?;
+    f.write_all(serde_json::to_string_pretty(value)?.as_bytes())?;
+    Ok(())
+}
+
+/// Line counts for an entry, as (covered, missed)
+fn line_counts<'a, I: IntoIterator<Item = &'a i64>>(coverage: I) -> (i64, i64) {
+    coverage.into_iter().fold((0, 0), |(covered, missed), hits| match *hits {
+        hits if hits > 0 => (covered + 1, missed),
+        0 => (covered, missed + 1),
+        _ => (covered, missed)
+    })
+}
+
+fn name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// An entry in the shape the API uses, without children or coverage
+fn entry(path: &str, path_type: &str, (covered, missed): (i64, i64)) -> Value {
+    let total = covered + missed;
+    json!({
+        "changeset": CHANGESET,
+        "coveragePercent": if total > 0 { 100f64 * covered as f64 / total as f64 } else { 0f64 },
+        "linesCovered": covered,
+        "linesMissed": missed,
+        "linesTotal": total,
+        "name": name(path),
+        "path": path,
+        "type": path_type,
+    })
+}
+
+/// Directories in a suite, including the root "", keyed by path, with the
+/// paths of their children and the coverage of every file under them
+type Directories = BTreeMap<String, (BTreeSet<String>, Vec<i64>)>;
+
+fn directories(suite: Option<&str>) -> Directories {
+    let mut rv = Directories::new();
+    for file in FILES {
+        let mut child = file.path;
+        loop {
+            let dir = parent_dir(child);
+            let entry = rv.entry(dir.to_owned()).or_default();
+            entry.0.insert(child.to_owned());
+            entry.1.extend(file.coverage(suite));
+            if dir.is_empty() {
+                break;
+            }
+            child = dir;
+        }
+    }
+    rv
+}
+
+fn directory_entry(path: &str,
+                   children: &BTreeSet<String>,
+                   coverage: &[i64],
+                   directories: &Directories,
+                   suite: Option<&str>) -> Value {
+    let mut rv = entry(path, "directory", line_counts(coverage));
+    rv["children"] = children
+        .iter()
+        .map(|child| {
+            let mut rv = match directories.get(child) {
+                Some((grandchildren, coverage)) => {
+                    let mut rv = entry(child, "directory", line_counts(coverage));
+                    rv["children"] = Value::from(grandchildren.len());
+                    rv
+                }
+                None => {
+                    let file = FILES.iter().find(|x| x.path == child).expect("Child is a fixture file");
+                    entry(child, "file", line_counts(file.coverage(suite)))
+                }
+            };
+            // Children don't say which changeset they're from
+            if let Value::Object(ref mut fields) = rv {
+                fields.remove("changeset");
+            }
+            rv
+        })
+        .collect();
+    rv
+}
+
+/// Write the fixture dataset into the cache directory, in the same layout as
+/// cached API responses so that the traversal reads them as they are
+pub(crate) fn generate() -> Result<()> {
+    let date = "2026-01-05T12:00:00Z";
+    write_json(&api::fixture_path(&Endpoint::Latest)?,
+               &json!([{"changeset": CHANGESET, "push": 1, "date": date}]))?;
+    write_json(&api::fixture_path(&Endpoint::Filters)?, &json!({"suites": [SUITE_1, SUITE_2]}))?;
+
+    for suite in [None, Some(SUITE_1), Some(SUITE_2)].iter().cloned() {
+        let directories = directories(suite);
+        // Only the root is queried for all suites, to find the top-level directories
+        for (path, (children, coverage)) in directories.iter().filter(|(path, _)| suite.is_some() || path.is_empty()) {
+            let endpoint = Endpoint::Path { path, suite, changeset: Some(CHANGESET) };
+            write_json(&api::fixture_path(&endpoint)?, &directory_entry(path, children, coverage, &directories, suite))?;
+        }
+        if suite.is_none() {
+            continue;
+        }
+        for file in FILES {
+            let endpoint = Endpoint::Path { path: file.path, suite, changeset: Some(CHANGESET) };
+            let mut data = entry(file.path, "file", line_counts(file.coverage(suite)));
+            data["coverage"] = Value::from(file.coverage(suite));
+            write_json(&api::fixture_path(&endpoint)?, &data)?;
+        }
+    }
+
+    for file in FILES {
+        let source = file.suite_1
+            .iter()
+            .enumerate()
+            .map(|(i, hits)| if *hits == -1 {
+                format!("// {} line {}\n", name(file.path), i + 1)
+            } else {
+                format!("  step({});\n", i + 1)
+            })
+            .collect::<String>();
+        let path = source::source_path(&cache::changeset_root(CHANGESET), file.path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        File::create(&path)?.write_all(source.as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::fs;
+    use std::sync::Once;
+
+    use structopt::StructOpt;
+
+    use super::{generate, CHANGESET, SUITE_1, SUITE_2};
+    use crate::api::{self, Endpoint};
+    use crate::report::tests::temp_path;
+    use crate::report::Report;
+    use crate::{cache, get_differences, load_comparison, DiffArgs, Error};
+
+    /// Use a fixture dataset in a temporary directory for everything that's
+    /// fetched, as --fixtures does
+    pub(crate) fn init() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            let root = temp_path("fixtures");
+            if root.exists() {
+                fs::remove_dir_all(&root).unwrap();
+            }
+            cache::set_root(&root);
+            api::use_fixtures();
+            generate().unwrap();
+        });
+    }
+
+    /// Comparison arguments, as given on the command line
+    pub(crate) fn args(args: &[&str]) -> DiffArgs {
+        DiffArgs::from_iter_safe(std::iter::once("wptcoverage").chain(args.iter().cloned())).unwrap()
+    }
+
+    #[test]
+    fn compare() {
+        init();
+        let comparison = load_comparison(&reqwest::Client::new(), &args(&[])).unwrap();
+        assert_eq!(comparison.metadata.changeset, CHANGESET);
+        assert_eq!((comparison.metadata.suite_1.as_str(), comparison.metadata.suite_2.as_str()), (SUITE_1, SUITE_2));
+        let differences = get_differences(comparison.suite_1_data, comparison.suite_2_data);
+        let report = Report::new(comparison.metadata, &differences, None);
+        let files = report.files
+            .iter()
+            .map(|x| (x.path.as_str(), x.suite_1_only, x.suite_2_only, x.both, x.coverable))
+            .collect::<Vec<_>>();
+        assert_eq!(files, vec![("dom/base/Element.cpp", 2, 1, 2, 6),
+                               ("dom/base/Node.cpp", 1, 1, 2, 5),
+                               ("dom/media/HTMLMediaElement.cpp", 0, 4, 0, 5),
+                               ("layout/base/PresShell.cpp", 3, 0, 0, 4)]);
+    }
+
+    #[test]
+    fn missing_fixture() {
+        init();
+        let endpoint = Endpoint::Path { path: "js", suite: Some(SUITE_1), changeset: Some(CHANGESET) };
+        assert!(matches!(api::get(&reqwest::Client::new(), &endpoint), Err(Error::Data(_))));
+    }
+}
@@ -0,0 +1,151 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::report::Totals;
+use crate::{log, Error, Result};
+
+#[derive(Debug, Clone, Copy)]
+enum Comparison {
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    fn symbol(self) -> &'static str {
+        match self {
+            Comparison::Greater => ">",
+            Comparison::GreaterEqual => ">=",
+            Comparison::Less => "<",
+            Comparison::LessEqual => "<=",
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+        }
+    }
+
+    fn holds(self, lhs: f64, rhs: f64) -> bool {
+        match self {
+            Comparison::Greater => lhs > rhs,
+            Comparison::GreaterEqual => lhs >= rhs,
+            Comparison::Less => lhs < rhs,
+            Comparison::LessEqual => lhs <= rhs,
+            Comparison::Equal => lhs == rhs,
+            Comparison::NotEqual => lhs != rhs,
+        }
+    }
+}
+
+/// A condition on the report totals, like `total.suite_2_only_percent > 10`,
+/// that fails the run when it holds
+#[derive(Debug, Clone)]
+pub(crate) struct FailIf {
+    metric: String,
+    comparison: Comparison,
+    value: f64,
+}
+
+impl fmt::Display for FailIf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "total.{} {} {}", self.metric, self.comparison.symbol(), self.value)
+    }
+}
+
+impl FromStr for FailIf {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<FailIf, String> {
+        // Check the two character operators first so that >= isn't read as >
+        let operators = [
+            Comparison::GreaterEqual,
+            Comparison::LessEqual,
+            Comparison::Equal,
+            Comparison::NotEqual,
+            Comparison::Greater,
+            Comparison::Less,
+        ];
+        let (idx, comparison) = operators
+            .iter()
+            .filter_map(|x| s.find(x.symbol()).map(|idx| (idx, *x)))
+            .min_by_key(|(idx, _)| *idx)
+            .ok_or_else(|| format!("No comparison operator in condition {}", s))?;
+        let lhs = s[..idx].trim();
+        let rhs = s[idx + comparison.symbol().len()..].trim();
+
+        let metric = lhs
+            .strip_prefix("total.")
+            .ok_or_else(|| format!("Condition {} must start with total.", s))?;
+        if !Totals::METRICS.contains(&metric) {
+            return Err(format!("Unknown metric {} in condition {}; expected one of {}",
+                               metric, s, Totals::METRICS.join(", ")));
+        }
+        let value = rhs
+            .parse::<f64>()
+            .map_err(|e| format!("Invalid value {} in condition {}: {}", rhs, s, e))?;
+        Ok(FailIf {
+            metric: metric.to_owned(),
+            comparison,
+            value,
+        })
+    }
+}
+
+/// Check each condition against the totals, logging those that hold and
+/// failing if there are any
+pub(crate) fn check(conditions: &[FailIf], totals: &Totals) -> Result<()> {
+    let mut failed = 0;
+    for condition in conditions {
+        // Metrics are validated when parsing
+        let actual = totals.metric(&condition.metric).unwrap_or(0f64);
+        if condition.comparison.holds(actual, condition.value) {
+            log::verdict("FAIL", format_args!("{} (total.{} is {})", condition, condition.metric, actual));
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Err(Error::Threshold(format!("{} of {} --fail-if conditions held", failed, conditions.len())));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, Comparison, FailIf};
+    use crate::report::tests::{file, report};
+    use crate::Error;
+
+    #[test]
+    fn parse() {
+        let condition = "total.suite_2_only_percent >= 10".parse::<FailIf>().unwrap();
+        assert_eq!(condition.metric, "suite_2_only_percent");
+        assert!(matches!(condition.comparison, Comparison::GreaterEqual));
+        assert_eq!(condition.value, 10f64);
+        assert_eq!(condition.to_string(), "total.suite_2_only_percent >= 10");
+
+        let condition = "total.suite_2_only_percent<2.5".parse::<FailIf>().unwrap();
+        assert!(matches!(condition.comparison, Comparison::Less));
+        assert_eq!(condition.value, 2.5);
+        let condition = "total.suite_2_only_percent != 0".parse::<FailIf>().unwrap();
+        assert!(matches!(condition.comparison, Comparison::NotEqual));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!("total.suite_2_only_percent 10".parse::<FailIf>().is_err());
+        assert!("suite_2_only_percent > 10".parse::<FailIf>().is_err());
+        assert!("total.unknown > 10".parse::<FailIf>().is_err());
+        assert!("total.suite_2_only_percent > ten".parse::<FailIf>().is_err());
+    }
+
+    #[test]
+    fn conditions() {
+        let totals = report("abc", vec![file("a.cpp", 1, 3, 10), file("b.cpp", 0, 2, 10)]).totals();
+        let parse = |x: &str| x.parse::<FailIf>().unwrap();
+        assert!(check(&[parse("total.suite_2_only > 5"), parse("total.files != 2")], &totals).is_ok());
+        assert!(matches!(check(&[parse("total.suite_2_only >= 5"), parse("total.suite_2_only_percent > 30")],
+                               &totals),
+                         Err(Error::Threshold(_))));
+    }
+}
@@ -0,0 +1,84 @@
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, USER_AGENT};
+use serde::{Deserialize, Serialize};
+
+use crate::{log, parse, Error, Result};
+
+/// Hidden marker used to find the comment from an earlier run, so it can be
+/// updated rather than adding another comment
+const COMMENT_MARKER: &str = "<!-- wptcoverage -->";
+
+/// Comments requested per page when searching for an existing comment
+const PAGE_SIZE: usize = 100;
+
+#[derive(Debug, Deserialize)]
+struct Comment {
+    id: u64,
+    body: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct CommentBody<'a> {
+    body: &'a str,
+}
+
+fn headers(token: &str) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    let auth = HeaderValue::from_str(&format!("token {}", token))
+        .map_err(|_| Error::Usage("GitHub token contains invalid characters".into()))?;
+    headers.insert(AUTHORIZATION, auth);
+    headers.insert(ACCEPT, HeaderValue::from_static("application/vnd.github+json"));
+    headers.insert(USER_AGENT, HeaderValue::from_static("wptcoverage"));
+    Ok(headers)
+}
+
+fn find_comment(client: &reqwest::Client,
+                api_url: &str,
+                repo: &str,
+                pr: u64,
+                headers: &HeaderMap) -> Result<Option<u64>> {
+    let mut page = 1;
+    loop {
+        let url = format!("{}/repos/{}/issues/{}/comments?per_page={}&page={}",
+                          api_url, repo, pr, PAGE_SIZE, page);
+        let resp_str = crate::get(client, &url, Some(headers.clone()))?;
+        let comments: Vec<Comment> = parse::from_str(&url, &resp_str)?;
+        if let Some(comment) = comments
+            .iter()
+            .find(|x| x.body.as_ref().map(|body| body.contains(COMMENT_MARKER)).unwrap_or(false)) {
+            return Ok(Some(comment.id));
+        }
+        if comments.len() < PAGE_SIZE {
+            return Ok(None);
+        }
+        page += 1;
+    }
+}
+
+/// Post `summary` as a comment on a pull request or issue, replacing the
+/// comment from an earlier run if there is one
+pub(crate) fn publish_comment(client: &reqwest::Client,
+                              api_url: &str,
+                              repo: &str,
+                              pr: u64,
+                              token: &str,
+                              summary: &str) -> Result<()> {
+    let api_url = api_url.trim_end_matches('/');
+    let headers = headers(token)?;
+    let body = format!("{}\n{}", COMMENT_MARKER, summary);
+    let body = CommentBody { body: &body };
+
+    let req = match find_comment(client, api_url, repo, pr, &headers)? {
+        Some(id) => {
+            let url = format!("{}/repos/{}/issues/comments/{}", api_url, repo, id);
+            log::debug(format_args!("PATCH {}", url));
+            client.patch(&url)
+        }
+        None => {
+            let url = format!("{}/repos/{}/issues/{}/comments", api_url, repo, pr);
+            log::debug(format_args!("POST {}", url));
+            client.post(&url)
+        }
+    };
+    req.headers(headers).json(&body).send()?.error_for_status()?;
+    Ok(())
+}
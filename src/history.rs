@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::output::{self, OutputOptions};
+use crate::report::{RunMetadata, Totals};
+use crate::Result;
+
+/// Metrics for the totals or a top-level directory at one changeset
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct HistoryRow {
+    pub changeset: String,
+    pub push: Option<i64>,
+    pub date: Option<DateTime<Utc>>,
+    pub suite_1: String,
+    pub suite_2: String,
+    /// Top-level directory, or "" for the totals
+    pub directory: String,
+    pub files: usize,
+    pub suite_1_only: i64,
+    pub suite_2_only: i64,
+    pub both: i64,
+    pub covered: i64,
+    pub coverable: i64,
+    pub lines: i64,
+    pub suite_1_only_percent: f64,
+    pub suite_2_only_percent: f64,
+    pub covered_percent: f64,
+}
+
+impl HistoryRow {
+    pub(crate) fn new(metadata: &RunMetadata,
+                      push: Option<i64>,
+                      date: Option<DateTime<Utc>>,
+                      directory: String,
+                      totals: &Totals) -> HistoryRow {
+        HistoryRow {
+            changeset: metadata.changeset.clone(),
+            push,
+            date,
+            suite_1: metadata.suite_1.clone(),
+            suite_2: metadata.suite_2.clone(),
+            directory,
+            files: totals.files,
+            suite_1_only: totals.suite_1_only,
+            suite_2_only: totals.suite_2_only,
+            both: totals.both,
+            covered: totals.covered,
+            coverable: totals.coverable,
+            lines: totals.lines,
+            suite_1_only_percent: totals.suite_1_only_percent(),
+            suite_2_only_percent: totals.suite_2_only_percent(),
+            covered_percent: totals.covered_percent(),
+        }
+    }
+}
+
+/// Write the history as CSV with one row per changeset and directory. The
+/// columns are named after the suites of the first row, as in the CSV
+/// output of a single comparison.
+pub(crate) fn write_history_csv<W: Write>(out: &mut W,
+                                          rows: &[HistoryRow],
+                                          options: &OutputOptions) -> Result<()> {
+    let mut writer = output::csv_writer(out, options);
+    if options.header {
+        let (suite_1, suite_2) = rows
+            .first()
+            .map(|x| (x.suite_1.as_str(), x.suite_2.as_str()))
+            .unwrap_or(("suite 1", "suite 2"));
+        writer.write_record(&[
+            "changeset".to_owned(),
+            "push".to_owned(),
+            "date".to_owned(),
+            "directory".to_owned(),
+            "files".to_owned(),
+            format!("{} only", suite_1),
+            format!("{} only", suite_2),
+            "both".to_owned(),
+            "total covered".to_owned(),
+            "total coverable".to_owned(),
+            "total lines".to_owned(),
+            format!("{}-only percent", suite_1),
+            format!("{}-only percent", suite_2),
+            "coverage percent".to_owned(),
+        ])?;
+    }
+    for row in rows.iter() {
+        writer.write_record(&[
+            row.changeset.clone(),
+            row.push.map(|x| x.to_string()).unwrap_or_default(),
+            row.date.map(|x| x.to_rfc3339()).unwrap_or_default(),
+            row.directory.clone(),
+            row.files.to_string(),
+            row.suite_1_only.to_string(),
+            row.suite_2_only.to_string(),
+            row.both.to_string(),
+            row.covered.to_string(),
+            row.coverable.to_string(),
+            row.lines.to_string(),
+            row.suite_1_only_percent.to_string(),
+            row.suite_2_only_percent.to_string(),
+            row.covered_percent.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write the history as a JSON array of rows
+pub(crate) fn write_history_json<W: Write>(out: &mut W, rows: &[HistoryRow]) -> Result<()> {
+    serde_json::to_writer_pretty(&mut *out, rows)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// A sharp drop in the unique coverage of suite 2 for a directory between
+/// two consecutive changesets
+#[derive(Debug)]
+pub(crate) struct CoverageDrop<'a> {
+    pub before: &'a HistoryRow,
+    pub after: &'a HistoryRow,
+}
+
+impl<'a> CoverageDrop<'a> {
+    pub(crate) fn change(&self) -> f64 {
+        self.after.suite_2_only_percent - self.before.suite_2_only_percent
+    }
+}
+
+impl<'a> fmt::Display for CoverageDrop<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let date = |row: &HistoryRow| row.date
+            .map(|x| x.format("%Y-%m-%d").to_string())
+            .unwrap_or_else(|| "-".into());
+        write!(f, "{}: {} only {:.2}% -> {:.2}% ({:+.2}) between {} ({}) and {} ({})",
+               if self.after.directory.is_empty() { "total" } else { &self.after.directory },
+               self.after.suite_2,
+               self.before.suite_2_only_percent,
+               self.after.suite_2_only_percent,
+               self.change(),
+               self.before.changeset,
+               date(self.before),
+               self.after.changeset,
+               date(self.after))
+    }
+}
+
+/// Find the points where the suite 2 only percentage of a directory fell by
+/// more than `threshold` percentage points from one changeset to the next.
+/// `rows` must be in changeset order, as returned by the history.
+pub(crate) fn find_drops(rows: &[HistoryRow], threshold: f64) -> Vec<CoverageDrop<'_>> {
+    let mut by_directory: BTreeMap<&str, Vec<&HistoryRow>> = BTreeMap::new();
+    for row in rows.iter() {
+        by_directory.entry(&row.directory).or_default().push(row);
+    }
+
+    let mut drops = Vec::new();
+    for series in by_directory.values() {
+        for pair in series.windows(2) {
+            let drop = CoverageDrop {
+                before: pair[0],
+                after: pair[1],
+            };
+            if -drop.change() > threshold {
+                drops.push(drop);
+            }
+        }
+    }
+    drops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_drops, write_history_csv, HistoryRow};
+    use crate::output::OutputOptions;
+    use crate::report::tests::{file, report};
+
+    fn rows(changeset: &str, suite_2_only: i64) -> Vec<HistoryRow> {
+        let report = report(changeset, vec![file("dom/a.cpp", 1, suite_2_only, 100)]);
+        report.top_level_totals()
+            .into_iter()
+            .map(|(dir, totals)| HistoryRow::new(&report.metadata, None, None, dir, &totals))
+            .collect()
+    }
+
+    #[test]
+    fn csv() {
+        let mut out = Vec::new();
+        write_history_csv(&mut out, &rows("abc", 10), &OutputOptions::default()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let mut lines = out.lines();
+        assert_eq!(lines.next(), Some("changeset,push,date,directory,files,\
+                                       web-platform-tests only,mochitest-plain only,\
+                                       both,total covered,total coverable,total lines,\
+                                       web-platform-tests-only percent,mochitest-plain-only percent,coverage percent"));
+        assert_eq!(lines.next(), Some("abc,,,,1,1,10,89,100,100,100,1,10,100"));
+        assert_eq!(lines.next(), Some("abc,,,dom,1,1,10,89,100,100,100,1,10,100"));
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn drops() {
+        let history = [rows("a", 30), rows("b", 29), rows("c", 10), rows("d", 40), rows("e", 5)].concat();
+        let drops = find_drops(&history, 15.0)
+            .iter()
+            .map(|x| (x.after.directory.as_str(), x.before.changeset.as_str(), x.after.changeset.as_str(), x.change()))
+            .collect::<Vec<_>>();
+        assert_eq!(drops, vec![("", "b", "c", -19.0), ("", "d", "e", -35.0),
+                               ("dom", "b", "c", -19.0), ("dom", "d", "e", -35.0)]);
+        assert!(find_drops(&history, 40.0).is_empty());
+        assert_eq!(find_drops(&history, 30.0)[0].to_string(),
+                   "total: mochitest-plain only 40.00% -> 5.00% (-35.00) between d (-) and e (-)");
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::HashSet;
+use std::sync::{Arc, LazyLock, Mutex};
+
+use serde::{Deserialize, Deserializer};
+
+/// A set of strings that hands out a shared copy of each
+#[derive(Default)]
+struct Interner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(rv) = self.strings.get(s) {
+            return rv.clone();
+        }
+        let rv: Arc<str> = Arc::from(s);
+        self.strings.insert(rv.clone());
+        rv
+    }
+
+    fn clear(&mut self) {
+        self.strings.clear();
+        self.strings.shrink_to_fit();
+    }
+}
+
+/// Strings that are repeated across the entries of a comparison, such as
+/// paths, which appear in both suites and in the parent directory's
+/// children, and entry types and changesets, which are the same for almost
+/// every entry. Each is only stored once however many entries refer to it.
+/// This is cleared once the suites are loaded, so that strings from one
+/// comparison aren't kept for the rest of a long-running process.
+static STRINGS: LazyLock<Mutex<Interner>> = LazyLock::new(Default::default);
+
+pub(crate) fn intern(s: &str) -> Arc<str> {
+    STRINGS.lock().unwrap().intern(s)
+}
+
+/// Forget the interned strings. Strings that are still in use are kept by
+/// their users, but aren't shared with strings interned afterwards.
+pub(crate) fn clear() {
+    STRINGS.lock().unwrap().clear();
+}
+
+/// Read a string field as an interned string
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Arc<str>, D::Error> {
+    Ok(intern(&String::deserialize(deserializer)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::Interner;
+
+    #[test]
+    fn interned() {
+        let mut interner = Interner::default();
+        let path = interner.intern("dom/base/Element.cpp");
+        assert!(Arc::ptr_eq(&path, &interner.intern("dom/base/Element.cpp")));
+        assert!(!Arc::ptr_eq(&path, &interner.intern("dom/base/Node.cpp")));
+        assert_eq!(interner.strings.len(), 2);
+
+        interner.clear();
+        assert!(interner.strings.is_empty());
+        let after = interner.intern("dom/base/Element.cpp");
+        assert_eq!(path, after);
+        assert!(!Arc::ptr_eq(&path, &after));
+    }
+}
@@ -0,0 +1,271 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::{intern, log, normalize_path, parse, CoverageMap, Error, PathCoverage, Result};
+
+/// A directory or file node in a grcov covdir report
+#[derive(Debug, Deserialize)]
+struct CovdirNode {
+    children: Option<BTreeMap<String, CovdirNode>>,
+    coverage: Option<Vec<i64>>,
+}
+
+fn load_covdir(path: &Path) -> Result<Vec<(String, Vec<i64>)>> {
+    let root: CovdirNode = parse::from_file(path)?;
+    let mut rv = Vec::new();
+    let mut stack = vec![(String::new(), root)];
+    while let Some((node_path, node)) = stack.pop() {
+        if let Some(coverage) = node.coverage {
+            rv.push((node_path.clone(), coverage));
+        }
+        if let Some(children) = node.children {
+            for (name, child) in children.into_iter() {
+                let child_path = if node_path.is_empty() {
+                    name
+                } else {
+                    format!("{}/{}", node_path, name)
+                };
+                stack.push((child_path, child));
+            }
+        }
+    }
+    Ok(rv)
+}
+
+fn lcov_line_hits(value: &str) -> Option<(usize, i64)> {
+    let mut parts = value.split(',');
+    let line = parts.next()?.trim().parse::<usize>().ok()?;
+    let hits = parts.next()?.trim().parse::<i64>().ok()?;
+    Some((line, hits))
+}
+
+/// Convert a set of (1-based line number, hit count) pairs into a
+/// coverage vector, with -1 for lines that aren't instrumented.
+pub(crate) fn line_hits_to_coverage(hits: &BTreeMap<usize, i64>) -> Vec<i64> {
+    let len = hits.keys().next_back().cloned().unwrap_or(0);
+    let mut coverage = vec![-1; len];
+    for (line, count) in hits.iter() {
+        if *line > 0 {
+            coverage[line - 1] = *count;
+        }
+    }
+    coverage
+}
+
+fn load_lcov(path: &Path) -> Result<Vec<(String, Vec<i64>)>> {
+    parse_lcov(BufReader::new(File::open(path)?), &path.display().to_string())
+}
+
+/// Parse LCOV data; `name` is only used to identify the source in warnings
+pub(crate) fn parse_lcov<R: BufRead>(f: R, name: &str) -> Result<Vec<(String, Vec<i64>)>> {
+    let mut rv = Vec::new();
+    let mut current: Option<(String, BTreeMap<usize, i64>)> = None;
+    for line in f.lines() {
+        let line = line?;
+        let line = line.trim();
+        if let Some(source) = line.strip_prefix("SF:") {
+            current = Some((source.trim_start_matches("./").to_owned(), BTreeMap::new()));
+        } else if let Some(value) = line.strip_prefix("DA:") {
+            if let Some((_, ref mut hits)) = current {
+                match lcov_line_hits(value) {
+                    Some((line_no, count)) => {
+                        let entry = hits.entry(line_no).or_insert(0);
+                        *entry += count;
+                    }
+                    None => log::warning(format_args!("Invalid LCOV line {} in {}", line, name)),
+                }
+            }
+        } else if line == "end_of_record" {
+            if let Some((source, hits)) = current.take() {
+                rv.push((source, line_hits_to_coverage(&hits)));
+            }
+        }
+    }
+    if let Some((source, hits)) = current.take() {
+        rv.push((source, line_hits_to_coverage(&hits)));
+    }
+    Ok(rv)
+}
+
+fn load_cobertura(path: &Path) -> Result<Vec<(String, Vec<i64>)>> {
+    let data = fs::read_to_string(path)?;
+    let doc = roxmltree::Document::parse(&data)
+        .map_err(|e| Error::Data(format!("Failed to parse {}: {}", path.display(), e)))?;
+    let mut files: BTreeMap<String, BTreeMap<usize, i64>> = BTreeMap::new();
+    for class in doc.descendants().filter(|x| x.has_tag_name("class")) {
+        let filename = match class.attribute("filename") {
+            Some(filename) => filename.trim_start_matches("./").to_owned(),
+            None => continue
+        };
+        let hits = files.entry(filename).or_default();
+        // Only the class-level <lines>; the per-method ones repeat the same lines
+        let lines = class.children().filter(|x| x.has_tag_name("lines"));
+        for line in lines.flat_map(|x| x.children()).filter(|x| x.has_tag_name("line")) {
+            let number = line.attribute("number").and_then(|x| x.parse::<usize>().ok());
+            let count = line.attribute("hits").and_then(|x| x.parse::<i64>().ok());
+            match (number, count) {
+                (Some(number), Some(count)) => {
+                    let entry = hits.entry(number).or_insert(0);
+                    *entry = cmp::max(*entry, count);
+                }
+                _ => log::warning(format_args!("Invalid Cobertura line in {}", path.display()))
+            }
+        }
+    }
+    Ok(files
+        .into_iter()
+        .map(|(filename, hits)| (filename, line_hits_to_coverage(&hits)))
+        .collect())
+}
+
+/// Load per-file coverage vectors from a local coverage file, picking
+/// the format based on the file extension.
+pub(crate) fn load_file(path: &Path) -> Result<Vec<(String, Vec<i64>)>> {
+    match path.extension().and_then(|x| x.to_str()) {
+        Some("json") => load_covdir(path),
+        Some("info") | Some("lcov") => load_lcov(path),
+        Some("xml") => load_cobertura(path),
+        _ => Err(Error::Data(format!("Unknown coverage file format {}", path.display())))
+    }
+}
+
+/// Combine two coverage vectors for the same file so that a line is
+/// covered if it was covered in either input.
+pub(crate) fn union_coverage(existing: &mut Vec<i64>, other: &[i64]) {
+    if existing.len() < other.len() {
+        existing.resize(other.len(), -1);
+    }
+    for (target, hits) in existing.iter_mut().zip(other.iter()) {
+        *target = match (*target, *hits) {
+            (-1, -1) => -1,
+            (x, y) => x.max(0) + y.max(0),
+        };
+    }
+}
+
+fn under_roots(path: &str, roots: &[&str]) -> bool {
+    roots.iter().any(|root| {
+        root.is_empty()
+            || path == *root
+            || (path.starts_with(root) && path[root.len()..].starts_with('/'))
+    })
+}
+
+/// Load several local coverage files and merge them into a single
+/// suite, restricted to files under the given roots.
+pub(crate) fn load_merged(paths: &[&Path], gecko_roots: &[&str]) -> Result<CoverageMap> {
+    let mut merged = MergedCoverage::new(gecko_roots);
+    for path in paths.iter() {
+        log::info(format_args!("Loading {}", path.display()));
+        merged.add(load_file(path)?);
+    }
+    Ok(merged.into_coverage_map())
+}
+
+/// Accumulator for coverage vectors from several sources, restricted to
+/// files under a set of roots.
+pub(crate) struct MergedCoverage<'a> {
+    gecko_roots: &'a [&'a str],
+    files: BTreeMap<String, Vec<i64>>,
+}
+
+impl<'a> MergedCoverage<'a> {
+    pub(crate) fn new(gecko_roots: &'a [&'a str]) -> MergedCoverage<'a> {
+        MergedCoverage {
+            gecko_roots,
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Check if a path is under one of the roots
+    pub(crate) fn includes(&self, path: &str) -> bool {
+        under_roots(&normalize_path(path), self.gecko_roots)
+    }
+
+    pub(crate) fn add(&mut self, entries: Vec<(String, Vec<i64>)>) {
+        for (file_path, coverage) in entries {
+            let file_path = normalize_path(&file_path);
+            if !self.includes(&file_path) {
+                continue;
+            }
+            match self.files.get_mut(&file_path) {
+                Some(existing) => union_coverage(existing, &coverage),
+                None => {
+                    self.files.insert(file_path, coverage);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn into_coverage_map(self) -> CoverageMap {
+        self.files
+            .into_iter()
+            .map(|(path, coverage)| (intern::intern(&path), PathCoverage::file(&path, coverage)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::process;
+
+    use super::{load_file, parse_lcov, union_coverage, MergedCoverage};
+
+    #[test]
+    fn lcov() {
+        let data = "TN:\nSF:./dom/base/a.cpp\nDA:2,3\nDA:4,0\nDA:2,1\nDA:x,1\nend_of_record\n\
+                    SF:layout/b.cpp\nDA:1,5\n";
+        let files = parse_lcov(data.as_bytes(), "test").unwrap();
+        assert_eq!(files, vec![("dom/base/a.cpp".to_owned(), vec![-1, 4, -1, 0]),
+                               ("layout/b.cpp".to_owned(), vec![5])]);
+    }
+
+    #[test]
+    fn cobertura() {
+        let data = r#"<?xml version="1.0"?>
+<coverage>
+  <packages><package><classes>
+    <class filename="./dom/base/a.cpp">
+      <methods><method><lines><line number="1" hits="9"/></lines></method></methods>
+      <lines><line number="1" hits="2"/><line number="3" hits="0"/></lines>
+    </class>
+    <class filename="dom/base/a.cpp">
+      <lines><line number="1" hits="1"/><line number="2" hits="4"/></lines>
+    </class>
+  </classes></package></packages>
+</coverage>"#;
+        let path = std::env::temp_dir().join(format!("wptcoverage-cobertura-{}.xml", process::id()));
+        fs::write(&path, data).unwrap();
+        let files = load_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(files.unwrap(), vec![("dom/base/a.cpp".to_owned(), vec![2, 4, 0])]);
+    }
+
+    #[test]
+    fn unknown_format() {
+        assert!(load_file(Path::new("coverage.txt")).is_err());
+    }
+
+    #[test]
+    fn merge() {
+        let mut existing = vec![-1, 0, 2];
+        union_coverage(&mut existing, &[1, -1, 3, 0]);
+        assert_eq!(existing, vec![1, 0, 5, 0]);
+
+        let roots = ["dom"];
+        let mut merged = MergedCoverage::new(&roots);
+        merged.add(vec![("./dom/a.cpp".to_owned(), vec![1, -1]),
+                        ("domain/b.cpp".to_owned(), vec![1]),
+                        ("dom//a.cpp".to_owned(), vec![-1, 2])]);
+        assert!(merged.includes("dom/a.cpp"));
+        assert!(!merged.includes("domain/b.cpp"));
+        assert_eq!(merged.files.into_iter().collect::<Vec<_>>(), vec![("dom/a.cpp".to_owned(), vec![1, 2])]);
+    }
+}
@@ -0,0 +1,59 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::progress;
+
+/// How much is logged to stderr. Each level includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub(crate) enum Level {
+    /// Only errors
+    Quiet = 0,
+    /// Warnings, the default
+    Warning = 1,
+    /// What's being loaded and written, with -v
+    Info = 2,
+    /// Every request made, with -vv
+    Debug = 3,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Warning as u8);
+
+/// Set the level from the -q flag and the number of -v flags
+pub(crate) fn init(quiet: bool, verbose: u64) {
+    let level = match (quiet, verbose) {
+        (true, _) => Level::Quiet,
+        (false, 0) => Level::Warning,
+        (false, 1) => Level::Info,
+        (false, _) => Level::Debug,
+    };
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub(crate) fn enabled(level: Level) -> bool {
+    LEVEL.load(Ordering::Relaxed) >= level as u8
+}
+
+fn log(level: Level, prefix: &str, message: fmt::Arguments) {
+    if enabled(level) {
+        // Always stderr, so that logs never end up mixed into the output
+        progress::suspend(|| eprintln!("{}: {}", prefix, message));
+    }
+}
+
+/// The outcome of a check, such as a regression, labelled with `prefix`.
+/// These are shown at the same level as warnings, so -q hides them.
+pub(crate) fn verdict(prefix: &str, message: fmt::Arguments) {
+    log(Level::Warning, prefix, message)
+}
+
+pub(crate) fn warning(message: fmt::Arguments) {
+    log(Level::Warning, "WARNING", message)
+}
+
+pub(crate) fn info(message: fmt::Arguments) {
+    log(Level::Info, "INFO", message)
+}
+
+pub(crate) fn debug(message: fmt::Arguments) {
+    log(Level::Debug, "DEBUG", message)
+}
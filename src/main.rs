@@ -1,24 +1,113 @@
-use serde::{Deserialize};
-use reqwest;
-use serde_json;
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
 use std::cmp;
 use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
 use std::fs::{self, File};
-use std::io::{self, Read, Write};
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
 use structopt::StructOpt;
 
-static BASE_URL: &str =  "https://api.coverage.testing.moz.tools/v2";
+use crate::coverage::LineCoverage;
+
+mod allowlist;
+mod annotate;
+mod api;
+mod badge;
+mod baseline;
+mod blame;
+mod browser;
+mod cache;
+mod chart;
+mod check;
+mod clipboard;
+mod compare;
+mod compat;
+mod completions;
+mod config;
+mod coverage;
+mod coveralls;
+mod editor;
+mod export;
+mod fixtures;
+mod gate;
+mod github;
+mod history;
+mod intern;
+mod local;
+mod log;
+mod man;
+mod notify;
+mod output;
+mod pager;
+mod parse;
+mod progress;
+mod prompt;
+mod report;
+mod serve;
+mod site;
+mod source;
+mod taskcluster;
+mod timings;
+mod tui;
+mod wptreport;
+
 
 #[derive(Debug)]
 pub enum Error {
     Reqwest(reqwest::Error),
     Serde(serde_json::Error),
+    Yaml(serde_yaml::Error),
+    Csv(csv::Error),
+    Sqlite(rusqlite::Error),
+    Arrow(arrow_schema::ArrowError),
+    Parquet(parquet::errors::ParquetError),
+    Template(tera::Error),
     Io(io::Error),
+    /// A JSON document that couldn't be parsed, with where it came from
+    Parse(parse::ParseError),
+    /// Invalid arguments or combinations of options
+    Usage(String),
+    /// Input data that couldn't be read or didn't make sense
+    Data(String),
+    /// A --fail-if, --baseline or check condition failed
+    Threshold(String),
     String(String)
 }
 
+/// Exit codes, so that CI can retry transient failures but not regressions
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_NETWORK: i32 = 3;
+const EXIT_DATA: i32 = 4;
+const EXIT_THRESHOLD: i32 = 5;
+
+static EXIT_CODES_HELP: &str = "EXIT CODES:
+    1    Other failures
+    2    Invalid arguments
+    3    Network errors
+    4    Invalid or missing data
+    5    A --fail-if, --baseline or check condition failed";
+
+impl Error {
+    fn exit_code(&self) -> i32 {
+        match self {
+            Error::Reqwest(_) => EXIT_NETWORK,
+            Error::Serde(_) | Error::Parse(_) | Error::Yaml(_) | Error::Csv(_) | Error::Sqlite(_) | Error::Data(_) => {
+                EXIT_DATA
+            }
+            Error::Usage(_) | Error::Template(_) => EXIT_USAGE,
+            Error::Threshold(_) => EXIT_THRESHOLD,
+            Error::Arrow(_) | Error::Parquet(_) | Error::Io(_) | Error::String(_) => EXIT_FAILURE,
+        }
+    }
+}
+
 impl From<reqwest::Error> for Error {
     fn from(error: reqwest::Error) -> Error {
         Error::Reqwest(error)
@@ -31,6 +120,42 @@ impl From<serde_json::Error> for Error {
     }
 }
 
+impl From<serde_yaml::Error> for Error {
+    fn from(error: serde_yaml::Error) -> Error {
+        Error::Yaml(error)
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(error: csv::Error) -> Error {
+        Error::Csv(error)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(error: rusqlite::Error) -> Error {
+        Error::Sqlite(error)
+    }
+}
+
+impl From<arrow_schema::ArrowError> for Error {
+    fn from(error: arrow_schema::ArrowError) -> Error {
+        Error::Arrow(error)
+    }
+}
+
+impl From<parquet::errors::ParquetError> for Error {
+    fn from(error: parquet::errors::ParquetError) -> Error {
+        Error::Parquet(error)
+    }
+}
+
+impl From<tera::Error> for Error {
+    fn from(error: tera::Error) -> Error {
+        Error::Template(error)
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Error {
         Error::Io(error)
@@ -42,7 +167,19 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 pub fn get(client:&reqwest::Client, url:&str, headers: Option<reqwest::header::HeaderMap>) -> Result<String> {
     // TODO - If there's a list then support continuationToken
-    eprintln!("DEBUG: GET {}", url);
+    String::from_utf8(get_bytes(client, url, headers)?)
+        .map_err(|e| Error::Data(format!("Response from {} isn't valid UTF-8: {}", url, e)))
+}
+
+/// Fetch a response that isn't necessarily text, such as an artifact
+pub(crate) fn get_bytes(client: &reqwest::Client,
+                        url: &str,
+                        headers: Option<reqwest::header::HeaderMap>) -> Result<Vec<u8>> {
+    if api::fixtures() {
+        return Err(Error::Data(format!("Can't fetch {} when using --fixtures", url)));
+    }
+    log::debug(format_args!("GET {}", url));
+    let _timer = timings::start(timings::Stage::Network);
     let mut req = client.get(url);
     if let Some(extra_headers) = headers {
         req = req.headers(extra_headers)
@@ -50,97 +187,573 @@ pub fn get(client:&reqwest::Client, url:&str, headers: Option<reqwest::header::H
     let mut resp = req.send()?;
     resp.error_for_status_ref()?;
     let mut resp_body = match resp.content_length() {
-        Some(len) => String::with_capacity(len as usize),
-        None => String::new()
+        Some(len) => Vec::with_capacity(len as usize),
+        None => Vec::new()
     };
-    resp.read_to_string(&mut resp_body)?;
+    resp.read_to_end(&mut resp_body)?;
+    progress::fetched(resp_body.len() as u64);
     Ok(resp_body)
 }
 
-#[derive(Debug, Deserialize)]
+/// An entry for a directory or file from the coverage API. Responses are
+/// read through `compat`, and missing fields get their defaults, so that
+/// small changes to the API don't make every entry unreadable. Unknown
+/// fields are refused here, so that the entry is read through `compat`,
+/// which leaves them out, rather than buffering every entry to keep them.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
 struct PathCoverage {
-    changeset: String,
+    #[serde(deserialize_with = "intern::deserialize")]
+    changeset: Arc<str>,
     children: Option<Vec<FileCoverage>>,
-    coveragePercent: f64,
-    linesCovered: i64,
-    linesMissed: i64,
-    linesTotal: i64,
-    name: String,
-    path: String,
-    #[serde(rename="type")]
-    path_type: String,
-    coverage: Option<Vec<i64>>
+    coverage_percent: f64,
+    lines_covered: i64,
+    lines_missed: i64,
+    lines_total: i64,
+    #[serde(deserialize_with = "intern::deserialize")]
+    name: Arc<str>,
+    #[serde(deserialize_with = "intern::deserialize")]
+    path: Arc<str>,
+    #[serde(rename="type", deserialize_with = "intern::deserialize")]
+    path_type: Arc<str>,
+    coverage: Option<LineCoverage>,
 }
 
+impl PathCoverage {
+    /// Build a file entry from a coverage vector that didn't come from the API
+    fn file(path: &str, coverage: Vec<i64>) -> PathCoverage {
+        let lines_covered = coverage.iter().filter(|x| **x > 0).count() as i64;
+        let lines_missed = coverage.iter().filter(|x| **x == 0).count() as i64;
+        let lines_total = lines_covered + lines_missed;
+        let coverage_percent = if lines_total > 0 {
+            100f64 * lines_covered as f64 / lines_total as f64
+        } else {
+            0f64
+        };
+        PathCoverage {
+            coverage_percent,
+            lines_covered,
+            lines_missed,
+            lines_total,
+            name: intern::intern(path.rsplit('/').next().unwrap_or(path)),
+            path: intern::intern(path),
+            path_type: intern::intern("file"),
+            coverage: Some(coverage.into()),
+            ..Default::default()
+        }
+    }
 
-#[derive(Debug, Deserialize)]
+    /// Parse an entry from the coverage API or the cache
+    fn from_slice(body: &[u8]) -> serde_json::Result<PathCoverage> {
+        compat::from_slice(body, PathCoverage::is_current, PathCoverage::remove_unexpected)
+    }
+
+    /// Whether the entry was read as it is without needing any defaults
+    /// that `compat` would fill in, i.e. it's in the shape the current API uses
+    fn is_current(&self) -> bool {
+        !self.path_type.is_empty() &&
+            self.children
+                .iter()
+                .flatten()
+                .all(|x| !x.path_type.is_empty())
+    }
+
+    /// Remove and log any fields in a normalized entry, or its children,
+    /// that aren't used
+    fn remove_unexpected(value: &mut serde_json::Value) {
+        compat::remove_unexpected("path entry", value, PATH_FIELDS);
+        if let Some(serde_json::Value::Array(children)) = value.get_mut("children") {
+            for child in children.iter_mut() {
+                compat::remove_unexpected("child entry", child, CHILD_FIELDS);
+            }
+        }
+    }
+}
+
+/// Fields of `PathCoverage`, as they're named in responses
+const PATH_FIELDS: &[&str] = &["changeset",
+                               "children",
+                               "coveragePercent",
+                               "linesCovered",
+                               "linesMissed",
+                               "linesTotal",
+                               "name",
+                               "path",
+                               "type",
+                               "coverage"];
+
+/// Fields of `FileCoverage`, as they're named in responses
+const CHILD_FIELDS: &[&str] = &["children",
+                                "coveragePercent",
+                                "linesCovered",
+                                "linesMissed",
+                                "linesTotal",
+                                "name",
+                                "path",
+                                "type",
+                                "coverage"];
+
+
+/// A child listed in a directory entry. Some entries leave out fields, so
+/// everything is optional here and a child without a path is skipped.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
 struct FileCoverage {
     children: Option<i64>,
-    coveragePercent: f64,
-    linesCovered: i64,
-    linesMissed: i64,
-    linesTotal: i64,
-    name: String,
-    path: String,
-    #[serde(rename="type")]
-    path_type: String,
-    coverage: Option<Vec<i64>>
+    coverage_percent: f64,
+    lines_covered: i64,
+    lines_missed: i64,
+    lines_total: i64,
+    #[serde(deserialize_with = "intern::deserialize")]
+    name: Arc<str>,
+    #[serde(deserialize_with = "intern::deserialize")]
+    path: Arc<str>,
+    #[serde(rename="type", deserialize_with = "intern::deserialize")]
+    path_type: Arc<str>,
+    coverage: Option<LineCoverage>,
 }
 
-type CoverageMap = BTreeMap<String, PathCoverage>;
+/// Entries for a suite by path. Paths are interned, since each is also in
+/// the entry, in the other suite and in the parent directory's children.
+type CoverageMap = BTreeMap<Arc<str>, PathCoverage>;
 
-fn get_suite_data(client: &reqwest::Client,
-                  changeset: &str,
-                  root_path: &Path,
-                  suite_name: &str,
-                  gecko_roots: &[&str]) -> Result<CoverageMap> {
+/// Most entries fetched for one suite. This is well above the number of
+/// directories and files in the tree, so reaching it means the data is
+/// leading the traversal in circles.
+const MAX_TRAVERSAL_ENTRIES: usize = 1_000_000;
 
-    let mut suite_root = root_path.to_owned();
-    suite_root.push(PathBuf::from(suite_name));
-    let mut rv = BTreeMap::new();
+/// Whether `path` is strictly below the directory `dir`; children that
+/// aren't would make the traversal revisit paths it has already been to
+fn is_inside(path: &str, dir: &str) -> bool {
+    if dir.is_empty() {
+        return !path.is_empty();
+    }
+    path.strip_prefix(dir).map(|x| x.starts_with('/')).unwrap_or(false)
+}
+
+/// How entries that are missing or incomplete in the API data are handled
+#[derive(Debug, Clone, Copy, Default)]
+struct FetchOptions {
+    /// Fetch entries again when the cached copy is incomplete
+    requery: bool,
+    /// How many earlier changesets to look for entries in when the changeset
+    /// being compared doesn't have them yet
+    fallback_changesets: usize,
+}
+
+/// Gecko paths that a comparison is restricted to, and how far down the
+/// API data is traversed. The filter is applied while the suites are loaded,
+/// so excluded directories are never fetched. It's recorded in the report,
+/// since runs with different filters cover different files.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct PathFilter {
+    /// Files and directories to keep; empty keeps everything
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    include: Vec<String>,
+    /// Files and directories to skip, even inside an included directory
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    exclude: Vec<String>,
+    /// Depth of the deepest entries that are fetched, with top-level
+    /// directories at depth 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_depth: Option<usize>,
+    /// Directories that are fetched without fetching their children
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    prune: Vec<String>,
+}
+
+impl fmt::Display for PathFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no path filters");
+        }
+        let mut parts = Vec::new();
+        for (name, paths) in [("include", &self.include), ("exclude", &self.exclude), ("prune", &self.prune)] {
+            if !paths.is_empty() {
+                parts.push(format!("{} {}", name, paths.join(",")));
+            }
+        }
+        if let Some(max_depth) = self.max_depth {
+            parts.push(format!("max depth {}", max_depth));
+        }
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+impl PathFilter {
+    fn new(args: &DiffArgs) -> PathFilter {
+        // Sorted so that filters given in a different order are the same
+        let normalize = |paths: &[String]| {
+            let mut rv = paths.iter().map(|x| normalize_path(x)).collect::<Vec<_>>();
+            rv.sort();
+            rv.dedup();
+            rv
+        };
+        PathFilter {
+            include: normalize(&args.include),
+            exclude: normalize(&args.exclude),
+            max_depth: args.max_depth,
+            prune: normalize(&args.prune),
+        }
+    }
+
+    /// Whether the whole tree is compared
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == PathFilter::default()
+    }
+
+    /// Whether `path` is `root` or inside it
+    fn is_under(path: &str, root: &str) -> bool {
+        path == root || is_inside(path, root)
+    }
+
+    fn is_excluded(&self, path: &str) -> bool {
+        self.exclude.iter().any(|x| PathFilter::is_under(path, x))
+    }
+
+    /// Whether a normalized path is kept
+    pub(crate) fn includes(&self, path: &str) -> bool {
+        !self.is_excluded(path) &&
+            (self.include.is_empty() || self.include.iter().any(|x| PathFilter::is_under(path, x)))
+    }
+
+    /// Whether any of the normalized path is kept, i.e. it's kept or it's a
+    /// directory containing an included path, so whether it has to be fetched
+    fn visits(&self, path: &str) -> bool {
+        self.includes(path) ||
+            (!self.is_excluded(path) && self.include.iter().any(|x| PathFilter::is_under(x, path)))
+    }
+
+    /// Whether the children of the directory at a normalized path are fetched
+    fn expands(&self, path: &str) -> bool {
+        let depth = if path.is_empty() { 0 } else { path.split('/').count() };
+        self.max_depth.map(|x| depth < x).unwrap_or(true) &&
+            !self.prune.iter().any(|x| PathFilter::is_under(path, x))
+    }
+}
+
+/// Traverse the API data for a suite, passing each entry to `f` as soon as
+/// it's loaded. Directories that `filter` excludes aren't fetched.
+#[allow(clippy::too_many_arguments)]
+fn visit_suite_data<F>(client: &reqwest::Client,
+                       changeset: &str,
+                       root_path: &Path,
+                       suite_name: &str,
+                       gecko_roots: &[&str],
+                       filter: &PathFilter,
+                       options: FetchOptions,
+                       mut f: F) -> Result<()>
+where F: FnMut(Arc<str>, PathCoverage) -> Result<()> {
+
+    let suite_root = cache::long_path(&root_path.join(cache::component(suite_name)));
 
     if !suite_root.exists() {
         fs::create_dir_all(&suite_root)?;
     }
 
-    let mut stack: Vec<String> = Vec::new();
-    for root in gecko_roots.iter() {
-        stack.push((*root).to_owned());
+    let mut stack: Vec<Arc<str>> = Vec::new();
+    for root in gecko_roots.iter().map(|x| normalize_path(x)).filter(|x| filter.visits(x)) {
+        stack.push(intern::intern(&root));
     }
+    progress::discovered(stack.len());
+    // Paths that have been queued, so that a child listed under two
+    // spellings is only fetched once
+    let mut queued = stack.iter().cloned().collect::<std::collections::BTreeSet<_>>();
+    // Found the first time an entry is missing
+    let mut fallbacks: Option<Vec<String>> = None;
+
+    let mut visited = 0;
+    // Directories with children that weren't fetched because of the depth limits
+    let mut unexpanded = 0;
 
     while let Some(gecko_path) = stack.pop() {
-        let mut local_path = suite_root.clone();
-        local_path.push(PathBuf::from(format!("{}.json", gecko_path.replace("/", "-"))));
-
-        if !local_path.exists() {
-            let url = format!("{}/path?path={}&suite={}&changeset={}",
-                              BASE_URL,
-                              gecko_path,
-                              suite_name,
-                              changeset);
-            let resp_str = get(&client,
-                               &url,
-                               None)?;
-            let mut f = File::create(&local_path)?;
-            f.write_all(&resp_str.as_bytes())?;
+        if visited >= MAX_TRAVERSAL_ENTRIES {
+            report::record_warning(report::Warning::TraversalAnomaly {
+                suite: suite_name.to_owned(),
+                path: gecko_path.to_string(),
+                problem: format!("traversal stopped after {} entries, with {} more queued",
+                                 visited,
+                                 stack.len() + 1),
+            });
+            break;
+        }
+        visited += 1;
+        let local_path = cache::file_path(&suite_root, &gecko_path, "json");
+        let endpoint = api::Endpoint::Path {
+            path: &gecko_path,
+            suite: Some(suite_name),
+            changeset: Some(changeset),
         };
 
-        let f = File::open(&local_path)?;
-        let data: PathCoverage = serde_json::from_reader(f)?;
+        let mut data = match read_path_coverage(client, &endpoint, &local_path, false) {
+            Ok(data) => data,
+            Err(ref e) if options.fallback_changesets > 0 && is_client_error(e) => {
+                Err("entry isn't available for the changeset".to_owned())
+            }
+            Err(e) => return Err(e)
+        };
+        if options.requery && !entry_problems(&data).is_empty() {
+            log::info(format_args!("Fetching incomplete entry {} again", gecko_path));
+            match read_path_coverage(client, &endpoint, &local_path, true) {
+                Ok(refreshed) => data = refreshed,
+                Err(e) => log::warning(format_args!("Failed to fetch {} again: {:?}", gecko_path, e))
+            }
+        }
+        if options.fallback_changesets > 0 && !entry_problems(&data).is_empty() {
+            let fallbacks = match fallbacks {
+                Some(ref fallbacks) => fallbacks,
+                None => fallbacks.insert(fallback_changesets(client, changeset, options.fallback_changesets)?)
+            };
+            if let Some((fallback, fallback_data)) = fallback_entry(client, fallbacks, suite_name, &gecko_path)? {
+                report::record_warning(report::Warning::FallbackChangeset {
+                    suite: suite_name.to_owned(),
+                    path: gecko_path.to_string(),
+                    changeset: fallback.to_owned(),
+                });
+                data = Ok(fallback_data);
+            }
+        }
+        for problem in entry_problems(&data) {
+            report::record_warning(report::Warning::IncompleteEntry {
+                suite: suite_name.to_owned(),
+                path: gecko_path.to_string(),
+                problem,
+            });
+        }
+        progress::visited();
 
-        if let Some(ref children) = data.children {
-            for file in children.iter() {
-                stack.push(file.path.clone());
+        let data = match data {
+            Ok(data) => data,
+            Err(_) => continue
+        };
+        match data.children {
+            Some(ref children) if filter.expands(&gecko_path) => {
+                let paths = children
+                    .iter()
+                    .filter(|x| !x.path.is_empty())
+                    .map(|x| intern::intern(&normalize_path(&x.path)))
+                    .filter(|x| {
+                        let inside = is_inside(x, &gecko_path);
+                        if !inside {
+                            report::record_warning(report::Warning::TraversalAnomaly {
+                                suite: suite_name.to_owned(),
+                                path: gecko_path.to_string(),
+                                problem: format!("child {} isn't inside the directory, so it was skipped", x),
+                            });
+                        }
+                        inside
+                    })
+                    .filter(|x| filter.visits(x) && queued.insert(x.clone()))
+                    .collect::<Vec<_>>();
+                progress::discovered(paths.len());
+                stack.extend(paths);
             }
+            Some(ref children) if !children.is_empty() => unexpanded += 1,
+            _ => {}
         }
 
-        rv.insert(gecko_path.clone(), data);
+        f(gecko_path, data)?;
+    }
+    if unexpanded > 0 {
+        log::info(format_args!("Stopped at {} directories in {} because of --max-depth or --prune; \
+                                the files in them aren't compared",
+                               unexpanded,
+                               suite_name));
     }
 
-    Ok(rv)
+    Ok(())
 }
 
+/// Read the API entry for a path from the cache, fetching it when it isn't
+/// cached or `refresh` is set. Only failing to fetch the entry is an error;
+/// an entry that can't be parsed is returned as the parse error message.
+fn read_path_coverage(client: &reqwest::Client,
+                      endpoint: &api::Endpoint,
+                      local_path: &Path,
+                      refresh: bool) -> Result<std::result::Result<PathCoverage, String>> {
+    let body = if refresh || !local_path.exists() {
+        let resp_str = api::get(client, endpoint)?;
+        cache::write(local_path, resp_str.as_bytes())?;
+        resp_str.into_bytes()
+    } else {
+        // Read in one go rather than through a reader, since directory
+        // entries can be many megabytes
+        timings::time(timings::Stage::CacheRead, || fs::read(local_path))?
+    };
+    let parsed = timings::time(timings::Stage::Parse, || PathCoverage::from_slice(&body));
+    let data: PathCoverage = match parsed {
+        Ok(data) => data,
+        Err(e) => {
+            let body = String::from_utf8_lossy(&body);
+            let error = parse::ParseError::new(&local_path.display().to_string(), Some(local_path), &body, e);
+            return Ok(Err(format!("entry can't be read ({})", error.summary())))
+        }
+    };
+    Ok(Ok(data))
+}
+
+/// The nearest `count` changesets before `changeset` that have coverage
+/// data, newest first
+fn fallback_changesets(client: &reqwest::Client, changeset: &str, count: usize) -> Result<Vec<String>> {
+    let recent = get_recent_changesets(client)?;
+    let idx = match recent.iter().position(|x| x.changeset == changeset) {
+        Some(idx) => idx,
+        None => {
+            log::warning(format_args!("Changeset {} isn't listed as having recent coverage data, so there are no \
+                                       earlier changesets to fill in missing entries from",
+                                      changeset));
+            return Ok(Vec::new());
+        }
+    };
+    let changesets = if recent.len() > idx + count {
+        recent
+    } else {
+        get_changesets(client, idx + 1 + count)?
+    };
+    Ok(changesets.into_iter().skip(idx + 1).take(count).map(|x| x.changeset).collect())
+}
+
+/// Find a complete entry for a path in the first of `fallbacks` that has
+/// one, along with the changeset it came from
+fn fallback_entry<'a>(client: &reqwest::Client,
+                      fallbacks: &'a [String],
+                      suite_name: &str,
+                      gecko_path: &str) -> Result<Option<(&'a str, PathCoverage)>> {
+    for fallback in fallbacks.iter() {
+        let suite_root = cache::long_path(&cache::changeset_root(fallback).join(cache::component(suite_name)));
+        fs::create_dir_all(&suite_root)?;
+        let endpoint = api::Endpoint::Path {
+            path: gecko_path,
+            suite: Some(suite_name),
+            changeset: Some(fallback),
+        };
+        let local_path = cache::file_path(&suite_root, gecko_path, "json");
+        let data = match read_path_coverage(client, &endpoint, &local_path, false) {
+            Ok(data) => data,
+            Err(ref e) if is_client_error(e) => continue,
+            Err(e) => return Err(e)
+        };
+        if entry_problems(&data).is_empty() {
+            if let Ok(data) = data {
+                return Ok(Some((fallback, data)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Ways in which an API entry is incomplete, if any
+fn entry_problems(data: &std::result::Result<PathCoverage, String>) -> Vec<String> {
+    let data = match data {
+        Ok(data) => data,
+        Err(message) => return vec![message.clone()]
+    };
+    let mut rv = Vec::new();
+    if &*data.path_type == "directory" {
+        match data.children {
+            None => rv.push("directory has no children listed".to_owned()),
+            Some(ref children) => {
+                let unnamed = children.iter().filter(|x| x.path.is_empty()).count();
+                if unnamed > 0 {
+                    rv.push(format!("{} children have no path", unnamed));
+                }
+            }
+        }
+    } else if data.coverage.is_none() {
+        rv.push("file has no coverage".to_owned());
+    }
+    rv
+}
+
+/// Where the data for a suite comes from
 #[derive(Debug)]
+enum SuiteSource {
+    /// A suite hosted by the coverage API
+    Api(String),
+    /// Several local covdir, LCOV or Cobertura files combined into one suite
+    Merge(Vec<PathBuf>),
+    /// Raw grcov artifacts from the Taskcluster tasks for a suite, either
+    /// from an explicit task group or the decision task for the changeset
+    Taskcluster {
+        suite: String,
+        task_group: Option<String>,
+    },
+    /// Per-file line coverage from a Coveralls build
+    Coveralls(String),
+}
+
+impl SuiteSource {
+    fn parse(suite: &str) -> Result<SuiteSource> {
+        if let Some(files) = suite.strip_prefix("merge:") {
+            let paths = files
+                .split(',')
+                .map(|x| x.trim())
+                .filter(|x| !x.is_empty())
+                .map(PathBuf::from)
+                .collect::<Vec<_>>();
+            if paths.is_empty() {
+                return Err(Error::Usage(format!("No files given in suite {}", suite)));
+            }
+            Ok(SuiteSource::Merge(paths))
+        } else if let Some(spec) = suite.strip_prefix("taskcluster:") {
+            let mut parts = spec.splitn(2, '@');
+            let suite_name = parts.next().unwrap_or("");
+            if suite_name.is_empty() {
+                return Err(Error::Usage(format!("No suite name given in suite {}", suite)));
+            }
+            Ok(SuiteSource::Taskcluster {
+                suite: suite_name.to_owned(),
+                task_group: parts.next().map(|x| x.to_owned()),
+            })
+        } else if let Some(build) = suite.strip_prefix("coveralls:") {
+            if build.is_empty() {
+                return Err(Error::Usage(format!("No build given in suite {}", suite)));
+            }
+            Ok(SuiteSource::Coveralls(build.to_owned()))
+        } else {
+            Ok(SuiteSource::Api(suite.to_owned()))
+        }
+    }
+}
+
+/// Load a suite, passing each entry to `f`. Entries from the API are passed
+/// on as they're fetched; other sources are loaded in full first.
+#[allow(clippy::too_many_arguments)]
+fn visit_suite<F>(client: &reqwest::Client,
+                  changeset: &str,
+                  root_path: &Path,
+                  source: &SuiteSource,
+                  gecko_roots: &[&str],
+                  filter: &PathFilter,
+                  options: FetchOptions,
+                  mut f: F) -> Result<()>
+where F: FnMut(Arc<str>, PathCoverage) -> Result<()> {
+    let data = match source {
+        SuiteSource::Api(suite_name) => {
+            return visit_suite_data(client, changeset, root_path, suite_name, gecko_roots, filter, options, f);
+        }
+        SuiteSource::Merge(paths) => {
+            let paths = paths.iter().map(|x| x.as_path()).collect::<Vec<_>>();
+            local::load_merged(&paths, gecko_roots)?
+        }
+        SuiteSource::Taskcluster { suite, task_group } => {
+            taskcluster::get_suite_data(client,
+                                        changeset,
+                                        root_path,
+                                        suite,
+                                        task_group.as_ref().map(|x| x.as_str()),
+                                        gecko_roots)?
+        }
+        SuiteSource::Coveralls(build) => coveralls::get_suite_data(client, root_path, build, gecko_roots, filter)?,
+    };
+    for (path, entry) in data.into_iter().filter(|(path, _)| filter.includes(path)) {
+        f(path, entry)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum CoverageType {
     NotRun,
     NotCovered,
@@ -149,6 +762,35 @@ enum CoverageType {
     Both
 }
 
+impl CoverageType {
+    /// Short name used for the category in output and on the command line
+    fn name(self) -> &'static str {
+        match self {
+            CoverageType::NotRun => "not-run",
+            CoverageType::NotCovered => "not-covered",
+            CoverageType::Suite1Only => "suite1-only",
+            CoverageType::Suite2Only => "suite2-only",
+            CoverageType::Both => "both",
+        }
+    }
+}
+
+impl std::str::FromStr for CoverageType {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<CoverageType, String> {
+        [CoverageType::NotRun,
+         CoverageType::NotCovered,
+         CoverageType::Suite1Only,
+         CoverageType::Suite2Only,
+         CoverageType::Both]
+            .iter()
+            .find(|x| x.name() == s)
+            .cloned()
+            .ok_or_else(|| format!("Unknown coverage category {}", s))
+    }
+}
+
 struct CoverageDifference {
     line_differences: Vec<CoverageType>,
     line_count: i64,
@@ -157,20 +799,48 @@ struct CoverageDifference {
     suite_1_only_count: i64,
     suite_2_only_count: i64,
     both_count: i64,
+    /// Lengths of the two coverage vectors, when they differ
+    line_count_mismatch: Option<(usize, usize)>,
+    /// Hash of the two coverage vectors, see `coverage_hash`
+    coverage_hash: Option<String>,
+}
+
+impl CoverageDifference {
+    /// The counters of a record from an earlier run, without the line level
+    /// differences
+    fn from_record(record: &report::FileRecord) -> CoverageDifference {
+        CoverageDifference {
+            line_differences: Vec::new(),
+            line_count: record.lines,
+            coverable_count: record.coverable,
+            covered_count: record.covered,
+            suite_1_only_count: record.suite_1_only,
+            suite_2_only_count: record.suite_2_only,
+            both_count: record.both,
+            line_count_mismatch: None,
+            coverage_hash: record.coverage_hash.clone(),
+        }
+    }
 }
 
-fn coverage_difference(suite_1_coverage: &[i64], suite_2_coverage:&[i64]) -> CoverageDifference {
-    let mut line_differences = Vec::new();
+/// Compare a file's coverage in the two suites. The line level differences
+/// are only kept if `keep_lines` is set, so that they aren't allocated when
+/// the output doesn't use them.
+fn coverage_difference(suite_1_coverage: &LineCoverage,
+                       suite_2_coverage: &LineCoverage,
+                       keep_lines: bool) -> CoverageDifference {
+    let _timer = timings::start(timings::Stage::Diff);
     let mut suite_2_only_count = 0;
     let mut suite_1_only_count = 0;
     let mut both_count = 0;
 
-    let line_count = if suite_2_coverage.len() != suite_1_coverage.len() {
-        eprintln!("WARNING: line counts differ");
-        cmp::min(suite_1_coverage.len(), suite_2_coverage.len())
+    let line_count_mismatch = if suite_2_coverage.len() != suite_1_coverage.len() {
+        Some((suite_1_coverage.len(), suite_2_coverage.len()))
     } else {
-        suite_1_coverage.len()
-    } as i64;
+        None
+    };
+    let line_count = cmp::min(suite_1_coverage.len(), suite_2_coverage.len()) as i64;
+    let mut line_differences = Vec::with_capacity(if keep_lines { line_count as usize } else { 0 });
 
     let mut coverable_count = line_count;
     for (suite_1_hit_count, suite_2_hit_count) in suite_1_coverage.iter().zip(suite_2_coverage.iter()) {
@@ -182,11 +852,11 @@ fn coverage_difference(suite_1_coverage: &[i64], suite_2_coverage:&[i64]) -> Cov
             (0, 0) => {
                 CoverageType::NotCovered
             },
-            (x, y) if *x > 0 && *y <= 0 => {
+            (x, y) if x > 0 && y <= 0 => {
                 suite_1_only_count += 1;
                 CoverageType::Suite1Only
             },
-            (x, y) if *x <= 0 && *y > 0 => {
+            (x, y) if x <= 0 && y > 0 => {
                 suite_2_only_count += 1;
                 CoverageType::Suite2Only
             },
@@ -196,7 +866,9 @@ fn coverage_difference(suite_1_coverage: &[i64], suite_2_coverage:&[i64]) -> Cov
             }
         };
         //println!("{} {} {:?}", suite_1_hit_count, suite_2_hit_count, coverage_type);
-        line_differences.push(coverage_type);
+        if keep_lines {
+            line_differences.push(coverage_type);
+        }
     }
 
     let covered_count = both_count + suite_1_only_count + suite_2_only_count;
@@ -208,112 +880,2625 @@ fn coverage_difference(suite_1_coverage: &[i64], suite_2_coverage:&[i64]) -> Cov
         suite_2_only_count,
         suite_1_only_count,
         both_count,
+        line_count_mismatch,
+        coverage_hash: None,
     }
 }
 
-fn zero_coverage(other_data: &[i64]) -> Vec<i64> {
-    other_data.iter().map(|x| if *x == -1 {-1} else {0}).collect()
+/// Hash of a file's coverage in both suites, which is stored in reports so
+/// that later runs can tell whether it changed
+fn coverage_hash(suite_1_coverage: &LineCoverage, suite_2_coverage: &LineCoverage) -> String {
+    format!("{:016x}{:016x}", suite_1_coverage.content_hash(), suite_2_coverage.content_hash())
 }
 
-fn get_differences(suite_1_data: CoverageMap, suite_2_data: CoverageMap) -> BTreeMap<String, CoverageDifference> {
-    let mut rv = BTreeMap::new();
+/// Compare a file's coverage in the two suites, reusing the counters from
+/// `previous` if its coverage hasn't changed since
+fn file_difference(path: &str,
+                   suite_1_coverage: &LineCoverage,
+                   suite_2_coverage: &LineCoverage,
+                   previous: Option<&report::PreviousReport>,
+                   keep_lines: bool) -> CoverageDifference {
+    let hash = coverage_hash(suite_1_coverage, suite_2_coverage);
+    // Records don't say whether the line counts differed, so those files are
+    // always compared again to warn about it
+    if suite_1_coverage.len() == suite_2_coverage.len() {
+        if let Some(record) = previous.and_then(|x| x.unchanged(path, &hash)) {
+            return CoverageDifference::from_record(record);
+        }
+    }
+    let mut rv = coverage_difference(suite_1_coverage, suite_2_coverage, keep_lines);
+    rv.coverage_hash = Some(hash);
+    rv
+}
+
+/// Log a warning if the two suites had coverage for different numbers of
+/// lines in a file, which usually means the data came from different revisions
+fn warn_line_count_mismatch(path: &str, difference: &CoverageDifference) {
+    if let Some((suite_1_lines, suite_2_lines)) = difference.line_count_mismatch {
+        log::warning(format_args!("line counts differ for {}: {} in suite 1, {} in suite 2",
+                                  path,
+                                  suite_1_lines,
+                                  suite_2_lines));
+    }
+}
+
+fn zero_coverage(other_data: &LineCoverage) -> LineCoverage {
+    other_data.zeroed()
+}
+
+/// Files shorter than this aren't matched as renames, since short files are
+/// too likely to look alike by chance
+const MIN_RENAME_LINES: usize = 5;
+
+/// Fraction of lines that must agree on whether they're coverable for a
+/// file only in one suite to be treated as a rename of a file only in the other
+const RENAME_SIMILARITY: f64 = 0.9;
+
+fn file_coverage(data: &CoverageMap) -> impl Iterator<Item=(&str, &LineCoverage)> {
+    data.iter()
+        .filter(|(_, x)| &*x.path_type != "directory")
+        .filter_map(|(path, x)| x.coverage.as_ref().map(|coverage| (&**path, coverage)))
+}
+
+fn extension(path: &str) -> &str {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.rfind('.').map(|idx| &name[idx..]).unwrap_or("")
+}
+
+/// Fraction of lines that are either coverable in both vectors or in neither
+fn coverable_similarity(suite_1_coverage: &LineCoverage, suite_2_coverage: &LineCoverage) -> f64 {
+    let same = suite_1_coverage
+        .iter()
+        .zip(suite_2_coverage.iter())
+        .filter(|(x, y)| (*x == -1) == (*y == -1))
+        .count();
+    same as f64 / suite_1_coverage.len() as f64
+}
+
+/// Find files that only appear in one suite and that look like a file that
+/// only appears in the other, under a new name. Files match when they have
+/// the same extension and number of lines, and mostly the same coverable
+/// lines; only files with a single match are treated as renamed. Returns a
+/// map from the path in suite 2 to the path in suite 1.
+fn find_renames(suite_1_data: &CoverageMap, suite_2_data: &CoverageMap) -> BTreeMap<String, String> {
+    let mut suite_1_only: BTreeMap<(usize, &str), Vec<&str>> = BTreeMap::new();
+    for (path, coverage) in file_coverage(suite_1_data) {
+        if coverage.len() >= MIN_RENAME_LINES && !suite_2_data.contains_key(path) {
+            suite_1_only.entry((coverage.len(), extension(path))).or_default().push(path);
+        }
+    }
+    let mut candidates: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (path, coverage) in file_coverage(suite_2_data).filter(|(path, _)| !suite_1_data.contains_key(*path)) {
+        if let Some(others) = suite_1_only.get(&(coverage.len(), extension(path))) {
+            for other_path in others {
+                let other_coverage = suite_1_data[*other_path].coverage.as_ref();
+                if other_coverage.map(|x| coverable_similarity(x, coverage)).unwrap_or(0f64) >= RENAME_SIMILARITY {
+                    candidates.entry(path).or_default().push(other_path);
+                }
+            }
+        }
+    }
+    let mut matched: BTreeMap<&str, usize> = BTreeMap::new();
+    for other_paths in candidates.values() {
+        for other_path in other_paths {
+            *matched.entry(other_path).or_default() += 1;
+        }
+    }
+    candidates
+        .into_iter()
+        .filter(|(_, other_paths)| other_paths.len() == 1 && matched[other_paths[0]] == 1)
+        .map(|(path, other_paths)| (path.to_owned(), other_paths[0].to_owned()))
+        .collect()
+}
+
+/// Compute the difference for each file in either suite, passing each
+/// result to `f` as soon as it's computed. Files that were renamed between
+/// the suites are compared under their name in suite 2. The line level
+/// differences are left empty unless `keep_lines` is set.
+fn for_each_difference<F>(suite_1_data: &CoverageMap,
+                          suite_2_data: &CoverageMap,
+                          previous: Option<&report::PreviousReport>,
+                          keep_lines: bool,
+                          mut f: F) -> Result<()>
+where F: FnMut(&str, CoverageDifference) -> Result<()> {
+    let mut f = |path: &str, difference: CoverageDifference| {
+        warn_line_count_mismatch(path, &difference);
+        f(path, difference)
+    };
+    progress::phase("diff");
+    let renames = find_renames(suite_1_data, suite_2_data);
+    for (suite_2_path, suite_1_path) in renames.iter() {
+        report::record_warning(report::Warning::Renamed {
+            suite_1_path: suite_1_path.clone(),
+            suite_2_path: suite_2_path.clone(),
+        });
+    }
+    let renamed = renames.values().map(|x| x.as_str()).collect::<std::collections::BTreeSet<_>>();
+    // Files to compare, with the coverage in each suite. Files missing from
+    // one suite are compared with the same lines uncovered.
+    let mut files: Vec<(&str, Option<&LineCoverage>, Option<&LineCoverage>)> = Vec::new();
     for (path, suite_1_coverage) in suite_1_data.iter() {
-        if suite_1_coverage.path_type == "directory" {
+        if &*suite_1_coverage.path_type == "directory" || renamed.contains(&**path) {
             continue;
         }
         if let Some(ref suite_1_coverage_vec) = suite_1_coverage.coverage {
-            if let Some(ref suite_2_coverage) = suite_2_data.get(path) {
-                if let Some(ref suite_2_coverage_vec) = suite_2_coverage.coverage {
-                    rv.insert(path.clone(),
-                              coverage_difference(suite_1_coverage_vec, suite_2_coverage_vec));
+            match suite_2_data.get(path) {
+                Some(suite_2_coverage) => {
+                    if let Some(ref suite_2_coverage_vec) = suite_2_coverage.coverage {
+                        files.push((path, Some(suite_1_coverage_vec), Some(suite_2_coverage_vec)));
+                    }
                 }
-            } else {
-                let suite_2_coverage_vec = zero_coverage(&suite_1_coverage_vec);
-                rv.insert(path.clone(),
-                          coverage_difference(suite_1_coverage_vec, &suite_2_coverage_vec));
-
+                None => files.push((path, Some(suite_1_coverage_vec), None))
             }
         }
     }
     for (path, suite_2_coverage) in suite_2_data.iter() {
-            if !suite_1_data.contains_key(path) {
-                if let Some(ref suite_2_coverage_vec) = suite_2_coverage.coverage {
-                    let suite_1_coverage_vec = zero_coverage(&suite_2_coverage_vec);
-                    rv.insert(path.clone(),
-                              coverage_difference(&suite_1_coverage_vec, suite_2_coverage_vec));
+        if !suite_1_data.contains_key(path) {
+            if let Some(ref suite_2_coverage_vec) = suite_2_coverage.coverage {
+                let suite_1_coverage_vec = renames
+                    .get(&**path)
+                    .and_then(|x| suite_1_data[x.as_str()].coverage.as_ref());
+                files.push((path, suite_1_coverage_vec, Some(suite_2_coverage_vec)));
+            }
+        }
+    }
+    progress::discovered(files.len());
+    let difference = |&(path, suite_1_coverage, suite_2_coverage): &(&str, _, _)| {
+        let difference = match (suite_1_coverage, suite_2_coverage) {
+            (Some(suite_1), Some(suite_2)) => file_difference(path, suite_1, suite_2, previous, keep_lines),
+            (Some(suite_1), None) => file_difference(path, suite_1, &zero_coverage(suite_1), previous, keep_lines),
+            (None, Some(suite_2)) => file_difference(path, &zero_coverage(suite_2), suite_2, previous, keep_lines),
+            (None, None) => unreachable!("Files are in at least one suite")
+        };
+        progress::visited();
+        difference
+    };
+    let threads = thread::available_parallelism().map(|x| x.get()).unwrap_or(1);
+    for_each_in_order(&files, threads, difference, |file, difference| f(file.0, difference))
+}
 
+/// Compute `compute(item)` for each of `items` on `threads` threads, and pass
+/// the results to `f` in the order of `items`. Each worker takes the next
+/// item until there are none left. Results arriving out of order are held
+/// until the ones before them have been passed on, and the workers wait when
+/// `f` falls behind them. The first error from `f` stops the workers and is
+/// returned.
+fn for_each_in_order<T, R, C, F>(items: &[T], threads: usize, compute: C, mut f: F) -> Result<()>
+where T: Sync,
+      R: Send,
+      C: Fn(&T) -> R + Sync,
+      F: FnMut(&T, R) -> Result<()> {
+    if threads <= 1 {
+        for item in items.iter() {
+            f(item, compute(item))?;
+        }
+        return Ok(());
+    }
+    let next = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::sync_channel(DIFFERENCE_BUFFER);
+        for _ in 0..threads {
+            let (sender, next, compute) = (sender.clone(), &next, &compute);
+            scope.spawn(move || loop {
+                let idx = next.fetch_add(1, Ordering::Relaxed);
+                // Sending fails if the comparison stopped because of an error
+                if idx >= items.len() || sender.send((idx, compute(&items[idx]))).is_err() {
+                    break;
                 }
+            });
+        }
+        drop(sender);
+        let mut computed = BTreeMap::new();
+        let mut passed = 0;
+        for (idx, result) in receiver {
+            computed.insert(idx, result);
+            while let Some(result) = computed.remove(&passed) {
+                f(&items[passed], result)?;
+                passed += 1;
             }
-    }
+        }
+        Ok(())
+    })
+}
+
+/// Differences that can be computed ahead of the one being passed on, so
+/// that only a limited number of them is held in memory at a time
+const DIFFERENCE_BUFFER: usize = 4096;
+
+fn get_differences(suite_1_data: CoverageMap, suite_2_data: CoverageMap) -> BTreeMap<String, CoverageDifference> {
+    let mut rv = BTreeMap::new();
+    // Collecting into the map can't fail
+    let _ = for_each_difference(&suite_1_data, &suite_2_data, None, true, |path, difference| {
+        rv.insert(path.to_owned(), difference);
+        Ok(())
+    });
     rv
 }
 
 fn get_latest_changeset(client: &reqwest::Client) -> Result<String> {
-    let resp_str = get(&client,
-                       &format!("{}/path?path=", BASE_URL),
-                       None)?;
-    let data: PathCoverage = serde_json::from_str(&resp_str)?;
-    Ok(data.changeset)
+    let endpoint = api::Endpoint::Path { path: "", suite: None, changeset: None };
+    let resp_str = api::get(client, &endpoint)?;
+    let data = parse::context(PathCoverage::from_slice(resp_str.as_bytes()), &api::origin(&endpoint), &resp_str)?;
+    Ok(data.changeset.to_string())
 }
 
-#[derive(Debug, StructOpt)]
-#[structopt(name = "coverage", about = "Download and process wpt coverage data")]
-struct Opt {
-    #[structopt(long)]
-    changeset: Option<String>,
-    suite_1: String,
-    suite_2: String,
-    base_paths: String
+/// The part of the hg json-rev response that's used
+#[derive(Debug, Deserialize)]
+struct HgRevision {
+    node: String,
 }
 
+/// Whether a request failed because the server rejected it, e.g. with a 404
+fn is_client_error(error: &Error) -> bool {
+    match error {
+        Error::Reqwest(e) => e.status().map(|x| x.is_client_error()).unwrap_or(false),
+        _ => false
+    }
+}
 
-fn run() -> Result<()> {
-    let client = reqwest::Client::new();
+/// Turn an abbreviated changeset hash, or a name like tip, into the full
+/// hash that the coverage API and the cache use
+fn resolve_changeset(client: &reqwest::Client, changeset: &str) -> Result<String> {
+    let is_hash = changeset.chars().all(|x| x.is_ascii_hexdigit());
+    if is_hash && changeset.len() == 40 {
+        return Ok(changeset.to_lowercase());
+    }
+    // Data cached by earlier versions may be under an abbreviated hash
+    if is_hash && cache::changeset_root(changeset).exists() {
+        return Ok(changeset.to_owned());
+    }
+    let url = format!("{}/json-rev/{}", source::HG_URL, changeset);
+    let resp_str = match get(client, &url, None) {
+        Ok(resp_str) => resp_str,
+        Err(ref e) if is_client_error(e) => {
+            return Err(Error::Usage(format!("Unknown or ambiguous changeset {}", changeset)))
+        }
+        Err(e) => return Err(e)
+    };
+    let revision: HgRevision = parse::from_str(&url, &resp_str)?;
+    log::info(format_args!("Resolved changeset {} to {}", changeset, revision.node));
+    if !has_coverage(client, &revision.node)? {
+        return Err(Error::Data(format!("No coverage data for changeset {} (resolved from {})",
+                                       revision.node,
+                                       changeset)));
+    }
+    Ok(revision.node)
+}
 
-    let opt = Opt::from_args();
-    let changeset = opt.changeset
-        .map(|x| Ok(x))
-        .unwrap_or_else(|| get_latest_changeset(&client))?;
+/// How far back from --date to look in the pushlog for a changeset with
+/// coverage data, when it's older than anything the API lists as recent
+const DATE_SEARCH_DAYS: i64 = 14;
 
-    let base_path = PathBuf::from(format!("data/{}", changeset));
+/// The part of the hg json-pushes response that's used
+#[derive(Debug, Deserialize)]
+struct Pushlog {
+    pushes: BTreeMap<String, Push>,
+}
 
-    let gecko_base_paths = opt.base_paths.split(',').map(|x| x.trim()).collect::<Vec<&str>>();
+#[derive(Debug, Deserialize)]
+struct Push {
+    changesets: Vec<String>,
+    date: i64,
+}
 
-    let suite_1_data = get_suite_data(&client, &changeset, &base_path, &opt.suite_1, &gecko_base_paths)?;
-    let suite_2_data = get_suite_data(&client, &changeset, &base_path, &opt.suite_2, &gecko_base_paths)?;
+/// Look up when a changeset was pushed to mozilla-central. This is only used
+/// for information, so failures are logged rather than returned.
+fn push_date(client: &reqwest::Client, changeset: &str) -> Option<DateTime<Utc>> {
+    let url = format!("{}/json-pushes?version=2&changeset={}", source::HG_URL, changeset);
+    let pushlog = get(client, &url, None)
+        .and_then(|resp_str| parse::from_str::<Pushlog>(&url, &resp_str));
+    match pushlog {
+        Ok(pushlog) => pushlog.pushes
+            .values()
+            .map(|x| x.date)
+            .min()
+            .and_then(|x| Utc.timestamp_opt(x, 0).single()),
+        Err(e) => {
+            log::info(format_args!("Couldn't find the push date of {}: {:?}", changeset, e));
+            None
+        }
+    }
+}
 
-    let differences = get_differences(suite_1_data, suite_2_data);
+/// Warn if the changeset was pushed more than `stale_days` ago
+fn check_stale(metadata: &report::RunMetadata, stale_days: i64) {
+    if let Some(push_date) = metadata.push_date {
+        let age_days = (metadata.date - push_date).num_days();
+        if stale_days > 0 && age_days > stale_days {
+            report::record_warning(report::Warning::StaleChangeset {
+                changeset: metadata.changeset.clone(),
+                push_date,
+                age_days,
+            });
+        }
+    }
+}
 
-    println!("path, {} only, {} only, both, total covered, total coverable, total lines, {}-only percent, {}-only percent, coverage percent",
-             &opt.suite_1, &opt.suite_2, &opt.suite_1, &opt.suite_2);
+/// Whether the coverage API has data for a full changeset hash
+fn has_coverage(client: &reqwest::Client, changeset: &str) -> Result<bool> {
+    if cache::changeset_root(changeset).exists() {
+        return Ok(true);
+    }
+    match api::get(client, &api::Endpoint::Path { path: "", suite: None, changeset: Some(changeset) }) {
+        Ok(_) => Ok(true),
+        Err(ref e) if is_client_error(e) => Ok(false),
+        Err(e) => Err(e)
+    }
+}
 
-    for (path, coverage_difference) in differences.iter() {
+/// Find the newest changeset pushed on or before `date` that has coverage data
+fn changeset_for_date(client: &reqwest::Client, date: NaiveDate) -> Result<String> {
+    let end = Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()) + Duration::days(1);
+    let recent = get_recent_changesets(client)?;
+    if let Some(info) = recent.iter().find(|x| x.date.map(|date| date < end).unwrap_or(false)) {
+        log::info(format_args!("Using changeset {} for {}", info.changeset, date));
+        return Ok(info.changeset.clone());
+    }
 
-        let percent = |count: i64| {
-            100f64 * count as f64 / coverage_difference.coverable_count as f64
-        };
+    // Older than the recent changesets, so try the pushes before the date in turn
+    let start = end - Duration::days(DATE_SEARCH_DAYS);
+    let url = format!("{}/json-pushes?version=2&startdate={}&enddate={}",
+                      source::HG_URL,
+                      start.format("%Y-%m-%d"),
+                      end.format("%Y-%m-%d"));
+    let resp_str = get(client, &url, None)?;
+    let pushlog: Pushlog = parse::from_str(&url, &resp_str)?;
+    let mut pushes = pushlog.pushes
+        .into_values()
+        .filter(|x| x.date < end.timestamp())
+        .collect::<Vec<_>>();
+    pushes.sort_by_key(|x| cmp::Reverse(x.date));
+    for push in pushes {
+        if let Some(head) = push.changesets.last() {
+            if has_coverage(client, head)? {
+                log::info(format_args!("Using changeset {} for {}", head, date));
+                return Ok(head.clone());
+            }
+        }
+    }
+    Err(Error::Data(format!("No changeset with coverage data found in the {} days up to {}",
+                            DATE_SEARCH_DAYS,
+                            date)))
+}
 
-        println!("\"{}\", {}, {}, {}, {}, {}, {}, {}, {}, {}",
-                 path,
-                 coverage_difference.suite_1_only_count,
-                 coverage_difference.suite_2_only_count,
-                 coverage_difference.both_count,
-                 coverage_difference.covered_count,
-                 coverage_difference.coverable_count,
-                 coverage_difference.line_count,
-                 percent(coverage_difference.suite_1_only_count),
-                 percent(coverage_difference.suite_2_only_count),
-                 percent(coverage_difference.covered_count),
-        );
+/// The full hash of the changeset selected by --changeset or --date, or the
+/// latest changeset with coverage data
+fn select_changeset(client: &reqwest::Client, args: &DiffArgs) -> Result<String> {
+    match (args.changeset.as_ref(), args.date) {
+        (Some(changeset), _) => resolve_changeset(client, changeset),
+        (None, Some(date)) => changeset_for_date(client, date),
+        (None, None) => get_latest_changeset(client)
     }
+}
 
-    Ok(())
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum FilterEntry {
+    Name(String),
+    Object { name: String },
 }
 
-fn main() {
-    if let Err(e) =  run() {
-        eprintln!("ERROR: Failed:\n{:?}", e);
-        process::exit(1);
-    };
+#[derive(Debug, Deserialize)]
+struct Filters {
+    suites: Vec<FilterEntry>,
+}
+
+/// A changeset that the API has coverage data for
+#[derive(Debug, Clone, Deserialize)]
+struct ChangesetInfo {
+    #[serde(alias = "revision")]
+    changeset: String,
+    push: Option<i64>,
+    #[serde(default, deserialize_with = "deserialize_date")]
+    date: Option<DateTime<Utc>>,
+}
+
+/// The API reports dates either as a unix timestamp or as an ISO 8601 string
+fn deserialize_date<'de, D>(deserializer: D) -> std::result::Result<Option<DateTime<Utc>>, D::Error>
+where D: serde::Deserializer<'de> {
+    let value = serde_json::Value::deserialize(deserializer)?;
+    Ok(match value {
+        serde_json::Value::Number(n) => n.as_i64().and_then(|x| Utc.timestamp_opt(x, 0).single()),
+        serde_json::Value::String(s) => DateTime::parse_from_rfc3339(&s).ok().map(|x| x.with_timezone(&Utc)),
+        _ => None
+    })
+}
+
+/// Get the changesets that the API lists as having recent coverage data,
+/// newest first
+fn get_recent_changesets(client: &reqwest::Client) -> Result<Vec<ChangesetInfo>> {
+    let resp_str = api::get(client, &api::Endpoint::Latest)?;
+    let mut data: Vec<ChangesetInfo> = parse::context(compat::from_str(&resp_str),
+                                                      &api::origin(&api::Endpoint::Latest),
+                                                      &resp_str)?;
+    data.sort_by_key(|x| cmp::Reverse(x.push));
+    Ok(data)
+}
+
+/// Number of pushes fetched from the pushlog at a time when looking for
+/// older changesets with coverage data
+const PUSHLOG_PAGE: i64 = 100;
 
+/// Pushes to look through before giving up on finding more changesets
+const PUSHLOG_MAX_PUSHES: i64 = 1000;
+
+/// Get the `limit` most recent changesets with coverage data, newest first.
+/// The API only lists a few recent changesets, so when more are wanted the
+/// pushes before the oldest of those are checked for coverage data in turn.
+fn get_changesets(client: &reqwest::Client, limit: usize) -> Result<Vec<ChangesetInfo>> {
+    let mut data = get_recent_changesets(client)?;
+    if data.len() >= limit {
+        data.truncate(limit);
+        return Ok(data);
+    }
+    let mut end_id = match data.last().and_then(|x| x.push) {
+        Some(push) => push - 1,
+        None => return Ok(data)
+    };
+    let stop_id = end_id - PUSHLOG_MAX_PUSHES;
+    while data.len() < limit && end_id > 0 && end_id > stop_id {
+        let start_id = cmp::max(end_id - PUSHLOG_PAGE, 0);
+        let url = format!("{}/json-pushes?version=2&startID={}&endID={}", source::HG_URL, start_id, end_id);
+        let resp_str = get(client, &url, None)?;
+        let pushlog: Pushlog = parse::from_str(&url, &resp_str)?;
+        let mut pushes = pushlog.pushes
+            .into_iter()
+            .filter_map(|(id, push)| id.parse::<i64>().ok().map(|id| (id, push)))
+            .collect::<Vec<_>>();
+        pushes.sort_by_key(|(id, _)| cmp::Reverse(*id));
+        for (id, push) in pushes {
+            if data.len() >= limit {
+                break;
+            }
+            if let Some(head) = push.changesets.last() {
+                if has_coverage(client, head)? {
+                    data.push(ChangesetInfo {
+                        changeset: head.clone(),
+                        push: Some(id),
+                        date: Utc.timestamp_opt(push.date, 0).single(),
+                    });
+                }
+            }
+        }
+        end_id = start_id;
+    }
+    if data.len() < limit {
+        log::info(format_args!("Only found {} changesets with coverage data", data.len()));
+    }
+    Ok(data)
+}
+
+/// Get the names of the suites that the API has coverage data for
+fn get_suites(client: &reqwest::Client) -> Result<Vec<String>> {
+    let resp_str = api::get(client, &api::Endpoint::Filters)?;
+    let data: Filters = parse::context(compat::from_str(&resp_str), &api::origin(&api::Endpoint::Filters), &resp_str)?;
+    let mut rv = data.suites
+        .into_iter()
+        .map(|x| match x {
+            FilterEntry::Name(name) => name,
+            FilterEntry::Object { name } => name
+        })
+        .collect::<Vec<_>>();
+    rv.sort();
+    Ok(rv)
+}
+
+/// Check that suites that will be fetched from the API actually exist, so
+/// that a typo doesn't fail with an opaque error part way through traversal.
+fn validate_suites(client: &reqwest::Client, root_path: &Path, sources: &[&SuiteSource]) -> Result<()> {
+    let uncached = sources
+        .iter()
+        .filter_map(|x| match x {
+            SuiteSource::Api(name) => Some(name),
+            _ => None
+        })
+        .filter(|name| !root_path.join(cache::component(name)).exists())
+        .collect::<Vec<_>>();
+    if uncached.is_empty() {
+        return Ok(());
+    }
+    let suites = get_suites(client)?;
+    for name in uncached {
+        if !suites.contains(name) {
+            return Err(Error::Usage(format!("Unknown suite {}; available suites are: {}",
+                                            name,
+                                            suites.join(", "))));
+        }
+    }
+    Ok(())
+}
+
+/// Use the top-level directories in the coverage data as base paths
+fn auto_base_paths(client: &reqwest::Client, changeset: &str, exclude: &[&str]) -> Result<Vec<String>> {
+    let endpoint = api::Endpoint::Path { path: "", suite: None, changeset: Some(changeset) };
+    let resp_str = api::get(client, &endpoint)?;
+    let data = parse::context(PathCoverage::from_slice(resp_str.as_bytes()), &api::origin(&endpoint), &resp_str)?;
+    let rv = data.children
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|x| &*x.path_type == "directory")
+        .filter(|x| !exclude.contains(&&*x.path))
+        .map(|x| x.path.to_string())
+        .collect::<Vec<_>>();
+    if rv.is_empty() {
+        return Err(Error::Data(format!("No top-level directories found for changeset {}", changeset)));
+    }
+    Ok(rv)
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Compare two reports previously generated with --format json
+    #[structopt(name = "compare-reports")]
+    CompareReports {
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
+        /// Only list files whose unique coverage changed by more than this many lines
+        #[structopt(long, default_value = "0")]
+        threshold: i64,
+    },
+    /// List the suites that have coverage data
+    #[structopt(name = "list-suites")]
+    ListSuites,
+    /// List recent changesets that have coverage data
+    #[structopt(name = "list-changesets")]
+    ListChangesets {
+        /// Number of changesets to list, newest first
+        #[structopt(long = "latest", alias = "limit", default_value = "10")]
+        latest: usize,
+        /// Only print the changeset hashes, one per line
+        #[structopt(long = "hashes-only")]
+        hashes_only: bool,
+    },
+    /// Generate a static HTML site with pages for each directory and file
+    #[structopt(name = "site")]
+    Site {
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// Directory to write the site to
+        #[structopt(long = "out-dir", parse(from_os_str))]
+        out_dir: PathBuf,
+    },
+    /// Show the source of files with each line marked by its coverage category
+    #[structopt(name = "annotate")]
+    Annotate {
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// Write an HTML page rather than terminal output
+        #[structopt(long)]
+        html: bool,
+    },
+    /// Write an LCOV file in which only lines in one coverage category are
+    /// covered, for viewing with editor coverage plugins
+    #[structopt(name = "lcov")]
+    Lcov {
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// Category of lines to mark as covered: suite1-only, suite2-only, both or not-covered
+        #[structopt(long, default_value = "suite1-only")]
+        category: CoverageType,
+    },
+    /// Write a Cobertura XML report in which only lines in one coverage
+    /// category are covered, or containing the data for a single suite
+    #[structopt(name = "cobertura")]
+    Cobertura {
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// Category of lines to mark as covered: suite1-only, suite2-only, both or not-covered
+        #[structopt(long, default_value = "suite1-only")]
+        category: CoverageType,
+        /// Write the hit counts from suite 1 or 2 rather than a coverage category
+        #[structopt(long)]
+        suite: Option<u8>,
+    },
+    /// Render a chart of the comparison to a PNG or SVG file
+    #[structopt(name = "chart")]
+    Chart {
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// Chart to draw: directories, a bar chart of the --top directories (default 20)
+        /// with the most unique coverage, or histogram, of per-file suite 2 only percentages
+        #[structopt(long, default_value = "directories")]
+        chart: chart::ChartKind,
+        /// Width of the image in pixels
+        #[structopt(long, default_value = "1024")]
+        width: u32,
+        /// Height of the image in pixels
+        #[structopt(long, default_value = "768")]
+        height: u32,
+    },
+    /// Write the hit counts in both suites and the coverage category of every
+    /// line in the selected files, as long-format csv or parquet
+    #[structopt(name = "lines-matrix")]
+    LinesMatrix {
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// Comma-separated files or directories to include; defaults to all files
+        #[structopt(long, raw(use_delimiter = "true"))]
+        paths: Vec<String>,
+    },
+    /// Find who last changed the lines in a coverage category, and in which bugs, from the checkout
+    /// at --srcdir or from hg.mozilla.org, and list the authors and bugs with the most lines. Use
+    /// --top to change how many are listed
+    #[structopt(name = "blame")]
+    Blame {
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// Category of lines to blame: suite1-only, suite2-only, both or not-covered
+        #[structopt(long, default_value = "suite2-only")]
+        category: CoverageType,
+        /// Root of a git or mercurial mozilla-central checkout at the compared changeset to blame
+        /// files in, instead of using hg.mozilla.org
+        #[structopt(long, parse(from_os_str))]
+        srcdir: Option<PathBuf>,
+    },
+    /// Split the lines in each coverage category by how long ago they were last changed, from the
+    /// blame of the checkout at --srcdir or of hg.mozilla.org, to show whether gaps in coverage are
+    /// in new or older code. Ages are measured back from when the changeset was pushed
+    #[structopt(name = "line-age")]
+    LineAge {
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// Comma-separated categories of lines to split: suite1-only, suite2-only, both or not-covered
+        #[structopt(long, default_value = "suite1-only,suite2-only,not-covered", raw(use_delimiter = "true"))]
+        categories: Vec<CoverageType>,
+        /// Comma-separated ages in months to split the lines at
+        #[structopt(long = "age-months", default_value = "6", raw(use_delimiter = "true"))]
+        age_months: Vec<u32>,
+        /// Root of a git or mercurial mozilla-central checkout at the compared changeset to blame
+        /// files in, instead of using hg.mozilla.org
+        #[structopt(long, parse(from_os_str))]
+        srcdir: Option<PathBuf>,
+    },
+    /// Write an SVG badge showing one metric from the comparison totals
+    #[structopt(name = "badge")]
+    Badge {
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// Metric to show, e.g. suite_2_only_percent, covered_percent or suite_2_only
+        #[structopt(long, default_value = "suite_2_only_percent")]
+        metric: String,
+        /// Text for the left side of the badge; defaults to a description of the metric
+        #[structopt(long)]
+        label: Option<String>,
+        /// Use a report previously written with --format json instead of running a comparison
+        #[structopt(long, parse(from_os_str))]
+        report: Option<PathBuf>,
+    },
+    /// Compare the latest changeset with the previous run, for use from cron.
+    /// Fails only when unique coverage moved by more than --threshold
+    #[structopt(name = "check")]
+    Check {
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// JSON report from the previous run, which is replaced by the current run
+        #[structopt(long, parse(from_os_str))]
+        state: PathBuf,
+        /// Percentage points by which the totals or a top-level directory may move
+        #[structopt(long, default_value = "1")]
+        threshold: f64,
+        /// SQLite database that each new run is added to
+        #[structopt(long, parse(from_os_str))]
+        history: Option<PathBuf>,
+        #[structopt(flatten)]
+        notify: NotifyArgs,
+    },
+    /// Poll for new changesets and check each one against the previous run
+    #[structopt(name = "watch")]
+    Watch {
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// JSON report from the previous run, which is replaced by each new run
+        #[structopt(long, parse(from_os_str))]
+        state: PathBuf,
+        /// Percentage points by which the totals or a top-level directory may move
+        #[structopt(long, default_value = "1")]
+        threshold: f64,
+        /// SQLite database that each new run is added to
+        #[structopt(long, parse(from_os_str))]
+        history: Option<PathBuf>,
+        #[structopt(flatten)]
+        notify: NotifyArgs,
+        /// Time between checks, e.g. 30m or 6h
+        #[structopt(long, default_value = "6h", parse(try_from_str = "check::parse_duration"))]
+        interval: std::time::Duration,
+        /// Check each of this many recent changesets that are newer than the stored run,
+        /// oldest first, rather than only the latest one
+        #[structopt(long, default_value = "1")]
+        latest: usize,
+    },
+    /// Serve the HTML report, with annotated source for each file, and JSON
+    /// endpoints for the summary, files and lines over HTTP
+    #[structopt(name = "serve")]
+    Serve {
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// Address to listen on; use 0.0.0.0 to allow connections from other machines
+        #[structopt(long, default_value = "127.0.0.1")]
+        host: String,
+        /// Port to listen on
+        #[structopt(short = "p", long, default_value = "8080")]
+        port: u16,
+    },
+    /// List the numbers of the lines in each coverage category for one file
+    #[structopt(name = "lines")]
+    Lines {
+        /// File to list lines for; also used as the base path if none is given
+        path: String,
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// Only list lines in this category: suite1-only, suite2-only, both or not-covered
+        #[structopt(long)]
+        category: Option<CoverageType>,
+        /// Write runs of consecutive lines as START-END
+        #[structopt(long)]
+        ranges: bool,
+    },
+    /// Show the source of one file with each line marked by its coverage category
+    #[structopt(name = "explain")]
+    Explain {
+        /// File to show; also used as the base path if none is given
+        path: String,
+        #[structopt(flatten)]
+        diff: DiffArgs,
+    },
+    /// Show the hit counts in each suite and the coverage category of every line of one file
+    #[structopt(name = "side-by-side")]
+    SideBySide {
+        /// File to show; also used as the base path if none is given
+        path: String,
+        #[structopt(flatten)]
+        diff: DiffArgs,
+    },
+    /// Open one file from a local checkout in $VISUAL or $EDITOR, at its first
+    /// line that's only covered by suite 2
+    #[structopt(name = "open")]
+    Open {
+        /// File to open; also used as the base path if none is given
+        path: String,
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// Root of a mozilla-central checkout
+        #[structopt(long, parse(from_os_str))]
+        srcdir: PathBuf,
+    },
+    /// Write a shell completion script: bash, zsh, fish, powershell or elvish
+    #[structopt(name = "completions")]
+    Completions {
+        #[structopt(raw(possible_values = "&structopt::clap::Shell::variants()", case_insensitive = "true"))]
+        shell: structopt::clap::Shell,
+    },
+    /// Write a roff man page generated from the command line definition
+    #[structopt(name = "man")]
+    Man,
+    /// List cached changesets or suites, for shell completion
+    #[structopt(name = "complete-values", raw(setting = "structopt::clap::AppSettings::Hidden"))]
+    CompleteValues {
+        #[structopt(raw(possible_values = r#"&["changesets", "suites"]"#))]
+        kind: String,
+    },
+    /// Browse the comparison interactively in the terminal
+    #[structopt(name = "tui")]
+    Tui {
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// Root of a mozilla-central checkout; e opens the selected file from it in $EDITOR
+        #[structopt(long, parse(from_os_str))]
+        srcdir: Option<PathBuf>,
+    },
+    /// Write the totals and top-level directory metrics for each of the most
+    /// recent changesets, oldest first, as csv or json
+    #[structopt(name = "history")]
+    History {
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// Number of recent changesets to include
+        #[structopt(long = "latest", alias = "count", default_value = "20")]
+        latest: usize,
+        /// Report directories whose suite 2 only percentage fell by more than
+        /// this many percentage points between consecutive changesets
+        #[structopt(long = "drop-threshold")]
+        drop_threshold: Option<f64>,
+    },
+    /// Manage the --baseline file
+    #[structopt(name = "baseline")]
+    Baseline(BaselineCommand),
+    /// Post a summary of the comparison somewhere
+    #[structopt(name = "publish")]
+    Publish(PublishCommand),
+    /// Write a SARIF log with a result for each file with code only covered
+    /// by suite 2, for display in code scanning tools
+    #[structopt(name = "sarif")]
+    Sarif {
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// Only report files with at least this many lines only covered by suite 2
+        #[structopt(long = "min-lines", default_value = "10")]
+        min_lines: i64,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum BaselineCommand {
+    /// Rewrite a baseline file from the current run
+    #[structopt(name = "update")]
+    Update {
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// Baseline file to write
+        #[structopt(long, parse(from_os_str))]
+        baseline: PathBuf,
+        /// Only update directories whose unique coverage improved, so the baseline
+        /// can only get stricter
+        #[structopt(long)]
+        ratchet: bool,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum PublishCommand {
+    /// Post the review-comment summary as a comment on a GitHub pull request or
+    /// issue, updating the comment from an earlier run if there is one
+    #[structopt(name = "github")]
+    Github {
+        #[structopt(flatten)]
+        diff: DiffArgs,
+        /// Repository as OWNER/NAME
+        #[structopt(long)]
+        repo: String,
+        /// Pull request or issue number
+        #[structopt(long)]
+        pr: u64,
+        /// Token used to authenticate with GitHub
+        #[structopt(long, env = "GITHUB_TOKEN", hide_env_values = true)]
+        token: String,
+        /// Base URL of the GitHub API
+        #[structopt(long = "api-url", default_value = "https://api.github.com")]
+        api_url: String,
+    },
+}
+
+/// Where to send notifications when metrics move by more than the threshold
+#[derive(Debug, StructOpt)]
+struct NotifyArgs {
+    /// URL to POST a JSON summary of the moved metrics to; may be repeated
+    #[structopt(long = "webhook", number_of_values = 1)]
+    webhooks: Vec<String>,
+    /// Slack incoming webhook URL to post a summary of the moved metrics to;
+    /// may be repeated
+    #[structopt(long = "slack", number_of_values = 1)]
+    slack: Vec<String>,
+    /// Number of moved directories to list in Slack messages
+    #[structopt(long = "slack-top", default_value = "5")]
+    slack_top: usize,
+    /// Link to the generated report to include in notifications instead of the
+    /// report itself; {changeset} is replaced with the checked changeset
+    #[structopt(long = "report-url")]
+    report_url: Option<String>,
+}
+
+/// Arguments selecting the data to compare
+#[derive(Debug, Clone, StructOpt)]
+struct DiffArgs {
+    /// Changeset to compare, as a full or abbreviated hash or tip; defaults to the latest with coverage data
+    #[structopt(long)]
+    changeset: Option<String>,
+    /// Compare the newest changeset with coverage data pushed on or before this date (YYYY-MM-DD)
+    #[structopt(long, conflicts_with = "changeset")]
+    date: Option<NaiveDate>,
+    /// Suite name, merge:FILE[,FILE...] to combine local covdir/LCOV/Cobertura files,
+    /// taskcluster:SUITE[@TASK_GROUP] to use raw grcov artifacts, or coveralls:BUILD
+    suite_1: Option<String>,
+    /// Suite name, merge:FILE[,FILE...] to combine local covdir/LCOV/Cobertura files,
+    /// taskcluster:SUITE[@TASK_GROUP] to use raw grcov artifacts, or coveralls:BUILD
+    suite_2: Option<String>,
+    /// Comma-separated list of gecko paths to start from, or auto to use all top-level directories
+    base_paths: Option<String>,
+    /// Comma-separated top-level directories to skip when using auto base paths
+    #[structopt(long = "auto-exclude", default_value = "testing,third_party")]
+    auto_exclude: String,
+    /// File containing gecko paths to start from, one per line, or - for stdin
+    #[structopt(long = "base-paths-file", parse(from_os_str), conflicts_with = "base_paths")]
+    base_paths_file: Option<PathBuf>,
+    /// wptreport.json file used to count the wpt tests touching each directory
+    #[structopt(long, parse(from_os_str))]
+    wptreport: Option<PathBuf>,
+    /// Warn when the changeset was pushed more than this many days ago; 0 never warns
+    #[structopt(long = "stale-days", default_value = "7")]
+    stale_days: i64,
+    /// Fetch entries again when the cached copy is missing children or coverage, or can't be read
+    #[structopt(long = "requery-incomplete")]
+    requery_incomplete: bool,
+    /// Fill in entries that are missing or incomplete for the changeset, e.g. while its data is still
+    /// being ingested, from up to N earlier changesets. Files that use them are listed in the warnings
+    #[structopt(long = "fallback-changesets", default_value = "0")]
+    fallback_changesets: usize,
+    /// Comma-separated files or directories to restrict the comparison to; only the directories
+    /// leading to them are fetched
+    #[structopt(long, raw(use_delimiter = "true"))]
+    include: Vec<String>,
+    /// Comma-separated files or directories to leave out of the comparison; directories aren't fetched
+    #[structopt(long, raw(use_delimiter = "true"))]
+    exclude: Vec<String>,
+    /// Don't fetch API entries more than N levels deep, where top-level directories are level 1. Files
+    /// below that aren't compared
+    #[structopt(long = "max-depth")]
+    max_depth: Option<usize>,
+    /// Comma-separated directories to fetch the API entries of without fetching anything inside them
+    #[structopt(long, raw(use_delimiter = "true"))]
+    prune: Vec<String>,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "coverage", about = "Download and process wpt coverage data", raw(after_help = "EXIT_CODES_HELP"))]
+struct Opt {
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+    #[structopt(flatten)]
+    diff: DiffArgs,
+    /// Output format: auto, table, csv, json, yaml, ndjson, markdown, html, treemap, vega,
+    /// openmetrics, influx, parquet, arrow (an Arrow IPC stream), gha, review-comment, quickfix or sqlite. auto uses a
+    /// table when writing to a terminal and csv otherwise. sqlite requires -o, and adds a run to the database if it already exists.
+    /// gha writes GitHub Actions annotations for the --top files (default 10) with the most suite 2 only
+    /// lines, review-comment writes totals and the --top files (default 10) for a review comment,
+    /// quickfix writes path:line: entries for the lines in --category and vega writes Vega-Lite charts
+    /// of the --top directories (default 20) and of per-file percentages
+    #[structopt(long, default_value = "auto")]
+    format: output::Format,
+    /// File to write the output to, instead of stdout
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    output: Option<PathBuf>,
+    /// Directory to write additional copies of the output to, in each of the --artifacts formats
+    #[structopt(long = "out-dir", parse(from_os_str))]
+    out_dir: Option<PathBuf>,
+    /// Comma-separated formats written to --out-dir
+    #[structopt(long, default_value = "csv,json,html", raw(use_delimiter = "true"))]
+    artifacts: Vec<output::Format>,
+    /// Compress output files with gzip or zstd. Files in --out-dir get a .gz or .zst suffix;
+    /// sqlite databases are never compressed
+    #[structopt(long, default_value = "none")]
+    compress: output::Compression,
+    /// Don't write a header row in tabular output
+    #[structopt(long = "no-header")]
+    no_header: bool,
+    /// Field separator for CSV output: a single character, or tab, comma, semicolon or pipe
+    #[structopt(long, default_value = "comma", parse(try_from_str = "output::parse_delimiter"))]
+    delimiter: u8,
+    /// Only output the N files with the most suite 2 only lines, in summary formats
+    #[structopt(long)]
+    top: Option<usize>,
+    /// Unique coverage percentages at which table output turns yellow and green.
+    /// Defaults to the config file's color_thresholds, or 5,20
+    #[structopt(long = "color-thresholds", parse(try_from_str = "output::parse_color_thresholds"))]
+    color_thresholds: Option<output::ColorThresholds>,
+    /// Use colors in terminal output: auto, always or never. auto uses colors when
+    /// writing to a terminal unless NO_COLOR is set
+    #[structopt(long, default_value = "auto")]
+    color: output::ColorChoice,
+    /// YAML config file; defaults to wptcoverage.yml in the current directory, if it exists
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// Embed a treemap of coverable lines by directory in HTML output
+    #[structopt(long)]
+    treemap: bool,
+    /// JSON report from an earlier run; review-comment output lists the files
+    /// that regressed relative to it
+    #[structopt(long = "base-report", parse(from_os_str))]
+    base_report: Option<PathBuf>,
+    /// JSON report from an earlier run; files whose coverage is unchanged since
+    /// then reuse its results instead of being compared again
+    #[structopt(long, parse(from_os_str))]
+    previous: Option<PathBuf>,
+    /// Add a column of searchfox links, pinned to the changeset, to csv, markdown and html output
+    #[structopt(long)]
+    searchfox: bool,
+    /// Fail with a nonzero exit code if a condition on the totals holds,
+    /// e.g. "total.suite_2_only_percent > 10". May be given more than once
+    #[structopt(long = "fail-if", number_of_values = 1)]
+    fail_if: Vec<gate::FailIf>,
+    /// Baseline file of expected per-directory unique coverage. The run fails if any directory
+    /// regresses by more than --baseline-tolerance; the file is created if it doesn't exist
+    #[structopt(long, parse(from_os_str))]
+    baseline: Option<PathBuf>,
+    /// Percentage points by which a directory's suite 2 only coverage may grow before it
+    /// counts as a regression from the baseline
+    #[structopt(long = "baseline-tolerance", default_value = "0.5")]
+    baseline_tolerance: f64,
+    /// File of accepted regressions; each line has a directory pattern, where * matches within
+    /// a path component and ** matches across them, and an expiry date in YYYY-MM-DD format
+    #[structopt(long, parse(from_os_str))]
+    allowlist: Option<PathBuf>,
+    /// Tera template to render the report with, instead of using --format
+    #[structopt(long, parse(from_os_str))]
+    template: Option<PathBuf>,
+    /// Category of lines listed in quickfix output: suite1-only, suite2-only, both or not-covered
+    #[structopt(long, default_value = "suite2-only")]
+    category: CoverageType,
+    /// Only log errors; also disables progress reporting
+    #[structopt(short = "q", long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Log what's being loaded and written; repeat (-vv) to also log every request
+    #[structopt(short = "v", long, parse(from_occurrences))]
+    verbose: u64,
+    /// Progress reporting: bar, log for periodic log lines, json for
+    /// newline-delimited events on stderr, none, or auto to use a bar when
+    /// stderr is a terminal and log lines otherwise
+    #[structopt(long, default_value = "auto")]
+    progress: progress::ProgressMode,
+    /// Version of the coverage API to use: auto, v2 or v3. auto uses v3 when the server supports it
+    #[structopt(long = "api-version", default_value = "auto", env = "WPTCOVERAGE_API_VERSION")]
+    api_version: api::ApiVersion,
+    /// Open the generated HTML report, site or server in a browser
+    #[structopt(long)]
+    open: bool,
+    /// Only write the totals instead of the per-file output, e.g. to keep CI
+    /// logs short while --out-dir gets the full results
+    #[structopt(long = "summary-only", conflicts_with = "template")]
+    summary_only: bool,
+    /// Run against the offline fixture dataset in DIR instead of the network, generating it first if
+    /// DIR doesn't exist. When no suites are given the two in the dataset are compared
+    #[structopt(long, parse(from_os_str))]
+    fixtures: Option<PathBuf>,
+    /// Print how long was spent fetching, reading the cache, parsing, diffing and writing output, and how
+    /// many requests were made, to stderr at the end of the run
+    #[structopt(long)]
+    timings: bool,
+}
+
+impl Opt {
+    fn output_options(&self) -> output::OutputOptions {
+        output::OutputOptions {
+            header: !self.no_header,
+            delimiter: self.delimiter,
+            top: self.top,
+            terminal: self.output.is_none() && io::stdout().is_terminal(),
+            color: self.color.enabled(self.output.is_none() && io::stdout().is_terminal()),
+            color_thresholds: self.color_thresholds.unwrap_or_default(),
+            treemap: self.treemap,
+            searchfox: self.searchfox,
+            hyperlinks: self.output.is_none() && io::stdout().is_terminal(),
+            file_links: None,
+        }
+    }
+}
+
+fn parse_base_paths_list<R: BufRead>(reader: R) -> Result<Vec<String>> {
+    let mut rv = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let path = match line.find('#') {
+            Some(idx) => &line[..idx],
+            None => &line
+        }.trim();
+        if !path.is_empty() {
+            rv.push(path.to_owned());
+        }
+    }
+    Ok(rv)
+}
+
+fn read_base_paths(opt: &DiffArgs) -> Result<Vec<String>> {
+    if let Some(ref base_paths_file) = opt.base_paths_file {
+        let paths = if base_paths_file == Path::new("-") {
+            let stdin = io::stdin();
+            let handle = stdin.lock();
+            parse_base_paths_list(handle)?
+        } else {
+            parse_base_paths_list(BufReader::new(File::open(base_paths_file)?))?
+        };
+        if paths.is_empty() {
+            return Err(Error::Data(format!("No base paths found in {}", base_paths_file.display())));
+        }
+        Ok(paths)
+    } else if let Some(ref base_paths) = opt.base_paths {
+        Ok(base_paths.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()).map(|x| x.to_owned()).collect())
+    } else {
+        Err(Error::Usage("No base paths supplied".into()))
+    }
+}
+
+/// Spell a gecko path the way the API does: relative to the root, with /
+/// separators, no empty or . components and any .. components resolved.
+/// Responses and local files occasionally use other spellings, which would
+/// otherwise end up as separate files.
+fn normalize_path(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part)
+        }
+    }
+    parts.join("/")
+}
+
+/// Put base paths in normalized form and drop any path that repeats another or is
+/// inside another, since its files would otherwise be fetched twice. The
+/// paths that are kept stay in the order they were given.
+fn normalize_base_paths(paths: &[String]) -> Vec<String> {
+    let mut rv: Vec<String> = Vec::new();
+    let normalized = paths
+        .iter()
+        .map(|path| normalize_path(path))
+        .collect::<Vec<_>>();
+    for (idx, path) in normalized.iter().enumerate() {
+        let contains = |other: &String| {
+            other.is_empty() || path == other || path.starts_with(&format!("{}/", other))
+        };
+        if rv.contains(path) {
+            log::warning(format_args!("Ignoring duplicate base path {}", paths[idx]));
+        } else if let Some(parent) = normalized.iter().filter(|x| *x != path).find(|x| contains(x)) {
+            log::warning(format_args!("Ignoring base path {}, which is inside base path {}",
+                                      paths[idx],
+                                      if parent.is_empty() { "/" } else { parent }));
+        } else {
+            rv.push(path.clone());
+        }
+    }
+    rv
+}
+
+/// The data for both suites, ready to be compared
+struct Comparison {
+    metadata: report::RunMetadata,
+    suite_1_data: CoverageMap,
+    suite_2_data: CoverageMap,
+    wpt_report: Option<wptreport::WptReport>,
+}
+
+/// Ask for the suites to compare, and the base paths if those are also
+/// missing, choosing from the suites the API has data for
+fn prompt_missing_args(client: &reqwest::Client, args: &DiffArgs) -> Result<DiffArgs> {
+    let mut args = args.clone();
+    let (suite_1, suite_2) = prompt::select_suites(&get_suites(client)?)?;
+    args.suite_1 = Some(suite_1);
+    args.suite_2 = Some(suite_2);
+    if args.base_paths.is_none() && args.base_paths_file.is_none() {
+        args.base_paths = Some(prompt::input_base_paths()?);
+    }
+    Ok(args)
+}
+
+/// Everything needed to load the two suites, resolved from the arguments
+struct ComparisonInputs {
+    metadata: report::RunMetadata,
+    wpt_report: Option<wptreport::WptReport>,
+    root_path: PathBuf,
+    suite_1_source: SuiteSource,
+    suite_2_source: SuiteSource,
+    filter: PathFilter,
+    options: FetchOptions,
+}
+
+impl ComparisonInputs {
+    fn visit_suite<F>(&self, client: &reqwest::Client, source: &SuiteSource, f: F) -> Result<()>
+    where F: FnMut(Arc<str>, PathCoverage) -> Result<()> {
+        let gecko_base_paths = self.metadata.base_paths.iter().map(|x| x.as_str()).collect::<Vec<&str>>();
+        visit_suite(client,
+                    &self.metadata.changeset,
+                    &self.root_path,
+                    source,
+                    &gecko_base_paths,
+                    &self.filter,
+                    self.options,
+                    f)
+    }
+
+    fn load_suite(&self, client: &reqwest::Client, source: &SuiteSource) -> Result<CoverageMap> {
+        let mut rv = BTreeMap::new();
+        self.visit_suite(client, source, |path, entry| {
+            rv.insert(path, entry);
+            Ok(())
+        })?;
+        Ok(rv)
+    }
+}
+
+fn comparison_inputs(client: &reqwest::Client, args: &DiffArgs) -> Result<ComparisonInputs> {
+    report::clear_warnings();
+    intern::clear();
+    let prompted;
+    let args = if args.suite_1.is_none() && args.suite_2.is_none() && api::fixtures() {
+        prompted = fixtures::default_args(args);
+        &prompted
+    } else if args.suite_1.is_none() && args.suite_2.is_none() && prompt::is_interactive() {
+        prompted = prompt_missing_args(client, args)?;
+        &prompted
+    } else {
+        args
+    };
+    progress::phase("fetch");
+    let (suite_1, suite_2) = match (args.suite_1.as_ref(), args.suite_2.as_ref()) {
+        (Some(suite_1), Some(suite_2)) => (suite_1, suite_2),
+        _ => return Err(Error::Usage("Two suites are required".into()))
+    };
+    let mut gecko_base_paths_owned = read_base_paths(args)?;
+
+    let changeset = select_changeset(client, args)?;
+
+    if gecko_base_paths_owned == ["auto"] {
+        let exclude = args.auto_exclude.split(',').map(|x| x.trim()).collect::<Vec<&str>>();
+        gecko_base_paths_owned = auto_base_paths(client, &changeset, &exclude)?;
+        log::info(format_args!("Using base paths {}", gecko_base_paths_owned.join(",")));
+    }
+    let gecko_base_paths_owned = normalize_base_paths(&gecko_base_paths_owned);
+
+    let root_path = cache::changeset_root(&changeset);
+
+    let suite_1_source = SuiteSource::parse(suite_1)?;
+    let suite_2_source = SuiteSource::parse(suite_2)?;
+    validate_suites(client, &root_path, &[&suite_1_source, &suite_2_source])?;
+
+    let wpt_report = match args.wptreport {
+        Some(ref path) => Some(wptreport::WptReport::load(path)?),
+        None => None
+    };
+
+    let metadata = report::RunMetadata {
+        push_date: push_date(client, &changeset),
+        changeset,
+        suite_1: suite_1.clone(),
+        suite_2: suite_2.clone(),
+        date: Utc::now(),
+        base_paths: gecko_base_paths_owned,
+        filters: PathFilter::new(args),
+    };
+    check_stale(&metadata, args.stale_days);
+
+    Ok(ComparisonInputs {
+        metadata,
+        wpt_report,
+        root_path,
+        suite_1_source,
+        suite_2_source,
+        filter: PathFilter::new(args),
+        options: FetchOptions {
+            requery: args.requery_incomplete,
+            fallback_changesets: args.fallback_changesets,
+        },
+    })
+}
+
+/// Wait for a suite being loaded on another thread
+fn join_suite<T>(handle: thread::ScopedJoinHandle<Result<T>>) -> Result<T> {
+    handle.join().unwrap_or_else(|e| std::panic::resume_unwind(e))
+}
+
+fn load_comparison(client: &reqwest::Client, args: &DiffArgs) -> Result<Comparison> {
+    let inputs = comparison_inputs(client, args)?;
+    // The suites are independent, so fetch them at the same time
+    let loaded = thread::scope(|scope| {
+        let suite_1 = scope.spawn(|| inputs.load_suite(client, &inputs.suite_1_source));
+        let suite_2_data = inputs.load_suite(client, &inputs.suite_2_source);
+        Ok::<_, Error>((join_suite(suite_1)?, suite_2_data?))
+    });
+    intern::clear();
+    let (suite_1_data, suite_2_data) = loaded?;
+    Ok(Comparison {
+        metadata: inputs.metadata,
+        suite_1_data,
+        suite_2_data,
+        wpt_report: inputs.wpt_report,
+    })
+}
+
+/// Compare one file of suite 2 with suite 1, or keep it for the end if it
+/// isn't in suite 1
+fn stream_difference<F>(suite_1_data: &mut CoverageMap,
+                        suite_2_only: &mut CoverageMap,
+                        path: Arc<str>,
+                        suite_2_coverage: PathCoverage,
+                        previous: Option<&report::PreviousReport>,
+                        keep_lines: bool,
+                        f: &mut F) -> Result<()>
+where F: FnMut(&str, CoverageDifference) -> Result<()> {
+    match suite_1_data.remove(&*path) {
+        Some(suite_1_coverage) => {
+            if let (Some(suite_1_coverage_vec), Some(suite_2_coverage_vec)) =
+                (suite_1_coverage.coverage, suite_2_coverage.coverage) {
+                let difference = file_difference(&path,
+                                                 &suite_1_coverage_vec,
+                                                 &suite_2_coverage_vec,
+                                                 previous,
+                                                 keep_lines);
+                warn_line_count_mismatch(&path, &difference);
+                f(&path, difference)?;
+            }
+        }
+        None => {
+            suite_2_only.insert(path, suite_2_coverage);
+        }
+    }
+    Ok(())
+}
+
+/// Compute the difference for each file while suite 2 is being loaded, and
+/// pass each result to `f`. Suite 1 is loaded at the same time, and suite 2
+/// is loaded on another thread that sends its files over a bounded channel,
+/// so once that's full, loading suite 2 waits until suite 1 is done and the
+/// files can be compared. Only suite 1, at most `STREAM_BUFFER` files of
+/// suite 2, and the files in suite 2 that aren't in suite 1 are held in
+/// memory; the last are compared at the end as in `for_each_difference`.
+fn stream_differences<F>(client: &reqwest::Client,
+                         inputs: &ComparisonInputs,
+                         previous: Option<&report::PreviousReport>,
+                         keep_lines: bool,
+                         mut f: F) -> Result<()>
+where F: FnMut(&str, CoverageDifference) -> Result<()> {
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::sync_channel(STREAM_BUFFER);
+        let suite_2 = scope.spawn(move || {
+            inputs.visit_suite(client, &inputs.suite_2_source, |path, suite_2_coverage| {
+                if &*suite_2_coverage.path_type == "directory" {
+                    return Ok(());
+                }
+                // Sending fails if the comparison stopped because of an error
+                sender
+                    .send((path, suite_2_coverage))
+                    .map_err(|_| Error::String("Comparison stopped before suite 2 was loaded".into()))
+            })
+        });
+        let mut suite_1_data = inputs.load_suite(client, &inputs.suite_1_source)?;
+        let mut suite_2_only = CoverageMap::new();
+        for (path, suite_2_coverage) in receiver {
+            stream_difference(&mut suite_1_data,
+                              &mut suite_2_only,
+                              path,
+                              suite_2_coverage,
+                              previous,
+                              keep_lines,
+                              &mut f)?;
+        }
+        join_suite(suite_2)?;
+        intern::clear();
+        for_each_difference(&suite_1_data, &suite_2_only, previous, keep_lines, f)
+    })
+}
+
+/// Files of suite 2 that can be loaded ahead of being compared
+const STREAM_BUFFER: usize = 1024;
+
+/// Whether any of the formats that will be written use the line level
+/// differences, rather than only the per-file counts in the report
+fn needs_line_differences(opt: &Opt) -> bool {
+    let uses_lines = |format: &output::Format| {
+        matches!(format, output::Format::Gha | output::Format::Quickfix)
+    };
+    let primary = opt.template.is_none() && !opt.summary_only && uses_lines(&opt.format);
+    let artifacts = opt.out_dir.is_some() && opt.artifacts.iter().any(uses_lines);
+    primary || artifacts
+}
+
+/// Write the comparison in a single format. Most formats only need the
+/// report, but some also use the line level differences.
+fn write_format<W: Write>(out: &mut W,
+                          format: output::Format,
+                          opt: &Opt,
+                          options: &output::OutputOptions,
+                          report: &report::Report,
+                          differences: &BTreeMap<String, CoverageDifference>,
+                          base_report: Option<&report::Report>) -> Result<()> {
+    match format {
+        output::Format::Gha => export::write_gha(out, &report.metadata, differences, opt.top.unwrap_or(10)),
+        output::Format::Quickfix => export::write_quickfix(out, &report.metadata, differences, opt.category),
+        output::Format::ReviewComment if base_report.is_some() => {
+            output::write_review_comment(out, report, base_report, opt.top.unwrap_or(10))
+        }
+        _ => output::write_report(out, format, options, report)
+    }
+}
+
+/// Write each of the --artifacts formats to a file in --out-dir
+fn write_artifacts(out_dir: &Path,
+                   opt: &Opt,
+                   report: &report::Report,
+                   differences: &BTreeMap<String, CoverageDifference>,
+                   base_report: Option<&report::Report>) -> Result<()> {
+    fs::create_dir_all(out_dir)?;
+    let options = output::OutputOptions {
+        terminal: false,
+        color: false,
+        hyperlinks: false,
+        ..opt.output_options()
+    };
+    for format in opt.artifacts.iter() {
+        let format = format.resolve(false);
+        if format == output::Format::Sqlite {
+            output::write_sqlite(&out_dir.join(format.file_name()), report)?;
+        } else {
+            let path = out_dir.join(format!("{}{}", format.file_name(), opt.compress.suffix()));
+            let mut f = opt.compress.writer(io::BufWriter::new(File::create(&path)?))?;
+            write_format(&mut f, format, opt, &options, report, differences, base_report)?;
+            f.finish()?;
+            log::info(format_args!("Wrote {}", path.display()));
+        }
+    }
+    Ok(())
+}
+
+/// Log how many files had their results taken from the --previous report
+fn log_reused(previous: Option<&report::PreviousReport>, files: usize) {
+    if let Some(previous) = previous {
+        log::info(format_args!("Reused the results for {} of {} files, which are unchanged since the previous report",
+                               previous.reused(),
+                               files));
+    }
+}
+
+fn run_diff(client: &reqwest::Client, opt: &Opt) -> Result<()> {
+    let primary_output = opt.template.is_none() && !opt.summary_only;
+    if opt.format == output::Format::Sqlite && opt.output.is_none() && primary_output {
+        return Err(Error::Usage("The sqlite format requires an output file".into()));
+    }
+
+    let base_report = match opt.base_report {
+        Some(ref path) => Some(load_base_report(path, &opt.diff)?),
+        None => None
+    };
+
+    // The line level differences are the bulk of the memory use, so they're
+    // only kept for the formats that use them
+    let keep_lines = needs_line_differences(opt);
+    let previous = match opt.previous {
+        Some(ref path) if keep_lines => {
+            log::warning(format_args!("Ignoring --previous {}, since the output needs line level differences, \
+                                       which reports don't keep",
+                                      path.display()));
+            None
+        }
+        Some(ref path) => Some(report::PreviousReport::load(path, &PathFilter::new(&opt.diff))?),
+        None => None
+    };
+
+    let inputs = comparison_inputs(client, &opt.diff)?;
+
+    if opt.format == output::Format::Ndjson && opt.out_dir.is_none() && primary_output {
+        let mut handle = output_writer(opt)?;
+        let wpt_report = inputs.wpt_report.as_ref();
+        let mut directories = BTreeMap::new();
+        let mut files = 0;
+        stream_differences(client, &inputs, previous.as_ref(), false, |path, difference| {
+            let record = report::FileRecord::new(path, &difference, wpt_report);
+            report::add_directory_totals(&mut directories, &record);
+            files += 1;
+            output::write_ndjson_record(&mut handle, &record)
+        })?;
+        handle.finish()?;
+        log_reused(previous.as_ref(), files);
+        return check_gates(opt, &inputs.metadata, &directories);
+    }
+
+    let mut differences = BTreeMap::new();
+    stream_differences(client, &inputs, previous.as_ref(), keep_lines, |path, difference| {
+        differences.insert(path.to_owned(), difference);
+        Ok(())
+    })?;
+    log_reused(previous.as_ref(), differences.len());
+    let report = report::Report::new(inputs.metadata, &differences, inputs.wpt_report.as_ref());
+
+    progress::phase("write");
+    let _timer = timings::start(timings::Stage::Output);
+    if let Some(ref out_dir) = opt.out_dir {
+        write_artifacts(out_dir, opt, &report, &differences, base_report.as_ref())?;
+    }
+
+    if opt.summary_only {
+        let mut handle = output_writer(opt)?;
+        output::write_summary(&mut handle, &report.metadata, &report.totals())?;
+        handle.finish()?;
+    } else if let Some(ref template) = opt.template {
+        let mut handle = output_writer(opt)?;
+        output::write_template(&mut handle, template, &report)?;
+        handle.finish()?;
+    } else {
+        match (opt.format, opt.output.as_ref()) {
+            (output::Format::Sqlite, Some(path)) => output::write_sqlite(path, &report)?,
+            (format, _) => {
+                let mut handle = output_writer(opt)?;
+                write_format(&mut handle,
+                             format,
+                             opt,
+                             &opt.output_options(),
+                             &report,
+                             &differences,
+                             base_report.as_ref())?;
+                handle.finish()?;
+            }
+        }
+    }
+
+    if opt.open {
+        open_html_output(opt);
+    }
+
+    check_gates(opt, &report.metadata, &report::directory_totals(report.files.iter()))
+}
+
+/// Open the HTML report from this run, either the -o file or the one in
+/// --out-dir
+fn open_html_output(opt: &Opt) {
+    let html_artifact = opt.artifacts.contains(&output::Format::Html) && opt.compress == output::Compression::None;
+    let path = match (opt.output.as_ref(), opt.out_dir.as_ref()) {
+        (Some(path), _) if opt.format == output::Format::Html && opt.template.is_none() => path.clone(),
+        (_, Some(out_dir)) if html_artifact => out_dir.join(output::Format::Html.file_name()),
+        _ => {
+            log::warning(format_args!("--open requires html output to a file"));
+            return;
+        }
+    };
+    browser::open(&path.to_string_lossy());
+}
+
+/// Apply the --fail-if conditions and the --baseline check, reporting all
+/// the failures before returning an error
+fn check_gates(opt: &Opt,
+               metadata: &report::RunMetadata,
+               directories: &BTreeMap<String, report::Totals>) -> Result<()> {
+    let totals = directories.get("").cloned().unwrap_or_default();
+    let fail_if = gate::check(&opt.fail_if, &totals);
+    let baseline = match opt.baseline {
+        Some(ref path) if path.exists() => {
+            let allowlist = match opt.allowlist {
+                Some(ref path) => allowlist::Allowlist::load(path)?,
+                None => allowlist::Allowlist::default()
+            };
+            baseline::Baseline::load(path)?.check(metadata, directories, opt.baseline_tolerance, &allowlist)
+        }
+        Some(ref path) => {
+            baseline::Baseline::new(metadata, directories).save(path)?;
+            log::info(format_args!("Created baseline {}", path.display()));
+            Ok(())
+        }
+        None => Ok(())
+    };
+    fail_if.and(baseline)
+}
+
+/// Writer for the output of a command: the --output file, compressed as
+/// requested, or stdout. Either way it's buffered, so it must be finished.
+fn output_writer(opt: &Opt) -> Result<output::Compressed<Box<dyn Write>>> {
+    Ok(match opt.output {
+        Some(ref path) => opt.compress.writer(Box::new(io::BufWriter::new(File::create(path)?)) as Box<dyn Write>)?,
+        None => output::Compressed::Plain(Box::new(output::stdout()))
+    })
+}
+
+fn run_annotate(client: &reqwest::Client, opt: &Opt, diff: &DiffArgs, html: bool) -> Result<()> {
+    let comparison = load_comparison(client, diff)?;
+    let metadata = comparison.metadata;
+    let differences = get_differences(comparison.suite_1_data, comparison.suite_2_data);
+    if differences.is_empty() {
+        return Err(Error::Data("No coverage data found for the given paths".into()));
+    }
+    let root_path = cache::changeset_root(&metadata.changeset);
+    let color = opt.output_options().color;
+
+    let mut handle = output_writer(opt)?;
+    for (path, difference) in differences.iter() {
+        let source = source::get_source(client, &root_path, &metadata.changeset, path)?;
+        if html {
+            annotate::write_html(&mut handle, &metadata, path, &source, difference)?;
+        } else {
+            annotate::write_terminal(&mut handle, path, &source, difference, color)?;
+        }
+    }
+    handle.finish()?;
+    if opt.open {
+        match opt.output {
+            Some(ref path) if html => browser::open(&path.to_string_lossy()),
+            _ => log::warning(format_args!("--open requires --html and an output file")),
+        }
+    }
+    Ok(())
+}
+
+/// Run the comparison for a single file, which is also used as the base path
+/// if `diff` doesn't have one
+fn load_file_comparison(client: &reqwest::Client, diff: &DiffArgs, path: &str) -> Result<Comparison> {
+    let mut diff = diff.clone();
+    if diff.base_paths.is_none() && diff.base_paths_file.is_none() {
+        diff.base_paths = Some(normalize_path(path));
+    }
+    load_comparison(client, &diff)
+}
+
+/// Run the comparison for a single file and get the difference for it
+fn load_file_difference(client: &reqwest::Client,
+                        diff: &DiffArgs,
+                        path: &str) -> Result<(report::RunMetadata, CoverageDifference)> {
+    let comparison = load_file_comparison(client, diff, path)?;
+    let mut differences = get_differences(comparison.suite_1_data, comparison.suite_2_data);
+    let difference = differences
+        .remove(&normalize_path(path))
+        .ok_or_else(|| Error::Data(format!("No coverage data found for {}", path)))?;
+    Ok((comparison.metadata, difference))
+}
+
+fn run_cobertura(client: &reqwest::Client,
+                 opt: &Opt,
+                 diff: &DiffArgs,
+                 category: CoverageType,
+                 suite: Option<u8>) -> Result<()> {
+    let comparison = load_comparison(client, diff)?;
+    let files = match suite {
+        Some(suite) => {
+            let data = match suite {
+                1 => comparison.suite_1_data,
+                2 => comparison.suite_2_data,
+                _ => return Err(Error::Usage(format!("Invalid suite {}, expected 1 or 2", suite)))
+            };
+            data
+                .into_iter()
+                .filter(|(_, x)| &*x.path_type != "directory")
+                .filter_map(|(path, x)| x.coverage.map(|coverage| (path.to_string(), coverage.to_vec())))
+                .collect()
+        }
+        None => get_differences(comparison.suite_1_data, comparison.suite_2_data)
+            .into_iter()
+            .map(|(path, x)| {
+                let hits = export::category_hits(&x, category);
+                (path, hits)
+            })
+            .collect()
+    };
+    let mut handle = output_writer(opt)?;
+    export::write_cobertura(&mut handle, &comparison.metadata, &files)?;
+    handle.finish()?;
+    Ok(())
+}
+
+/// Load the --base-report that a run with `diff` is compared with
+fn load_base_report(path: &Path, diff: &DiffArgs) -> Result<report::Report> {
+    let rv = report::Report::load(path)?;
+    report::warn_filters_differ(&format!("--base-report {}", path.display()),
+                                &rv.metadata.filters,
+                                &PathFilter::new(diff));
+    Ok(rv)
+}
+
+fn run_publish_github(client: &reqwest::Client,
+                      opt: &Opt,
+                      diff: &DiffArgs,
+                      repo: &str,
+                      pr: u64,
+                      token: &str,
+                      api_url: &str) -> Result<()> {
+    let base_report = match opt.base_report {
+        Some(ref path) => Some(load_base_report(path, diff)?),
+        None => None
+    };
+    let comparison = load_comparison(client, diff)?;
+    let differences = get_differences(comparison.suite_1_data, comparison.suite_2_data);
+    let report = report::Report::new(comparison.metadata, &differences, comparison.wpt_report.as_ref());
+
+    let mut summary = Vec::new();
+    output::write_review_comment(&mut summary, &report, base_report.as_ref(), opt.top.unwrap_or(10))?;
+    let summary = String::from_utf8(summary).map_err(|e| Error::String(e.to_string()))?;
+    github::publish_comment(client, api_url, repo, pr, token, &summary)
+}
+
+/// Run the comparison for each of the `count` most recent changesets, oldest
+/// first. Downloaded data is cached per changeset, so repeated runs only
+/// fetch the new changesets. Changesets that can't be compared are skipped.
+fn get_history(client: &reqwest::Client, diff: &DiffArgs, count: usize) -> Result<Vec<history::HistoryRow>> {
+    let mut changesets = get_changesets(client, count)?;
+    changesets.reverse();
+
+    let mut rows = Vec::new();
+    for info in changesets {
+        let mut diff = diff.clone();
+        diff.changeset = Some(info.changeset.clone());
+        // Older changesets are expected here
+        diff.stale_days = 0;
+        let comparison = match load_comparison(client, &diff) {
+            Ok(comparison) => comparison,
+            Err(e) => {
+                log::warning(format_args!("Skipping {}: {:?}", info.changeset, e));
+                continue;
+            }
+        };
+        let differences = get_differences(comparison.suite_1_data, comparison.suite_2_data);
+        let report = report::Report::new(comparison.metadata, &differences, None);
+        for (dir, totals) in report.top_level_totals() {
+            rows.push(history::HistoryRow::new(&report.metadata, info.push, info.date, dir, &totals));
+        }
+    }
+    Ok(rows)
+}
+
+/// Compare the latest changeset, or the one given in `diff`, with the run
+/// stored in `state`, and replace the stored run with the new one. New runs
+/// are also added to the `history` database.
+fn check_once(client: &reqwest::Client,
+              diff: &DiffArgs,
+              state: &Path,
+              history: Option<&Path>,
+              threshold: f64) -> Result<check::Outcome> {
+    let previous = if state.exists() {
+        Some(report::Report::load(state)?)
+    } else {
+        None
+    };
+    if let Some(previous) = previous.as_ref() {
+        let filters = PathFilter::new(diff);
+        if previous.metadata.filters != filters {
+            return Err(Error::Usage(format!("The run in {} was made with {}, but this run has {}; \
+                                             remove it to start again with these filters",
+                                            state.display(),
+                                            previous.metadata.filters,
+                                            filters)));
+        }
+    }
+    let mut diff = diff.clone();
+    diff.changeset = Some(select_changeset(client, &diff)?);
+    if let (Some(previous), Some(changeset)) = (previous.as_ref(), diff.changeset.as_ref()) {
+        if &previous.metadata.changeset == changeset {
+            return Ok(check::Outcome::Unchanged(changeset.clone()));
+        }
+    }
+
+    let comparison = load_comparison(client, &diff)?;
+    let differences = get_differences(comparison.suite_1_data, comparison.suite_2_data);
+    let report = report::Report::new(comparison.metadata, &differences, comparison.wpt_report.as_ref());
+
+    let mut f = io::BufWriter::new(File::create(state)?);
+    serde_json::to_writer(&mut f, &report)?;
+    f.flush()?;
+    if let Some(history) = history {
+        output::write_sqlite(history, &report)?;
+    }
+
+    Ok(match previous {
+        Some(previous) => {
+            let movements = check::movements(&previous, &report, threshold);
+            check::Outcome::Compared {
+                previous: Box::new(previous),
+                current: Box::new(report),
+                movements,
+            }
+        }
+        None => check::Outcome::Initial(Box::new(report))
+    })
+}
+
+/// Send notifications about metrics that moved between two runs. Failures
+/// are only logged, so that a broken webhook doesn't hide the results.
+fn notify(client: &reqwest::Client,
+          args: &NotifyArgs,
+          previous: &report::Report,
+          current: &report::Report,
+          movements: &[check::Movement],
+          threshold: f64) {
+    if movements.is_empty() {
+        return;
+    }
+    let notification = notify::Notification::new(previous, current, movements, threshold, args.report_url.as_deref());
+    for url in args.webhooks.iter() {
+        if let Err(e) = notify::send_webhook(client, url, &notification) {
+            log::warning(format_args!("Failed to notify {}: {:?}", url, e));
+        }
+    }
+    for url in args.slack.iter() {
+        if let Err(e) = notify::send_slack(client, url, &notification, args.slack_top) {
+            log::warning(format_args!("Failed to notify {}: {:?}", url, e));
+        }
+    }
+}
+
+fn run_check(client: &reqwest::Client,
+             diff: &DiffArgs,
+             state: &Path,
+             history: Option<&Path>,
+             threshold: f64,
+             notify_args: &NotifyArgs) -> Result<()> {
+    match check_once(client, diff, state, history, threshold)? {
+        check::Outcome::Unchanged(changeset) => {
+            log::info(format_args!("No new changeset since {}", changeset));
+            Ok(())
+        }
+        check::Outcome::Initial(report) => {
+            log::info(format_args!("No previous run; stored {} in {}", report.metadata.changeset, state.display()));
+            Ok(())
+        }
+        check::Outcome::Compared { previous, current, movements } => {
+            write_movements(&movements)?;
+            notify(client, notify_args, &previous, &current, &movements, threshold);
+            if movements.is_empty() {
+                return Ok(());
+            }
+            Err(Error::Threshold(format!("{} metrics moved by more than {} percentage points between {} and {}",
+                                         movements.len(),
+                                         threshold,
+                                         previous.metadata.changeset,
+                                         current.metadata.changeset)))
+        }
+    }
+}
+
+/// Changesets to check in order, oldest first: those among the `latest`
+/// most recent that are newer than the run stored in `state`
+fn pending_changesets(client: &reqwest::Client, state: &Path, latest: usize) -> Result<Vec<String>> {
+    let previous = if state.exists() {
+        Some(report::Report::load(state)?.metadata.changeset)
+    } else {
+        None
+    };
+    let mut rv = get_changesets(client, latest)?
+        .into_iter()
+        .map(|x| x.changeset)
+        .take_while(|x| Some(x) != previous.as_ref())
+        .collect::<Vec<_>>();
+    rv.reverse();
+    Ok(rv)
+}
+
+/// Write the metrics that moved in a check to stdout
+fn write_movements(movements: &[check::Movement]) -> Result<()> {
+    let mut handle = output::stdout();
+    for movement in movements.iter() {
+        writeln!(handle, "{}", movement)?;
+    }
+    handle.flush()?;
+    Ok(())
+}
+
+fn log_outcome(client: &reqwest::Client,
+               outcome: Result<check::Outcome>,
+               threshold: f64,
+               notify_args: &NotifyArgs) {
+    match outcome {
+        Ok(check::Outcome::Unchanged(changeset)) => {
+            log::info(format_args!("No new changeset since {}", changeset));
+        }
+        Ok(check::Outcome::Initial(report)) => {
+            log::info(format_args!("Stored initial run for {}", report.metadata.changeset));
+        }
+        Ok(check::Outcome::Compared { previous, current, movements }) => {
+            log::info(format_args!("Checked {}; {} metrics moved", current.metadata.changeset, movements.len()));
+            if let Err(e) = write_movements(&movements) {
+                log::warning(format_args!("Couldn't write the movements: {:?}", e));
+            }
+            notify(client, notify_args, &previous, &current, &movements, threshold);
+        }
+        Err(e) => log::warning(format_args!("Check failed: {:?}", e)),
+    }
+}
+
+/// Check for a new changeset every `interval`, until interrupted. Failures
+/// are logged rather than stopping the loop, since they're often transient.
+#[allow(clippy::too_many_arguments)]
+fn run_watch(client: &reqwest::Client,
+             diff: &DiffArgs,
+             state: &Path,
+             history: Option<&Path>,
+             threshold: f64,
+             interval: std::time::Duration,
+             latest: usize,
+             notify_args: &NotifyArgs) -> Result<()> {
+    loop {
+        if latest > 1 && diff.changeset.is_none() && diff.date.is_none() {
+            match pending_changesets(client, state, latest) {
+                Ok(changesets) if changesets.is_empty() => log::info(format_args!("No new changesets")),
+                Ok(changesets) => {
+                    for changeset in changesets {
+                        let mut diff = diff.clone();
+                        diff.changeset = Some(changeset);
+                        let outcome = check_once(client, &diff, state, history, threshold);
+                        log_outcome(client, outcome, threshold, notify_args);
+                    }
+                }
+                Err(e) => log::warning(format_args!("Check failed: {:?}", e)),
+            }
+        } else {
+            let outcome = check_once(client, diff, state, history, threshold);
+            log_outcome(client, outcome, threshold, notify_args);
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+fn run() -> Result<()> {
+    let client = reqwest::Client::new();
+
+    let mut opt = match Opt::from_iter_safe(env::args_os()) {
+        Ok(opt) => opt,
+        Err(e) if e.use_stderr() => {
+            eprintln!("{}", e.message);
+            process::exit(EXIT_USAGE);
+        }
+        Err(e) => e.exit()
+    };
+    log::init(opt.quiet, opt.verbose);
+    timings::init(opt.timings);
+    api::init(opt.api_version);
+    if let Some(ref dir) = opt.fixtures {
+        cache::set_root(dir);
+        api::use_fixtures();
+        if !dir.exists() {
+            log::info(format_args!("Writing fixture dataset to {}", dir.display()));
+            fixtures::generate()?;
+        }
+    }
+    progress::init(if opt.quiet { progress::ProgressMode::None } else { opt.progress });
+    let config = config::Config::load(opt.config.as_deref())?;
+    if opt.color_thresholds.is_none() {
+        opt.color_thresholds = config.color_thresholds;
+    }
+
+    let rv = match opt.cmd {
+        Some(Command::CompareReports { ref old, ref new, threshold }) => {
+            let mut handle = output::stdout();
+            compare::compare_reports(&mut handle, old, new, threshold, &opt.output_options())?;
+            handle.flush()?;
+            Ok(())
+        }
+        Some(Command::ListSuites) => {
+            let mut handle = output::stdout();
+            for suite in get_suites(&client)? {
+                writeln!(handle, "{}", suite)?;
+            }
+            handle.flush()?;
+            Ok(())
+        }
+        Some(Command::ListChangesets { latest, hashes_only }) => {
+            let mut handle = output::stdout();
+            for info in get_changesets(&client, latest)? {
+                if hashes_only {
+                    writeln!(handle, "{}", info.changeset)?;
+                    continue;
+                }
+                let date = info.date
+                    .map(|x| x.format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_else(|| "-".into());
+                let push = info.push
+                    .map(|x| x.to_string())
+                    .unwrap_or_else(|| "-".into());
+                writeln!(handle, "{} {} {}", info.changeset, push, date)?;
+            }
+            handle.flush()?;
+            Ok(())
+        }
+        Some(Command::Site { ref diff, ref out_dir }) => {
+            let comparison = load_comparison(&client, diff)?;
+            let differences = get_differences(comparison.suite_1_data, comparison.suite_2_data);
+            let report = report::Report::new(comparison.metadata, &differences, comparison.wpt_report.as_ref());
+            site::write_site(out_dir, &report, &differences)?;
+            if opt.open {
+                browser::open(&out_dir.join("index.html").to_string_lossy());
+            }
+            Ok(())
+        }
+        Some(Command::Annotate { ref diff, html }) => run_annotate(&client, &opt, diff, html),
+        Some(Command::Serve { ref diff, ref host, port }) => {
+            let comparison = load_comparison(&client, diff)?;
+            let differences = get_differences(comparison.suite_1_data, comparison.suite_2_data);
+            let report = report::Report::new(comparison.metadata, &differences, comparison.wpt_report.as_ref());
+            progress::clear();
+            let options = output::OutputOptions {
+                terminal: false,
+                color: false,
+                hyperlinks: false,
+                ..opt.output_options()
+            };
+            serve::serve(&client, (host.as_str(), port), &report, &differences, &options, opt.open)
+        }
+        Some(Command::Lines { ref path, ref diff, category, ranges }) => {
+            let (_, difference) = load_file_difference(&client, diff, path)?;
+            let categories = match category {
+                Some(category) => vec![category],
+                None => vec![CoverageType::Suite1Only,
+                             CoverageType::Suite2Only,
+                             CoverageType::Both,
+                             CoverageType::NotCovered]
+            };
+            let mut handle = output_writer(&opt)?;
+            export::write_lines(&mut handle, &difference, &categories, ranges)?;
+            handle.finish()?;
+            Ok(())
+        }
+        Some(Command::Explain { ref path, ref diff }) => {
+            let (metadata, difference) = load_file_difference(&client, diff, path)?;
+            let root_path = cache::changeset_root(&metadata.changeset);
+            let source = source::get_source(&client, &root_path, &metadata.changeset, path)?;
+            let color = opt.output_options().color;
+            let mut handle = output_writer(&opt)?;
+            annotate::write_terminal_legend(&mut handle, &metadata, &difference, color)?;
+            annotate::write_terminal(&mut handle, path, &source, &difference, color)?;
+            handle.finish()?;
+            Ok(())
+        }
+        Some(Command::SideBySide { ref path, ref diff }) => {
+            let comparison = load_file_comparison(&client, diff, path)?;
+            let normalized = normalize_path(path);
+            let rows = export::line_matrix(&comparison.suite_1_data, &comparison.suite_2_data, &[normalized.as_str()]);
+            let rows = rows.into_iter().filter(|x| x.path == normalized).collect::<Vec<_>>();
+            if rows.is_empty() {
+                return Err(Error::Data(format!("No coverage data found for {}", path)));
+            }
+            let color = opt.output_options().color;
+            progress::clear();
+            if opt.output.is_some() {
+                let mut handle = output_writer(&opt)?;
+                annotate::write_side_by_side(&mut handle, &comparison.metadata, &rows, color)?;
+                handle.finish()?;
+                Ok(())
+            } else {
+                pager::page(|out| annotate::write_side_by_side(out, &comparison.metadata, &rows, color))
+            }
+        }
+        Some(Command::Open { ref path, ref diff, ref srcdir }) => {
+            let (_, difference) = load_file_difference(&client, diff, path)?;
+            let line = editor::first_suite_2_only_line(&difference);
+            if line.is_none() {
+                log::warning(format_args!("{} has no lines only covered by suite 2", path));
+            }
+            progress::clear();
+            editor::open(srcdir, path, line)
+        }
+        Some(Command::Completions { shell }) => {
+            let mut handle = output::stdout();
+            completions::write_completions(&mut handle, Opt::clap(), shell)?;
+            handle.flush()?;
+            Ok(())
+        }
+        Some(Command::Man) => {
+            let mut handle = output::stdout();
+            man::write_man_page(&mut handle, &Opt::clap())?;
+            handle.flush()?;
+            Ok(())
+        }
+        Some(Command::CompleteValues { ref kind }) => {
+            let root = cache::root();
+            let values = if kind == "changesets" {
+                completions::cached_changesets(&root)
+            } else {
+                completions::cached_suites(&root)
+            };
+            let mut handle = output::stdout();
+            for value in values {
+                writeln!(handle, "{}", value)?;
+            }
+            handle.flush()?;
+            Ok(())
+        }
+        Some(Command::Tui { ref diff, ref srcdir }) => {
+            let comparison = load_comparison(&client, diff)?;
+            let differences = get_differences(comparison.suite_1_data, comparison.suite_2_data);
+            let report = report::Report::new(comparison.metadata, &differences, comparison.wpt_report.as_ref());
+            progress::clear();
+            tui::run(&report, &differences, srcdir.as_deref())
+        }
+        Some(Command::Lcov { ref diff, category }) => {
+            let comparison = load_comparison(&client, diff)?;
+            let differences = get_differences(comparison.suite_1_data, comparison.suite_2_data);
+            let mut handle = output_writer(&opt)?;
+            export::write_lcov(&mut handle, &differences, category)?;
+            handle.finish()?;
+            Ok(())
+        }
+        Some(Command::Chart { ref diff, chart, width, height }) => {
+            let path = match opt.output {
+                Some(ref path) => path,
+                None => return Err(Error::Usage("Charts require an output file".into()))
+            };
+            let comparison = load_comparison(&client, diff)?;
+            let differences = get_differences(comparison.suite_1_data, comparison.suite_2_data);
+            let report = report::Report::new(comparison.metadata, &differences, comparison.wpt_report.as_ref());
+            chart::write_chart(path, &report, chart, (width, height), opt.top.unwrap_or(20))
+        }
+        Some(Command::LinesMatrix { ref diff, ref paths }) => {
+            // Only fetch the selected files, rather than the whole tree
+            let mut diff = diff.clone();
+            diff.include.extend(paths.iter().cloned());
+            let comparison = load_comparison(&client, &diff)?;
+            let paths = if paths.is_empty() {
+                vec![""]
+            } else {
+                paths.iter().map(|x| x.as_str()).collect::<Vec<_>>()
+            };
+            let rows = export::line_matrix(&comparison.suite_1_data, &comparison.suite_2_data, &paths);
+            let mut handle = output_writer(&opt)?;
+            match opt.format.resolve(false) {
+                output::Format::Csv => export::write_line_matrix_csv(&mut handle, &rows, &opt.output_options())?,
+                output::Format::Parquet => output::write_line_matrix_parquet(&mut handle, &rows)?,
+                _ => return Err(Error::Usage("lines-matrix can only be written as csv or parquet".into()))
+            }
+            handle.finish()?;
+            Ok(())
+        }
+        Some(Command::Blame { ref diff, category, ref srcdir }) => {
+            let comparison = load_comparison(&client, diff)?;
+            let differences = get_differences(comparison.suite_1_data, comparison.suite_2_data);
+            let root_path = cache::changeset_root(&comparison.metadata.changeset);
+            let source = match srcdir {
+                Some(srcdir) => blame::BlameSource::Checkout(srcdir),
+                None => blame::BlameSource::Hg {
+                    client: &client,
+                    root_path: &root_path,
+                    changeset: &comparison.metadata.changeset,
+                },
+            };
+            let attribution = blame::attribute(&source, &differences, category)?;
+            let options = opt.output_options();
+            let mut handle = output_writer(&opt)?;
+            blame::write_attribution(&mut handle,
+                                     opt.format.resolve(options.terminal),
+                                     &comparison.metadata,
+                                     &attribution,
+                                     category,
+                                     opt.top.unwrap_or(20),
+                                     &options)?;
+            handle.finish()?;
+            Ok(())
+        }
+        Some(Command::LineAge { ref diff, ref categories, ref age_months, ref srcdir }) => {
+            let comparison = load_comparison(&client, diff)?;
+            let differences = get_differences(comparison.suite_1_data, comparison.suite_2_data);
+            let root_path = cache::changeset_root(&comparison.metadata.changeset);
+            let source = match srcdir {
+                Some(srcdir) => blame::BlameSource::Checkout(srcdir),
+                None => blame::BlameSource::Hg {
+                    client: &client,
+                    root_path: &root_path,
+                    changeset: &comparison.metadata.changeset,
+                },
+            };
+            let reference = comparison.metadata.push_date.unwrap_or(comparison.metadata.date);
+            let ages = blame::line_ages(&source, &differences, categories, age_months, reference)?;
+            let options = opt.output_options();
+            let mut handle = output_writer(&opt)?;
+            blame::write_line_ages(&mut handle,
+                                   opt.format.resolve(options.terminal),
+                                   &comparison.metadata,
+                                   &ages,
+                                   &options)?;
+            handle.finish()?;
+            Ok(())
+        }
+        Some(Command::Publish(PublishCommand::Github { ref diff, ref repo, pr, ref token, ref api_url })) => {
+            run_publish_github(&client, &opt, diff, repo, pr, token, api_url)
+        }
+        Some(Command::History { ref diff, latest, drop_threshold }) => {
+            let rows = get_history(&client, diff, latest)?;
+            if let Some(threshold) = drop_threshold {
+                for drop in history::find_drops(&rows, threshold) {
+                    log::verdict("DROP", format_args!("{}", drop));
+                }
+            }
+            let mut handle = output_writer(&opt)?;
+            match opt.format.resolve(false) {
+                output::Format::Csv => history::write_history_csv(&mut handle, &rows, &opt.output_options())?,
+                output::Format::Json => history::write_history_json(&mut handle, &rows)?,
+                _ => return Err(Error::Usage("history can only be written as csv or json".into()))
+            }
+            handle.finish()?;
+            Ok(())
+        }
+        Some(Command::Badge { ref diff, ref metric, ref label, ref report }) => {
+            let report = match report {
+                Some(path) => report::Report::load(path)?,
+                None => {
+                    let comparison = load_comparison(&client, diff)?;
+                    let differences = get_differences(comparison.suite_1_data, comparison.suite_2_data);
+                    report::Report::new(comparison.metadata, &differences, comparison.wpt_report.as_ref())
+                }
+            };
+            let mut handle = output_writer(&opt)?;
+            let thresholds = opt.output_options().color_thresholds;
+            badge::write_badge(&mut handle, &report, metric, label.as_ref().map(|x| x.as_str()), &thresholds)?;
+            handle.finish()?;
+            Ok(())
+        }
+        Some(Command::Check { ref diff, ref state, threshold, ref history, ref notify }) => {
+            run_check(&client, diff, state, history.as_ref().map(|x| x.as_path()), threshold, notify)
+        }
+        Some(Command::Watch { ref diff, ref state, threshold, ref history, interval, latest, ref notify }) => {
+            run_watch(&client, diff, state, history.as_ref().map(|x| x.as_path()), threshold, interval, latest, notify)
+        }
+        Some(Command::Baseline(BaselineCommand::Update { ref diff, ref baseline, ratchet })) => {
+            let comparison = load_comparison(&client, diff)?;
+            let differences = get_differences(comparison.suite_1_data, comparison.suite_2_data);
+            let report = report::Report::new(comparison.metadata, &differences, comparison.wpt_report.as_ref());
+            let current = baseline::Baseline::new(&report.metadata, &report::directory_totals(report.files.iter()));
+            let updated = if ratchet && baseline.exists() {
+                baseline::Baseline::load(baseline)?.ratchet(current)?
+            } else {
+                current
+            };
+            updated.save(baseline)?;
+            log::info(format_args!("Wrote baseline {}", baseline.display()));
+            Ok(())
+        }
+        Some(Command::Sarif { ref diff, min_lines }) => {
+            let comparison = load_comparison(&client, diff)?;
+            let differences = get_differences(comparison.suite_1_data, comparison.suite_2_data);
+            let mut handle = output_writer(&opt)?;
+            export::write_sarif(&mut handle, &comparison.metadata, &differences, min_lines)?;
+            handle.finish()?;
+            Ok(())
+        }
+        Some(Command::Cobertura { ref diff, category, suite }) => run_cobertura(&client, &opt, diff, category, suite),
+        None => run_diff(&client, &opt)
+    };
+    if rv.is_ok() {
+        progress::done();
+    }
+    rv
+}
+
+/// Report an error parsing a document, and offer to delete it if it's a
+/// cached copy, since otherwise every run fails the same way
+fn report_parse_error(error: &parse::ParseError) {
+    eprintln!("ERROR: {}", error);
+    let cached = match error.cached {
+        Some(ref cached) => cached,
+        None => return
+    };
+    if !prompt::is_interactive() {
+        eprintln!("Delete {} to fetch it again", cached.display());
+        return;
+    }
+    match prompt::confirm_delete(cached) {
+        Ok(true) => match fs::remove_file(cached) {
+            Ok(()) => eprintln!("Deleted {}", cached.display()),
+            Err(e) => eprintln!("Failed to delete {}: {}", cached.display(), e)
+        },
+        Ok(false) => {}
+        Err(e) => eprintln!("{:?}", e)
+    }
+}
+
+fn main() {
+    let rv = run();
+    timings::report();
+    if let Err(e) = rv {
+        match e {
+            Error::Parse(ref error) => report_parse_error(error),
+            ref e => eprintln!("ERROR: Failed:\n{:?}", e)
+        }
+        process::exit(e.exit_code());
+    };
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{check, check_once, comparison_inputs, coverage_difference, fallback_changesets, fallback_entry,
+                find_renames, for_each_difference, for_each_in_order, normalize_base_paths, normalize_path,
+                pending_changesets, report, stream_differences, visit_suite_data, CoverageDifference, CoverageMap,
+                CoverageType, Error, FetchOptions, LineCoverage, PathCoverage, PathFilter, MIN_RENAME_LINES,
+                RENAME_SIMILARITY};
+    use crate::fixtures::tests::{args, init};
+    use crate::cache;
+    use crate::fixtures::{CHANGESET, SUITE_1};
+    use crate::report::tests::temp_path;
+
+    #[test]
+    fn check_state() {
+        init();
+        let client = reqwest::Client::new();
+        let state = temp_path("check-state.json");
+        let _ = fs::remove_file(&state);
+
+        let first = check_once(&client, &args(&[]), &state, None, 1.0);
+        assert!(matches!(first, Ok(check::Outcome::Initial(_))));
+        assert!(state.exists());
+        let second = check_once(&client, &args(&[]), &state, None, 1.0);
+        assert!(matches!(second, Ok(check::Outcome::Unchanged(ref changeset)) if changeset == CHANGESET));
+
+        // Pretend the stored run was of an earlier changeset, with less unique coverage in dom/media
+        let mut previous = report::Report::load(&state).unwrap();
+        previous.metadata.changeset = "0".repeat(40);
+        let media = previous.files.iter_mut().find(|x| x.path.starts_with("dom/media/")).unwrap();
+        media.both += media.suite_2_only;
+        media.suite_2_only = 0;
+        fs::write(&state, serde_json::to_vec(&previous).unwrap()).unwrap();
+        match check_once(&client, &args(&[]), &state, None, 1.0) {
+            Ok(check::Outcome::Compared { previous, current, movements }) => {
+                assert_eq!(previous.metadata.changeset, "0".repeat(40));
+                assert_eq!(current.metadata.changeset, CHANGESET);
+                let moved = movements.iter().map(|x| (x.name(), x.suite.as_str())).collect::<Vec<_>>();
+                assert_eq!(moved, vec![("total", "mochitest-plain"), ("dom", "mochitest-plain")]);
+            }
+            _ => panic!("Expected the runs to be compared")
+        }
+        // A run with other filters isn't comparable with the stored one
+        let filtered = check_once(&client, &args(&["--include", "dom"]), &state, None, 1.0);
+        fs::remove_file(&state).unwrap();
+        assert!(matches!(filtered, Err(Error::Usage(_))));
+    }
+
+    #[test]
+    fn watch_history() {
+        init();
+        let client = reqwest::Client::new();
+        let state = temp_path("watch-state.json");
+        let history = temp_path("watch-history.sqlite");
+        let _ = fs::remove_file(&state);
+        let _ = fs::remove_file(&history);
+
+        assert_eq!(pending_changesets(&client, &state, 5).unwrap(), vec![CHANGESET]);
+        assert!(matches!(check_once(&client, &args(&[]), &state, Some(&history), 1.0),
+                         Ok(check::Outcome::Initial(_))));
+        assert!(pending_changesets(&client, &state, 5).unwrap().is_empty());
+        // Nothing is added for a changeset that was already checked
+        assert!(matches!(check_once(&client, &args(&[]), &state, Some(&history), 1.0),
+                         Ok(check::Outcome::Unchanged(_))));
+
+        let conn = rusqlite::Connection::open(&history).unwrap();
+        let count = |table: &str| -> i64 {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0)).unwrap()
+        };
+        assert_eq!((count("runs"), count("files"), count("counters")), (1, 4, 4));
+        drop(conn);
+        fs::remove_file(&state).unwrap();
+        fs::remove_file(&history).unwrap();
+    }
+
+    #[test]
+    fn base_paths() {
+        let paths = ["dom/media/", "layout", "dom", "./layout"].iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        assert_eq!(normalize_base_paths(&paths), vec!["layout", "dom"]);
+    }
+
+    fn coverage_map(files: &[(&str, Vec<i64>)]) -> CoverageMap {
+        files.iter().map(|(path, coverage)| (Arc::from(*path), PathCoverage::file(path, coverage.clone()))).collect()
+    }
+
+    #[test]
+    fn renames() {
+        // Differs from `lines` in whether one line in ten is coverable
+        let lines = vec![-1, 1, 0, 2, -1, 0, 0, 1, -1, 3];
+        let similar = vec![-1, 0, 0, 0, -1, 0, 0, 0, 0, 0];
+        assert!(lines.len() >= MIN_RENAME_LINES && RENAME_SIMILARITY <= 0.9);
+        let suite_1 = coverage_map(&[("dom/old/Moved.cpp", lines.clone()),
+                                     ("dom/old/Short.cpp", vec![1, 0, 1, 0]),
+                                     ("dom/old/Header.h", vec![0; 10]),
+                                     ("dom/Both.cpp", lines.clone())]);
+        let suite_2 = coverage_map(&[("dom/new/Moved.cpp", similar),
+                                     ("dom/new/Short.cpp", vec![0, 0, 0, 0]),
+                                     ("dom/new/Header.cpp", vec![0; 10]),
+                                     ("dom/Both.cpp", lines.clone())]);
+        let renames = find_renames(&suite_1, &suite_2);
+        assert_eq!(renames.into_iter().collect::<Vec<_>>(),
+                   vec![("dom/new/Moved.cpp".to_owned(), "dom/old/Moved.cpp".to_owned())]);
+
+        // Too few lines agree to be a rename
+        let dissimilar = vec![-1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let suite_2 = coverage_map(&[("dom/new/Moved.cpp", dissimilar)]);
+        assert!(find_renames(&suite_1, &suite_2).is_empty());
+
+        // Files that match more than one file aren't treated as renamed
+        let suite_1 = coverage_map(&[("a/One.cpp", lines.clone()), ("a/Two.cpp", lines.clone())]);
+        let suite_2 = coverage_map(&[("b/One.cpp", lines.clone())]);
+        assert!(find_renames(&suite_1, &suite_2).is_empty());
+    }
+
+    #[test]
+    fn line_differences() {
+        let suite_1 = LineCoverage::from(vec![-1, 2, 0, 1, 0]);
+        let suite_2 = LineCoverage::from(vec![-1, 0, 0, 3, 4]);
+        let kept = coverage_difference(&suite_1, &suite_2, true);
+        assert_eq!(kept.line_differences,
+                   vec![CoverageType::NotRun,
+                        CoverageType::Suite1Only,
+                        CoverageType::NotCovered,
+                        CoverageType::Both,
+                        CoverageType::Suite2Only]);
+        let counted = coverage_difference(&suite_1, &suite_2, false);
+        assert!(counted.line_differences.is_empty() && counted.line_differences.capacity() == 0);
+        let counts = |x: &CoverageDifference| {
+            (x.suite_1_only_count, x.suite_2_only_count, x.both_count, x.coverable_count)
+        };
+        assert_eq!(counts(&counted), counts(&kept));
+        assert_eq!(counts(&counted), (1, 1, 1, 4));
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_path("dom/base/nsDocument.cpp"), "dom/base/nsDocument.cpp");
+        assert_eq!(normalize_path("./dom//base/"), "dom/base");
+        assert_eq!(normalize_path("dom\\base\\..\\media"), "dom/media");
+        assert_eq!(normalize_path("../dom"), "dom");
+        assert_eq!(normalize_path("/"), "");
+    }
+
+    /// Add an API entry for suite 1 to the fixture data of another changeset
+    fn write_entry(changeset: &str, path: &str, entry: &serde_json::Value) {
+        let suite_root = cache::changeset_root(changeset).join(cache::component(SUITE_1));
+        let entry_path = cache::file_path(&suite_root, path, "json");
+        fs::create_dir_all(entry_path.parent().unwrap()).unwrap();
+        cache::write(&entry_path, entry.to_string().as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn fallbacks() {
+        init();
+        let client = reqwest::Client::new();
+        // The fixture changeset is the only one with data
+        assert!(fallback_changesets(&client, CHANGESET, 2).unwrap().is_empty());
+        assert!(fallback_changesets(&client, &"0".repeat(40), 2).unwrap().is_empty());
+
+        let path = "dom/base/Fallback.cpp";
+        let fallbacks = ["1".repeat(40), "2".repeat(40), "3".repeat(40)];
+        let entries = [serde_json::json!({"path": path, "type": "file"}),
+                       serde_json::json!({"path": path, "type": "file", "coverage": [-1, 2, 0]}),
+                       serde_json::json!({"path": path, "type": "file", "coverage": [-1, 3, 3]})];
+        for (changeset, entry) in fallbacks.iter().zip(entries.iter()) {
+            write_entry(changeset, path, entry);
+        }
+        // The first changeset's entry has no coverage, so the second's is used
+        let (changeset, entry) = fallback_entry(&client, &fallbacks, SUITE_1, path).unwrap().unwrap();
+        assert_eq!(changeset, fallbacks[1]);
+        assert_eq!(entry.coverage.unwrap().to_vec(), vec![-1, 2, 0]);
+        assert!(fallback_entry(&client, &fallbacks[..1], SUITE_1, path).unwrap().is_none());
+    }
+
+    #[test]
+    fn traversal_anomalies() {
+        init();
+        let changeset = "4".repeat(40);
+        let file = |path: &str| serde_json::json!({"path": path, "type": "file"});
+        let directory = |path: &str| serde_json::json!({"path": path, "type": "directory"});
+        // Children listed twice, outside the directory, or as the directory
+        // itself are skipped, as is a child that lists its parent
+        write_entry(&changeset, "dom", &serde_json::json!({
+            "path": "dom",
+            "type": "directory",
+            "children": [file("dom/a.cpp"), file("./dom//a.cpp"), file("layout/b.cpp"), directory("dom"),
+                         directory("dom/sub")]
+        }));
+        write_entry(&changeset, "dom/a.cpp", &serde_json::json!({
+            "path": "dom/a.cpp",
+            "type": "file",
+            "coverage": [1]
+        }));
+        write_entry(&changeset, "dom/sub", &serde_json::json!({
+            "path": "dom/sub",
+            "type": "directory",
+            "children": [directory("dom")]
+        }));
+
+        let mut visited = Vec::new();
+        visit_suite_data(&reqwest::Client::new(),
+                         &changeset,
+                         &cache::changeset_root(&changeset),
+                         SUITE_1,
+                         &["dom"],
+                         &PathFilter::default(),
+                         FetchOptions::default(),
+                         |path, _| {
+                             visited.push(path.to_string());
+                             Ok(())
+                         }).unwrap();
+        visited.sort();
+        assert_eq!(visited, vec!["dom", "dom/a.cpp", "dom/sub"]);
+    }
+
+    #[test]
+    fn ordered_differences() {
+        // Earlier items take longer, so that the workers finish out of order
+        let items = (0..64u64).collect::<Vec<_>>();
+        let compute = |x: &u64| {
+            thread::sleep(Duration::from_millis((64 - x) % 8));
+            x * 2
+        };
+        for threads in [1, 4] {
+            let mut results = Vec::new();
+            for_each_in_order(&items, threads, compute, |x, result| {
+                results.push((*x, result));
+                Ok(())
+            }).unwrap();
+            assert_eq!(results, items.iter().map(|x| (*x, x * 2)).collect::<Vec<_>>());
+        }
+
+        let suite_1 = coverage_map(&[("b.cpp", vec![1, 0]), ("a.cpp", vec![0, 1]), ("c.cpp", vec![1])]);
+        let suite_2 = coverage_map(&[("c.cpp", vec![0]), ("a.cpp", vec![1, 1]), ("0.cpp", vec![1])]);
+        let mut paths = Vec::new();
+        for_each_difference(&suite_1, &suite_2, None, false, |path, _| {
+            paths.push(path.to_owned());
+            Ok(())
+        }).unwrap();
+        // Files in suite 1 come first, followed by the ones only in suite 2
+        assert_eq!(paths, vec!["a.cpp", "b.cpp", "c.cpp", "0.cpp"]);
+    }
+
+    #[test]
+    fn difference_errors() {
+        let items = (0..1000).collect::<Vec<_>>();
+        for threads in [1, 4] {
+            let mut passed = Vec::new();
+            let result = for_each_in_order(&items, threads, |x| *x, |_, x| {
+                if x == 10 {
+                    return Err(Error::String("stop".into()));
+                }
+                passed.push(x);
+                Ok(())
+            });
+            assert!(matches!(result, Err(Error::String(ref message)) if message == "stop"));
+            assert_eq!(passed, (0..10).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn previous_report() {
+        init();
+        let client = reqwest::Client::new();
+        let records = |previous: Option<&report::PreviousReport>| {
+            let inputs = comparison_inputs(&client, &args(&[])).unwrap();
+            let mut rv = Vec::new();
+            stream_differences(&client, &inputs, previous, false, |path, difference| {
+                rv.push(report::FileRecord::new(path, &difference, None));
+                Ok(())
+            }).unwrap();
+            rv
+        };
+        let mut first = report::tests::report(CHANGESET, records(None));
+        assert!(first.files.iter().all(|x| x.coverage_hash.is_some()));
+
+        // Unchanged files take their counters from the previous report, so
+        // change one to show that it was reused, and the coverage of another
+        // so that it's compared again
+        first.files[0].suite_1_only += 100;
+        first.files[1].coverage_hash = Some("0".repeat(32));
+        let path = temp_path("previous-report.json");
+        fs::write(&path, serde_json::to_vec(&first).unwrap()).unwrap();
+        let previous = report::PreviousReport::load(&path, &PathFilter::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let second = records(Some(&previous));
+        assert_eq!(previous.reused(), first.files.len() - 1);
+        assert_eq!(second[0].suite_1_only, first.files[0].suite_1_only);
+        assert_eq!(second[1].coverage_hash, records(None)[1].coverage_hash);
+        assert_ne!(second[1].coverage_hash, first.files[1].coverage_hash);
+    }
+
+    fn filter(filter_args: &[&str]) -> PathFilter {
+        PathFilter::new(&args(filter_args))
+    }
+
+    #[test]
+    fn filter_include_exclude() {
+        let filter = filter(&["--include", "dom/", "--include", "./layout", "--exclude", "dom/media"]);
+        assert!(filter.includes("dom"));
+        assert!(filter.includes("dom/base/nsDocument.cpp"));
+        assert!(filter.includes("layout/base"));
+        assert!(!filter.includes("dom/media"));
+        assert!(!filter.includes("dom/media/MediaDecoder.cpp"));
+        assert!(!filter.includes("domain"));
+        assert!(!filter.includes(""));
+        assert!(filter.visits(""));
+        assert!(!filter.visits("js"));
+        assert!(!filter.is_empty());
+    }
+
+    #[test]
+    fn filter_order() {
+        assert!(filter(&[]).is_empty());
+        assert_eq!(filter(&[]).to_string(), "no path filters");
+        let filter_1 = filter(&["--include", "layout", "--include", "dom/"]);
+        let filter_2 = filter(&["--include", "./dom", "--include", "layout", "--include", "dom"]);
+        assert_eq!(filter_1, filter_2);
+        assert_eq!(filter_1.to_string(), "include dom,layout");
+    }
+
+    #[test]
+    fn filter_depth() {
+        let filter = filter(&["--max-depth", "2", "--prune", "dom/media"]);
+        assert!(filter.expands(""));
+        assert!(filter.expands("dom"));
+        assert!(!filter.expands("dom/base"));
+        assert!(!filter.expands("dom/media"));
+        assert!(filter.includes("js/src/jit"));
+    }
 }
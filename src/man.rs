@@ -0,0 +1,190 @@
+use std::io::Write;
+
+use structopt::clap::{App, AppSettings, ArgSettings};
+
+use crate::Result;
+
+/// Name of the installed binary, which the page documents
+const BIN_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// Escape text so that roff renders it literally
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    // A leading . or ' would start a request
+    text.lines()
+        .map(|line| if line.starts_with('.') || line.starts_with('\'') {
+            format!("\\&{}", line)
+        } else {
+            line.to_owned()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// One documented argument: the term line and its description
+struct Item {
+    term: String,
+    help: String,
+}
+
+fn arg_help(help: Option<&str>,
+            long_help: Option<&str>,
+            possible_values: Option<&Vec<&str>>,
+            default: Option<&std::ffi::OsStr>,
+            env: Option<&std::ffi::OsStr>) -> String {
+    let mut help = escape(long_help.or(help).unwrap_or(""));
+    if let Some(values) = possible_values {
+        help.push_str(&format!("\n.br\nPossible values: {}", escape(&values.join(", "))));
+    }
+    if let Some(default) = default {
+        help.push_str(&format!("\n.br\nDefault: {}", escape(&default.to_string_lossy())));
+    }
+    if let Some(env) = env {
+        help.push_str(&format!("\n.br\nEnvironment: \\fB{}\\fR", escape(&env.to_string_lossy())));
+    }
+    help
+}
+
+fn switch(short: Option<char>, long: Option<&str>) -> String {
+    let mut names = Vec::new();
+    if let Some(short) = short {
+        names.push(format!("\\fB\\-{}\\fR", short));
+    }
+    if let Some(long) = long {
+        names.push(format!("\\fB\\-\\-{}\\fR", escape(long)));
+    }
+    names.join(", ")
+}
+
+fn options(app: &App) -> Vec<Item> {
+    let parser = &app.p;
+    let mut items = parser.flags
+        .iter()
+        .filter(|x| !x.b.is_set(ArgSettings::Hidden))
+        .map(|x| (x.s.disp_ord, x.s.unified_ord, Item {
+            term: switch(x.s.short, x.s.long),
+            help: arg_help(x.b.help, x.b.long_help, None, None, None),
+        }))
+        .collect::<Vec<_>>();
+    items.extend(parser.opts
+        .iter()
+        .filter(|x| !x.b.is_set(ArgSettings::Hidden))
+        .map(|x| {
+            let value = x.v.val_names
+                .as_ref()
+                .and_then(|names| names.values().next().cloned())
+                .unwrap_or(x.b.name)
+                .to_uppercase();
+            (x.s.disp_ord, x.s.unified_ord, Item {
+                term: format!("{}=\\fI{}\\fR", switch(x.s.short, x.s.long), escape(&value)),
+                help: arg_help(x.b.help,
+                               x.b.long_help,
+                               x.v.possible_vals.as_ref(),
+                               x.v.default_val,
+                               x.v.env.as_ref().map(|(name, _)| *name)),
+            })
+        }));
+    items.sort_by_key(|x| (x.0, x.1));
+    items.into_iter().map(|(_, _, item)| item).collect()
+}
+
+fn positionals(app: &App) -> Vec<Item> {
+    app.p.positionals
+        .values()
+        .filter(|x| !x.b.is_set(ArgSettings::Hidden))
+        .map(|x| {
+            let name = escape(&x.b.name.to_uppercase());
+            Item {
+                term: if x.b.is_set(ArgSettings::Required) {
+                    format!("\\fI{}\\fR", name)
+                } else {
+                    format!("[\\fI{}\\fR]", name)
+                },
+                help: arg_help(x.b.help, x.b.long_help, x.v.possible_vals.as_ref(), x.v.default_val, None),
+            }
+        })
+        .collect()
+}
+
+fn write_items<W: Write>(out: &mut W, items: &[Item]) -> Result<()> {
+    for item in items {
+        writeln!(out, ".TP\n{}\n{}", item.term, item.help)?;
+    }
+    Ok(())
+}
+
+fn synopsis(name: &str, app: &App) -> String {
+    let mut parts = vec![format!("\\fB{}\\fR", escape(name))];
+    if !app.p.flags.is_empty() || !app.p.opts.is_empty() {
+        parts.push("[\\fIOPTIONS\\fR]".into());
+    }
+    parts.extend(positionals(app).into_iter().map(|x| x.term));
+    if app.p.subcommands.iter().any(|x| !x.p.is_set(AppSettings::Hidden)) {
+        parts.push("[\\fISUBCOMMAND\\fR]".into());
+    }
+    parts.join(" ")
+}
+
+/// Document a subcommand and any subcommands nested inside it
+fn write_subcommand<W: Write>(out: &mut W, prefix: &str, app: &App) -> Result<()> {
+    let name = format!("{} {}", prefix, app.p.meta.name);
+    writeln!(out, ".SS \"{}\"", escape(&name))?;
+    writeln!(out, "{}", synopsis(&name, app))?;
+    if let Some(about) = app.p.meta.long_about.or(app.p.meta.about) {
+        writeln!(out, ".PP\n{}", escape(about))?;
+    }
+    write_items(out, &positionals(app))?;
+    write_items(out, &options(app))?;
+    for subcommand in app.p.subcommands.iter().filter(|x| !x.p.is_set(AppSettings::Hidden)) {
+        write_subcommand(out, &name, subcommand)?;
+    }
+    Ok(())
+}
+
+/// Write a roff man page for the command line described by `app`
+pub(crate) fn write_man_page<W: Write>(out: &mut W, app: &App) -> Result<()> {
+    let meta = &app.p.meta;
+    writeln!(out, ".TH {} 1 \"\" \"{} {}\"",
+             BIN_NAME.to_uppercase(),
+             BIN_NAME,
+             env!("CARGO_PKG_VERSION"))?;
+    writeln!(out, ".SH NAME\n{} \\- {}", BIN_NAME, escape(meta.about.unwrap_or("")))?;
+    writeln!(out, ".SH SYNOPSIS\n{}", synopsis(BIN_NAME, app))?;
+    if let Some(about) = meta.long_about {
+        writeln!(out, ".SH DESCRIPTION\n{}", escape(about))?;
+    }
+    let arguments = positionals(app);
+    if !arguments.is_empty() {
+        writeln!(out, ".SH ARGUMENTS")?;
+        write_items(out, &arguments)?;
+    }
+    writeln!(out, ".SH OPTIONS")?;
+    write_items(out, &options(app))?;
+    write_items(out, &[
+        Item { term: switch(Some('h'), Some("help")), help: "Prints help information".into() },
+        Item { term: switch(Some('V'), Some("version")), help: "Prints version information".into() },
+    ])?;
+    let subcommands = app.p.subcommands
+        .iter()
+        .filter(|x| !x.p.is_set(AppSettings::Hidden))
+        .collect::<Vec<_>>();
+    if !subcommands.is_empty() {
+        writeln!(out, ".SH SUBCOMMANDS")?;
+        for subcommand in subcommands {
+            write_subcommand(out, BIN_NAME, subcommand)?;
+        }
+    }
+    if let Some(after) = meta.more_help {
+        // The after help text starts with its own heading, e.g. "EXIT CODES:"
+        let mut lines = after.lines();
+        if let Some(heading) = lines.next() {
+            writeln!(out, ".SH {}", escape(heading.trim_end_matches(':')))?;
+            writeln!(out, ".nf")?;
+            for line in lines {
+                writeln!(out, "{}", escape(line.trim_start()))?;
+            }
+            writeln!(out, ".fi")?;
+        }
+    }
+    Ok(())
+}
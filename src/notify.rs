@@ -0,0 +1,127 @@
+use serde::Serialize;
+
+use crate::check::Movement;
+use crate::report::Report;
+use crate::{log, Result};
+
+#[derive(Debug, Serialize)]
+struct MovementPayload<'a> {
+    directory: &'a str,
+    suite: &'a str,
+    old_percent: f64,
+    new_percent: f64,
+    change: f64,
+    removed: bool,
+}
+
+/// JSON payload describing the metrics that moved between two runs. The
+/// full report is attached unless there's a link to it.
+#[derive(Debug, Serialize)]
+pub(crate) struct Notification<'a> {
+    changeset: &'a str,
+    previous_changeset: &'a str,
+    suite_1: &'a str,
+    suite_2: &'a str,
+    threshold: f64,
+    movements: Vec<MovementPayload<'a>>,
+    report_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    report: Option<&'a Report>,
+}
+
+impl<'a> Notification<'a> {
+    /// `report_url` may contain {changeset}, which is replaced with the
+    /// changeset of the current run
+    pub(crate) fn new(previous: &'a Report,
+                      current: &'a Report,
+                      movements: &'a [Movement],
+                      threshold: f64,
+                      report_url: Option<&str>) -> Notification<'a> {
+        let metadata = &current.metadata;
+        let report_url = report_url.map(|x| x.replace("{changeset}", &metadata.changeset));
+        Notification {
+            changeset: &metadata.changeset,
+            previous_changeset: &previous.metadata.changeset,
+            suite_1: &metadata.suite_1,
+            suite_2: &metadata.suite_2,
+            threshold,
+            movements: movements
+                .iter()
+                .map(|x| MovementPayload {
+                    directory: &x.directory,
+                    suite: &x.suite,
+                    old_percent: x.old_percent,
+                    new_percent: x.new_percent,
+                    change: x.change(),
+                    removed: x.removed,
+                })
+                .collect(),
+            report: if report_url.is_none() { Some(current) } else { None },
+            report_url,
+        }
+    }
+}
+
+/// POST the notification to a webhook as JSON
+pub(crate) fn send_webhook(client: &reqwest::Client, url: &str, notification: &Notification) -> Result<()> {
+    log::debug(format_args!("POST {}", url));
+    client.post(url).json(notification).send()?.error_for_status()?;
+    Ok(())
+}
+
+/// Escape the characters that Slack treats as control sequences
+fn slack_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[derive(Debug, Serialize)]
+struct SlackMessage {
+    text: String,
+}
+
+/// Format a Slack message listing the movement in the totals and the
+/// `limit` directories that moved the most
+fn slack_message(notification: &Notification, limit: usize) -> SlackMessage {
+    let mut text = format!("*Coverage changed: {} vs {}* at `{}` (previous `{}`)\n",
+                           slack_escape(notification.suite_1),
+                           slack_escape(notification.suite_2),
+                           notification.changeset,
+                           notification.previous_changeset);
+    let (totals, mut directories): (Vec<_>, Vec<_>) = notification.movements
+        .iter()
+        .partition(|x| x.directory.is_empty());
+    directories.sort_by(|a, b| {
+        b.change.abs()
+            .partial_cmp(&a.change.abs())
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.directory.cmp(b.directory))
+    });
+    let hidden = directories.len().saturating_sub(limit);
+    directories.truncate(limit);
+    for movement in totals.iter().chain(directories.iter()) {
+        text.push_str(&format!("• `{}`: {} only {:.2}% → {:.2}% ({:+.2}){}\n",
+                               if movement.directory.is_empty() { "total" } else { movement.directory },
+                               slack_escape(movement.suite),
+                               movement.old_percent,
+                               movement.new_percent,
+                               movement.change,
+                               if movement.removed { " (removed)" } else { "" }));
+    }
+    if hidden > 0 {
+        text.push_str(&format!("_and {} more_\n", hidden));
+    }
+    if let Some(ref url) = notification.report_url {
+        text.push_str(&format!("<{}|Full report>\n", url));
+    }
+    SlackMessage { text }
+}
+
+/// POST a summary of the notification to a Slack incoming webhook
+pub(crate) fn send_slack(client: &reqwest::Client,
+                         url: &str,
+                         notification: &Notification,
+                         limit: usize) -> Result<()> {
+    log::debug(format_args!("POST {}", url));
+    client.post(url).json(&slack_message(notification, limit)).send()?.error_for_status()?;
+    Ok(())
+}
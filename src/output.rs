@@ -0,0 +1,503 @@
+use std::env;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::report::{FileRecord, Report, RunMetadata, Totals};
+use crate::{Error, Result};
+
+pub(crate) mod html;
+mod ipc;
+mod metrics;
+mod parquet;
+mod review;
+mod sqlite;
+mod table;
+mod template;
+mod treemap;
+mod vega;
+
+pub(crate) use self::parquet::write_line_matrix_parquet;
+pub(crate) use self::review::write_review_comment;
+pub(crate) use self::sqlite::write_sqlite;
+pub(crate) use self::table::{parse_color_thresholds, ColorThresholds};
+pub(crate) use self::template::write_template;
+
+/// Buffered writer to stdout, which holds the lock for as long as it's used.
+/// Writing through `io::stdout()` directly takes the lock and flushes for
+/// every line, which is slow for reports with many rows. The writer must be
+/// flushed to see errors writing the last of the output.
+pub(crate) fn stdout() -> io::BufWriter<io::StdoutLock<'static>> {
+    io::BufWriter::new(io::stdout().lock())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Format {
+    /// A table when writing to a terminal, otherwise CSV
+    Auto,
+    Table,
+    Csv,
+    Json,
+    Yaml,
+    Ndjson,
+    Markdown,
+    Html,
+    Treemap,
+    Vega,
+    /// Prometheus/OpenMetrics text exposition format
+    Openmetrics,
+    /// InfluxDB line protocol
+    Influx,
+    Parquet,
+    /// Arrow IPC stream
+    Arrow,
+    /// GitHub Actions workflow annotations, which need line level data
+    Gha,
+    /// Short summary for posting as a code review comment
+    ReviewComment,
+    /// path:line: entries for editor quickfix lists, which need line level data
+    Quickfix,
+    /// Written to a database file rather than a stream
+    Sqlite,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Format, String> {
+        match s {
+            "auto" => Ok(Format::Auto),
+            "table" => Ok(Format::Table),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "ndjson" => Ok(Format::Ndjson),
+            "markdown" | "md" => Ok(Format::Markdown),
+            "html" => Ok(Format::Html),
+            "treemap" => Ok(Format::Treemap),
+            "vega" => Ok(Format::Vega),
+            "openmetrics" | "prometheus" => Ok(Format::Openmetrics),
+            "influx" => Ok(Format::Influx),
+            "parquet" => Ok(Format::Parquet),
+            "arrow" => Ok(Format::Arrow),
+            "gha" => Ok(Format::Gha),
+            "review-comment" => Ok(Format::ReviewComment),
+            "quickfix" => Ok(Format::Quickfix),
+            "sqlite" => Ok(Format::Sqlite),
+            _ => Err(format!("Unknown output format {}", s))
+        }
+    }
+}
+
+impl Format {
+    /// Name of the file a format is written to in an output directory
+    pub(crate) fn file_name(self) -> &'static str {
+        match self {
+            Format::Auto | Format::Csv => "report.csv",
+            Format::Table => "report.txt",
+            Format::Json => "report.json",
+            Format::Yaml => "report.yaml",
+            Format::Ndjson => "report.ndjson",
+            Format::Markdown => "report.md",
+            Format::Html => "report.html",
+            Format::Treemap => "treemap.json",
+            Format::Vega => "charts.vl.json",
+            Format::Openmetrics => "metrics.txt",
+            Format::Influx => "metrics.influx",
+            Format::Parquet => "report.parquet",
+            Format::Arrow => "report.arrow",
+            Format::Gha => "annotations.txt",
+            Format::ReviewComment => "review-comment.md",
+            Format::Quickfix => "quickfix.txt",
+            Format::Sqlite => "report.sqlite",
+        }
+    }
+
+    /// Pick a concrete format for Auto depending on whether output is going
+    /// to a terminal
+    pub(crate) fn resolve(self, to_terminal: bool) -> Format {
+        match self {
+            Format::Auto if to_terminal => Format::Table,
+            Format::Auto => Format::Csv,
+            format => format
+        }
+    }
+}
+
+/// Compression applied to output files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Compression, String> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(format!("Unknown compression {}", s))
+        }
+    }
+}
+
+impl Compression {
+    /// Suffix added to the names of compressed files in an output directory
+    pub(crate) fn suffix(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    /// Wrap a writer so that everything written to it is compressed
+    pub(crate) fn writer<W: Write>(self, out: W) -> Result<Compressed<W>> {
+        Ok(match self {
+            Compression::None => Compressed::Plain(out),
+            Compression::Gzip => Compressed::Gzip(flate2::write::GzEncoder::new(out, flate2::Compression::default())),
+            Compression::Zstd => Compressed::Zstd(zstd::Encoder::new(out, 0)?),
+        })
+    }
+}
+
+/// A writer that compresses what's written to it, if compression was
+/// requested. It has to be ended with `finish` rather than just flushed,
+/// since that writes the end of the compressed stream, and any error doing
+/// so would be lost if that was left until the writer is dropped.
+pub(crate) enum Compressed<W: Write> {
+    Plain(W),
+    Gzip(flate2::write::GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Compressed<W> {
+    /// End the compressed stream and flush the underlying writer
+    pub(crate) fn finish(self) -> io::Result<W> {
+        let mut out = match self {
+            Compressed::Plain(out) => out,
+            Compressed::Gzip(encoder) => encoder.finish()?,
+            Compressed::Zstd(encoder) => encoder.finish()?,
+        };
+        out.flush()?;
+        Ok(out)
+    }
+}
+
+impl<W: Write> Write for Compressed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Compressed::Plain(out) => out.write(buf),
+            Compressed::Gzip(encoder) => encoder.write(buf),
+            Compressed::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Compressed::Plain(out) => out.flush(),
+            Compressed::Gzip(encoder) => encoder.flush(),
+            Compressed::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// When to use ANSI colors in terminal output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<ColorChoice, String> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("Unknown color choice {}, expected auto, always or never", s))
+        }
+    }
+}
+
+impl ColorChoice {
+    /// Whether to use colors. auto uses them when writing to a terminal,
+    /// unless the NO_COLOR environment variable is set to a non-empty value.
+    pub(crate) fn enabled(self, terminal: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => terminal && env::var_os("NO_COLOR").map(|x| x.is_empty()).unwrap_or(true),
+        }
+    }
+}
+
+/// Options that apply to the tabular output formats
+#[derive(Debug, Clone)]
+pub(crate) struct OutputOptions {
+    /// Whether to write a header row
+    pub header: bool,
+    /// Field separator for CSV output
+    pub delimiter: u8,
+    /// Only include this many files, those with the most suite 2 only lines
+    pub top: Option<usize>,
+    /// Whether the output is written to a terminal
+    pub terminal: bool,
+    /// Whether to use ANSI colors in terminal output
+    pub color: bool,
+    /// Cut-offs for coloring unique coverage percentages
+    pub color_thresholds: ColorThresholds,
+    /// Whether to embed a treemap in HTML output
+    pub treemap: bool,
+    /// Whether to include a column of searchfox links
+    pub searchfox: bool,
+    /// Whether to make paths in terminal output clickable with OSC 8 hyperlinks
+    pub hyperlinks: bool,
+    /// Prefix of links from each path in HTML output to a page for the file
+    pub file_links: Option<String>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> OutputOptions {
+        OutputOptions {
+            header: true,
+            delimiter: b',',
+            top: None,
+            terminal: false,
+            color: false,
+            color_thresholds: ColorThresholds::default(),
+            treemap: false,
+            searchfox: false,
+            hyperlinks: false,
+            file_links: None,
+        }
+    }
+}
+
+/// Parse a --delimiter value, which is either a single ASCII character or
+/// one of the names tab, comma, semicolon or pipe.
+pub(crate) fn parse_delimiter(value: &str) -> std::result::Result<u8, String> {
+    match value {
+        "tab" | "\\t" | "\t" => Ok(b'\t'),
+        "comma" => Ok(b','),
+        "semicolon" => Ok(b';'),
+        "pipe" => Ok(b'|'),
+        _ if value.len() == 1 && value.is_ascii() => Ok(value.as_bytes()[0]),
+        _ => Err(format!("Invalid delimiter {}", value))
+    }
+}
+
+/// Link to a file on searchfox, pinned to the changeset that was analyzed
+pub(crate) fn searchfox_url(changeset: &str, path: &str) -> String {
+    format!("https://searchfox.org/mozilla-central/rev/{}/{}", changeset, path)
+}
+
+/// Create a CSV writer configured according to the output options;
+/// headers are written explicitly by the caller
+pub(crate) fn csv_writer<W: Write>(out: W, options: &OutputOptions) -> csv::Writer<W> {
+    csv::WriterBuilder::new()
+        .has_headers(false)
+        .delimiter(options.delimiter)
+        .from_writer(out)
+}
+
+fn write_csv<W: Write>(out: &mut W, report: &Report, options: &OutputOptions) -> Result<()> {
+    let with_wpt_tests = report.has_wpt_tests();
+    let metadata = &report.metadata;
+    let mut writer = csv_writer(out, options);
+
+    if options.header {
+        let mut header = vec![
+            "path".to_owned(),
+            format!("{} only", metadata.suite_1),
+            format!("{} only", metadata.suite_2),
+            "both".to_owned(),
+            "total covered".to_owned(),
+            "total coverable".to_owned(),
+            "total lines".to_owned(),
+            format!("{}-only percent", metadata.suite_1),
+            format!("{}-only percent", metadata.suite_2),
+            "coverage percent".to_owned(),
+        ];
+        if with_wpt_tests {
+            header.push("wpt tests".to_owned());
+            header.push("wpt tests failing".to_owned());
+            header.push("wpt subtests".to_owned());
+            header.push("wpt subtests failing".to_owned());
+        }
+        if options.searchfox {
+            header.push("searchfox".to_owned());
+        }
+        writer.write_record(&header)?;
+    }
+
+    for file in report.files.iter() {
+        let mut row = vec![
+            file.path.clone(),
+            file.suite_1_only.to_string(),
+            file.suite_2_only.to_string(),
+            file.both.to_string(),
+            file.covered.to_string(),
+            file.coverable.to_string(),
+            file.lines.to_string(),
+            file.suite_1_only_percent.to_string(),
+            file.suite_2_only_percent.to_string(),
+            file.covered_percent.to_string(),
+        ];
+        if with_wpt_tests {
+            row.push(file.wpt_tests.unwrap_or(0).to_string());
+            row.push(file.wpt_tests_failing.unwrap_or(0).to_string());
+            row.push(file.wpt_subtests.unwrap_or(0).to_string());
+            row.push(file.wpt_subtests_failing.unwrap_or(0).to_string());
+        }
+        if options.searchfox {
+            row.push(searchfox_url(&metadata.changeset, &file.path));
+        }
+        writer.write_record(&row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Get the files to include in summary formats, honouring --top
+pub(crate) fn selected_files<'a>(report: &'a Report, options: &OutputOptions) -> Vec<&'a FileRecord> {
+    let mut files = report.files.iter().collect::<Vec<_>>();
+    if let Some(top) = options.top {
+        files.sort_by(|a, b| b.suite_2_only.cmp(&a.suite_2_only).then_with(|| a.path.cmp(&b.path)));
+        files.truncate(top);
+    }
+    files
+}
+
+pub(crate) fn markdown_escape(value: &str) -> String {
+    value.replace('|', "\\|")
+}
+
+fn write_markdown<W: Write>(out: &mut W, report: &Report, options: &OutputOptions) -> Result<()> {
+    let metadata = &report.metadata;
+    let with_wpt_tests = report.has_wpt_tests();
+
+    write!(out, "| path | {} only | {} only | both | covered | coverable | {} only % | {} only % | covered % |",
+           markdown_escape(&metadata.suite_1),
+           markdown_escape(&metadata.suite_2),
+           markdown_escape(&metadata.suite_1),
+           markdown_escape(&metadata.suite_2))?;
+    if with_wpt_tests {
+        write!(out, " wpt tests | wpt tests failing | wpt subtests | wpt subtests failing |")?;
+    }
+    writeln!(out)?;
+    write!(out, "|:---|---:|---:|---:|---:|---:|---:|---:|---:|")?;
+    if with_wpt_tests {
+        write!(out, "---:|---:|---:|---:|")?;
+    }
+    writeln!(out)?;
+
+    for file in selected_files(report, options) {
+        let path = if options.searchfox {
+            format!("[`{}`]({})", markdown_escape(&file.path), searchfox_url(&metadata.changeset, &file.path))
+        } else {
+            format!("`{}`", markdown_escape(&file.path))
+        };
+        // Percentages of no coverable lines are left blank
+        let percent = |value: f64| if file.coverable > 0 { format!("{:.1}", value) } else { String::new() };
+        write!(out, "| {} | {} | {} | {} | {} | {} | {} | {} | {} |",
+               path,
+               file.suite_1_only,
+               file.suite_2_only,
+               file.both,
+               file.covered,
+               file.coverable,
+               percent(file.suite_1_only_percent),
+               percent(file.suite_2_only_percent),
+               percent(file.covered_percent))?;
+        if with_wpt_tests {
+            write!(out, " {} | {} | {} | {} |",
+                   file.wpt_tests.unwrap_or(0),
+                   file.wpt_tests_failing.unwrap_or(0),
+                   file.wpt_subtests.unwrap_or(0),
+                   file.wpt_subtests_failing.unwrap_or(0))?;
+        }
+        writeln!(out)?;
+    }
+    if !report.warnings.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "**Warnings**")?;
+        writeln!(out)?;
+        for warning in report.warnings.iter() {
+            writeln!(out, "- {}", markdown_escape(&warning.to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+fn write_json<W: Write>(out: &mut W, report: &Report) -> Result<()> {
+    serde_json::to_writer_pretty(&mut *out, report)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+fn write_yaml<W: Write>(out: &mut W, report: &Report) -> Result<()> {
+    serde_yaml::to_writer(&mut *out, report)?;
+    Ok(())
+}
+
+/// Write just the aggregate counts for the run, without any per-file rows
+pub(crate) fn write_summary<W: Write>(out: &mut W, metadata: &RunMetadata, totals: &Totals) -> Result<()> {
+    writeln!(out, "{} vs {} at {}", metadata.suite_1, metadata.suite_2, metadata.changeset)?;
+    if let Some(push_date) = metadata.push_date {
+        writeln!(out, "  pushed: {}", push_date.format("%Y-%m-%d %H:%M UTC"))?;
+    }
+    writeln!(out, "  files: {}", totals.files)?;
+    writeln!(out, "  coverable lines: {}", totals.coverable)?;
+    writeln!(out, "  covered: {} ({:.2}%)", totals.covered, totals.covered_percent())?;
+    writeln!(out, "  {} only: {} ({:.2}%)", metadata.suite_1, totals.suite_1_only, totals.suite_1_only_percent())?;
+    writeln!(out, "  {} only: {} ({:.2}%)", metadata.suite_2, totals.suite_2_only, totals.suite_2_only_percent())?;
+    writeln!(out, "  both: {}", totals.both)?;
+    Ok(())
+}
+
+/// Write a single file record as a line of newline-delimited JSON
+pub(crate) fn write_ndjson_record<W: Write>(out: &mut W, record: &FileRecord) -> Result<()> {
+    serde_json::to_writer(&mut *out, record)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+fn write_ndjson<W: Write>(out: &mut W, report: &Report) -> Result<()> {
+    for record in report.files.iter() {
+        write_ndjson_record(out, record)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_report<W: Write>(out: &mut W,
+                                     format: Format,
+                                     options: &OutputOptions,
+                                     report: &Report) -> Result<()> {
+    match format.resolve(options.terminal) {
+        Format::Auto => unreachable!(),
+        Format::Table => table::write_table(out, report, options),
+        Format::Csv => write_csv(out, report, options),
+        Format::Json => write_json(out, report),
+        Format::Yaml => write_yaml(out, report),
+        Format::Ndjson => write_ndjson(out, report),
+        Format::Markdown => write_markdown(out, report, options),
+        Format::Html => html::write_html(out, report, options),
+        Format::Treemap => treemap::write_treemap(out, report),
+        Format::Vega => vega::write_vega(out, report, options),
+        Format::Openmetrics => metrics::write_openmetrics(out, report),
+        Format::Influx => metrics::write_influx(out, report),
+        Format::Parquet => parquet::write_parquet(out, report),
+        Format::Arrow => ipc::write_arrow(out, report),
+        Format::Gha => Err(Error::Usage("The gha format can only be written from line level data".into())),
+        Format::Quickfix => Err(Error::Usage("The quickfix format can only be written from line level data".into())),
+        Format::ReviewComment => write_review_comment(out, report, None, options.top.unwrap_or(10)),
+        Format::Sqlite => Err(Error::Usage("The sqlite format can only be written to a file".into())),
+    }
+}
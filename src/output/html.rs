@@ -0,0 +1,264 @@
+use std::io::Write;
+
+use super::{searchfox_url, selected_files, treemap, OutputOptions};
+use crate::report::Report;
+use crate::Result;
+
+/// Escape text for use in HTML element content or attribute values
+pub(crate) fn escape(value: &str) -> String {
+    let mut rv = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => rv.push_str("&amp;"),
+            '<' => rv.push_str("&lt;"),
+            '>' => rv.push_str("&gt;"),
+            '"' => rv.push_str("&quot;"),
+            '\'' => rv.push_str("&#39;"),
+            c => rv.push(c),
+        }
+    }
+    rv
+}
+
+/// Serialize a value as JSON that's safe to embed in a <script> element
+pub(crate) fn script_json<T: serde::Serialize>(value: &T) -> Result<String> {
+    Ok(serde_json::to_string(value)?.replace("</", "<\\/"))
+}
+
+const STYLE: &str = r##"
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+th, td { padding: 0.2em 0.6em; border-bottom: 1px solid #ddd; }
+th { cursor: pointer; background: #f4f4f4; position: sticky; top: 0; }
+td.num { text-align: right; font-variant-numeric: tabular-nums; }
+dl.summary { display: grid; grid-template-columns: max-content auto; gap: 0.2em 1em; }
+dl.summary dt { font-weight: bold; }
+#filter { margin: 1em 0; width: 30em; }
+"##;
+
+const SCRIPT: &str = r##"
+(function() {
+  const data = JSON.parse(document.getElementById("report-data").textContent);
+  const columns = data.columns;
+  const rows = data.rows;
+  const body = document.querySelector("#files tbody");
+  const filter = document.getElementById("filter");
+  let sortKey = null;
+  let sortDesc = false;
+
+  function render() {
+    const needle = filter.value;
+    let visible = rows.filter(row => row.path.includes(needle));
+    if (sortKey !== null) {
+      visible.sort((a, b) => {
+        const x = a[sortKey], y = b[sortKey];
+        const cmp = typeof x === "string" ? x.localeCompare(y) : x - y;
+        return sortDesc ? -cmp : cmp;
+      });
+    }
+    body.textContent = "";
+    for (const row of visible) {
+      const tr = document.createElement("tr");
+      for (const column of columns) {
+        const td = document.createElement("td");
+        const value = row[column.key];
+        if (column.percent) {
+          td.textContent = value === null || row.coverable === 0 ? "" : value.toFixed(1);
+        } else if (column.key === "path" && data.file_links !== null) {
+          const a = document.createElement("a");
+          a.href = data.file_links + value;
+          a.textContent = value;
+          td.appendChild(a);
+        } else if (column.key === "searchfox") {
+          const a = document.createElement("a");
+          a.href = value;
+          a.textContent = "searchfox";
+          td.appendChild(a);
+        } else {
+          td.textContent = value === null ? "" : value;
+        }
+        if (column.key !== "path" && column.key !== "searchfox") {
+          td.className = "num";
+        }
+        tr.appendChild(td);
+      }
+      body.appendChild(tr);
+    }
+    document.getElementById("count").textContent = visible.length + " of " + rows.length + " files";
+  }
+
+  document.querySelectorAll("#files th").forEach((th, i) => {
+    th.addEventListener("click", () => {
+      const key = columns[i].key;
+      sortDesc = sortKey === key ? !sortDesc : key !== "path";
+      sortKey = key;
+      render();
+    });
+  });
+  filter.addEventListener("input", render);
+  render();
+})();
+"##;
+
+const TREEMAP_STYLE: &str = r##"
+#treemap { position: relative; width: 100%; height: 500px; margin: 1em 0; }
+#treemap div { position: absolute; box-sizing: border-box; border: 1px solid #fff; overflow: hidden;
+               font-size: 11px; padding: 2px; white-space: nowrap; }
+"##;
+
+/// Slice-and-dice treemap layout, alternating direction with depth and
+/// colored from grey (no suite 2 only coverage) to green
+const TREEMAP_SCRIPT: &str = r##"
+(function() {
+  const tree = JSON.parse(document.getElementById("treemap-data").textContent);
+  const container = document.getElementById("treemap");
+  const maxDepth = 3;
+
+  function color(percent) {
+    const t = Math.min(percent / 50, 1);
+    const r = Math.round(220 - 180 * t), g = Math.round(220 - 40 * t), b = Math.round(220 - 180 * t);
+    return "rgb(" + r + "," + g + "," + b + ")";
+  }
+
+  function layout(node, x, y, w, h, depth) {
+    if (w <= 0 || h <= 0) {
+      return;
+    }
+    const children = (node.children || []).filter(c => c.coverable > 0);
+    if (depth > 0 && (depth >= maxDepth || children.length === 0)) {
+      const div = document.createElement("div");
+      div.style.left = x + "%";
+      div.style.top = y + "%";
+      div.style.width = w + "%";
+      div.style.height = h + "%";
+      div.style.background = color(node.suite_2_only_percent);
+      div.title = node.path + ": " + node.coverable + " coverable lines, " +
+        node.suite_2_only_percent.toFixed(1) + "% only covered by suite 2";
+      div.textContent = node.name;
+      container.appendChild(div);
+      return;
+    }
+    let offset = 0;
+    for (const child of children) {
+      const fraction = child.coverable / node.coverable;
+      if (depth % 2 === 0) {
+        layout(child, x + offset * w, y, fraction * w, h, depth + 1);
+      } else {
+        layout(child, x, y + offset * h, w, fraction * h, depth + 1);
+      }
+      offset += fraction;
+    }
+  }
+
+  layout(tree, 0, 0, 100, 100, 0);
+})();
+"##;
+
+#[derive(serde::Serialize)]
+struct Column {
+    key: &'static str,
+    title: String,
+    percent: bool,
+}
+
+/// Write the report as a single self-contained HTML page with a sortable,
+/// filterable table of files.
+pub(crate) fn write_html<W: Write>(out: &mut W, report: &Report, options: &OutputOptions) -> Result<()> {
+    let metadata = &report.metadata;
+    let totals = report.totals();
+
+    let mut columns = vec![
+        Column { key: "path", title: "path".into(), percent: false },
+        Column { key: "suite_1_only", title: format!("{} only", metadata.suite_1), percent: false },
+        Column { key: "suite_2_only", title: format!("{} only", metadata.suite_2), percent: false },
+        Column { key: "both", title: "both".into(), percent: false },
+        Column { key: "covered", title: "covered".into(), percent: false },
+        Column { key: "coverable", title: "coverable".into(), percent: false },
+        Column { key: "suite_1_only_percent", title: format!("{} only %", metadata.suite_1), percent: true },
+        Column { key: "suite_2_only_percent", title: format!("{} only %", metadata.suite_2), percent: true },
+        Column { key: "covered_percent", title: "covered %".into(), percent: true },
+    ];
+    if report.has_wpt_tests() {
+        columns.push(Column { key: "wpt_tests", title: "wpt tests".into(), percent: false });
+        columns.push(Column { key: "wpt_tests_failing", title: "wpt tests failing".into(), percent: false });
+        columns.push(Column { key: "wpt_subtests", title: "wpt subtests".into(), percent: false });
+        columns.push(Column { key: "wpt_subtests_failing", title: "wpt subtests failing".into(), percent: false });
+    }
+
+    if options.searchfox {
+        columns.push(Column { key: "searchfox", title: "searchfox".into(), percent: false });
+    }
+
+    let rows = selected_files(report, options)
+        .into_iter()
+        .map(|file| {
+            let mut row = serde_json::to_value(file)?;
+            if options.searchfox {
+                row["searchfox"] = searchfox_url(&metadata.changeset, &file.path).into();
+            }
+            Ok(row)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let data = serde_json::json!({
+        "columns": columns,
+        "rows": rows,
+        "file_links": options.file_links,
+    });
+
+    let title = format!("Coverage difference: {} vs {}", metadata.suite_1, metadata.suite_2);
+
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html lang=\"en\">")?;
+    writeln!(out, "<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>{}</title>", escape(&title))?;
+    writeln!(out, "<style>{}</style>", STYLE)?;
+    writeln!(out, "<h1>{}</h1>", escape(&title))?;
+    writeln!(out, "<dl class=\"summary\">")?;
+    let summary: [(String, String); 10] = [
+        ("Changeset".into(), metadata.changeset.clone()),
+        ("Pushed".into(), metadata.push_date.map(|x| x.format("%Y-%m-%d %H:%M UTC").to_string()).unwrap_or_default()),
+        ("Generated".into(), metadata.date.format("%Y-%m-%d %H:%M UTC").to_string()),
+        ("Base paths".into(), metadata.base_paths.join(", ")),
+        ("Files".into(), totals.files.to_string()),
+        ("Coverable lines".into(), totals.coverable.to_string()),
+        ("Covered lines".into(), format!("{} ({:.1}%)", totals.covered, totals.covered_percent())),
+        (format!("Only covered by {}", metadata.suite_1),
+         format!("{} ({:.1}%)", totals.suite_1_only, totals.suite_1_only_percent())),
+        (format!("Only covered by {}", metadata.suite_2),
+         format!("{} ({:.1}%)", totals.suite_2_only, totals.suite_2_only_percent())),
+        ("Covered by both".into(), totals.both.to_string()),
+    ];
+    for (name, value) in summary.iter() {
+        writeln!(out, "<dt>{}</dt><dd>{}</dd>", escape(name), escape(value))?;
+    }
+    writeln!(out, "</dl>")?;
+    if !report.warnings.is_empty() {
+        writeln!(out, "<h2>Warnings</h2>")?;
+        writeln!(out, "<ul class=\"warnings\">")?;
+        for warning in report.warnings.iter() {
+            writeln!(out, "<li>{}</li>", escape(&warning.to_string()))?;
+        }
+        writeln!(out, "</ul>")?;
+    }
+    if options.treemap {
+        writeln!(out, "<style>{}</style>", TREEMAP_STYLE)?;
+        writeln!(out, "<div id=\"treemap\"></div>")?;
+        writeln!(out, "<script type=\"application/json\" id=\"treemap-data\">{}</script>",
+                 script_json(&treemap::build_tree(report))?)?;
+        writeln!(out, "<script>{}</script>", TREEMAP_SCRIPT)?;
+    }
+    writeln!(out, "<input id=\"filter\" type=\"search\" placeholder=\"Filter by path\"> <span id=\"count\"></span>")?;
+    writeln!(out, "<table id=\"files\">")?;
+    write!(out, "<thead><tr>")?;
+    for column in columns.iter() {
+        write!(out, "<th>{}</th>", escape(&column.title))?;
+    }
+    writeln!(out, "</tr></thead>")?;
+    writeln!(out, "<tbody></tbody>")?;
+    writeln!(out, "</table>")?;
+    writeln!(out, "<script type=\"application/json\" id=\"report-data\">{}</script>", script_json(&data)?)?;
+    writeln!(out, "<script>{}</script>", SCRIPT)?;
+    writeln!(out, "</html>")?;
+    Ok(())
+}
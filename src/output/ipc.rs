@@ -0,0 +1,17 @@
+use std::io::Write;
+
+use arrow_ipc::writer::StreamWriter;
+
+use super::parquet::record_batch;
+use crate::report::Report;
+use crate::Result;
+
+/// Write the per-file records as an Arrow IPC stream, which can be read
+/// directly by e.g. DuckDB, polars or pyarrow.ipc.open_stream
+pub(crate) fn write_arrow<W: Write>(out: &mut W, report: &Report) -> Result<()> {
+    let batch = record_batch(report)?;
+    let mut writer = StreamWriter::try_new(&mut *out, &batch.schema())?;
+    writer.write(&batch)?;
+    writer.finish()?;
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use std::io::Write;
+
+use crate::report::{Report, Totals};
+use crate::Result;
+
+fn label_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn ratio(count: i64, total: i64) -> f64 {
+    if total > 0 {
+        count as f64 / total as f64
+    } else {
+        0f64
+    }
+}
+
+const GAUGES: &[(&str, &str)] = &[
+    ("coverable_lines", "Lines that are instrumented in either suite"),
+    ("covered_lines", "Lines covered by either suite"),
+    ("suite_1_only_lines", "Lines only covered by suite 1"),
+    ("suite_2_only_lines", "Lines only covered by suite 2"),
+    ("both_lines", "Lines covered by both suites"),
+    ("suite_1_only_ratio", "Fraction of coverable lines only covered by suite 1"),
+    ("suite_2_only_ratio", "Fraction of coverable lines only covered by suite 2"),
+];
+
+fn gauge_value(name: &str, totals: &Totals) -> f64 {
+    match name {
+        "coverable_lines" => totals.coverable as f64,
+        "covered_lines" => totals.covered as f64,
+        "suite_1_only_lines" => totals.suite_1_only as f64,
+        "suite_2_only_lines" => totals.suite_2_only as f64,
+        "both_lines" => totals.both as f64,
+        "suite_1_only_ratio" => ratio(totals.suite_1_only, totals.coverable),
+        "suite_2_only_ratio" => ratio(totals.suite_2_only, totals.coverable),
+        _ => unreachable!()
+    }
+}
+
+/// Write gauges for the totals and each top-level directory in the
+/// OpenMetrics text format. The totals have an empty directory label, which
+/// Prometheus treats the same as no label.
+pub(crate) fn write_openmetrics<W: Write>(out: &mut W, report: &Report) -> Result<()> {
+    let metadata = &report.metadata;
+    let directories = report.top_level_totals();
+    for (name, help) in GAUGES.iter() {
+        writeln!(out, "# TYPE wptcoverage_{} gauge", name)?;
+        writeln!(out, "# HELP wptcoverage_{} {}", name, help)?;
+        for (dir, totals) in directories.iter() {
+            writeln!(out, "wptcoverage_{}{{suite_1=\"{}\",suite_2=\"{}\",changeset=\"{}\",directory=\"{}\"}} {}",
+                     name,
+                     label_escape(&metadata.suite_1),
+                     label_escape(&metadata.suite_2),
+                     label_escape(&metadata.changeset),
+                     label_escape(dir),
+                     gauge_value(name, totals))?;
+        }
+    }
+    writeln!(out, "# EOF")?;
+    Ok(())
+}
+
+/// Escape a tag key or value in InfluxDB line protocol
+fn tag_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+/// Write a `wptcoverage` measurement for the totals and each top-level
+/// directory in InfluxDB line protocol, timestamped with the time of the
+/// run. The totals have a directory tag of `/`, since tags can't be empty.
+pub(crate) fn write_influx<W: Write>(out: &mut W, report: &Report) -> Result<()> {
+    let metadata = &report.metadata;
+    let timestamp = metadata.date.timestamp_nanos_opt().unwrap_or(0);
+    for (dir, totals) in report.top_level_totals() {
+        writeln!(out, "wptcoverage,suite_1={},suite_2={},directory={},changeset={} \
+                       coverable={}i,covered={}i,suite_1_only={}i,suite_2_only={}i,both={}i,\
+                       suite_1_only_ratio={},suite_2_only_ratio={} {}",
+                 tag_escape(&metadata.suite_1),
+                 tag_escape(&metadata.suite_2),
+                 tag_escape(if dir.is_empty() { "/" } else { &dir }),
+                 tag_escape(&metadata.changeset),
+                 totals.coverable,
+                 totals.covered,
+                 totals.suite_1_only,
+                 totals.suite_2_only,
+                 totals.both,
+                 ratio(totals.suite_1_only, totals.coverable),
+                 ratio(totals.suite_2_only, totals.coverable),
+                 timestamp)?;
+    }
+    Ok(())
+}
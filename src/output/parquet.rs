@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, Float64Array, Int64Array, RecordBatch, StringArray, UInt32Array, UInt64Array};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+use crate::export::LineRow;
+use crate::report::{FileRecord, Report};
+use crate::Result;
+
+/// Build a record batch with one row per file. The run metadata is attached
+/// to the schema so it survives conversion into other tools.
+pub(super) fn record_batch(report: &Report) -> Result<RecordBatch> {
+    let files = &report.files;
+    let metadata = &report.metadata;
+
+    let int_column = |f: fn(&FileRecord) -> i64| -> ArrayRef {
+        Arc::new(files.iter().map(f).collect::<Int64Array>())
+    };
+    let float_column = |f: fn(&FileRecord) -> f64| -> ArrayRef {
+        Arc::new(files.iter().map(f).collect::<Float64Array>())
+    };
+
+    let mut schema_metadata = HashMap::new();
+    schema_metadata.insert("changeset".to_owned(), metadata.changeset.clone());
+    schema_metadata.insert("suite_1".to_owned(), metadata.suite_1.clone());
+    schema_metadata.insert("suite_2".to_owned(), metadata.suite_2.clone());
+    schema_metadata.insert("date".to_owned(), metadata.date.to_rfc3339());
+    schema_metadata.insert("base_paths".to_owned(), metadata.base_paths.join(","));
+
+    let schema = Schema::new(vec![
+        Field::new("path", DataType::Utf8, false),
+        Field::new("suite_1_only", DataType::Int64, false),
+        Field::new("suite_2_only", DataType::Int64, false),
+        Field::new("both", DataType::Int64, false),
+        Field::new("covered", DataType::Int64, false),
+        Field::new("coverable", DataType::Int64, false),
+        Field::new("lines", DataType::Int64, false),
+        Field::new("suite_1_only_percent", DataType::Float64, false),
+        Field::new("suite_2_only_percent", DataType::Float64, false),
+        Field::new("covered_percent", DataType::Float64, false),
+        Field::new("wpt_tests", DataType::UInt64, true),
+        Field::new("wpt_tests_failing", DataType::UInt64, true),
+        Field::new("wpt_subtests", DataType::UInt64, true),
+        Field::new("wpt_subtests_failing", DataType::UInt64, true),
+    ]).with_metadata(schema_metadata);
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(files.iter().map(|x| Some(x.path.as_str())).collect::<StringArray>()),
+        int_column(|x| x.suite_1_only),
+        int_column(|x| x.suite_2_only),
+        int_column(|x| x.both),
+        int_column(|x| x.covered),
+        int_column(|x| x.coverable),
+        int_column(|x| x.lines),
+        float_column(|x| x.suite_1_only_percent),
+        float_column(|x| x.suite_2_only_percent),
+        float_column(|x| x.covered_percent),
+        Arc::new(files.iter().map(|x| x.wpt_tests).collect::<UInt64Array>()),
+        Arc::new(files.iter().map(|x| x.wpt_tests_failing).collect::<UInt64Array>()),
+        Arc::new(files.iter().map(|x| x.wpt_subtests).collect::<UInt64Array>()),
+        Arc::new(files.iter().map(|x| x.wpt_subtests_failing).collect::<UInt64Array>()),
+    ];
+
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+/// Write the per-file records as a snappy-compressed Parquet file
+pub(crate) fn write_parquet<W: Write>(out: &mut W, report: &Report) -> Result<()> {
+    write_batch(out, &record_batch(report)?)
+}
+
+/// Write per-line hit counts as a snappy-compressed Parquet file
+pub(crate) fn write_line_matrix_parquet<W: Write>(out: &mut W, rows: &[LineRow]) -> Result<()> {
+    let schema = Schema::new(vec![
+        Field::new("path", DataType::Utf8, false),
+        Field::new("line", DataType::UInt32, false),
+        Field::new("suite1_hits", DataType::Int64, false),
+        Field::new("suite2_hits", DataType::Int64, false),
+        Field::new("category", DataType::Utf8, false),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(rows.iter().map(|x| Some(x.path)).collect::<StringArray>()),
+        Arc::new(rows.iter().map(|x| x.line as u32).collect::<UInt32Array>()),
+        Arc::new(rows.iter().map(|x| x.suite_1_hits).collect::<Int64Array>()),
+        Arc::new(rows.iter().map(|x| x.suite_2_hits).collect::<Int64Array>()),
+        Arc::new(rows.iter().map(|x| Some(x.category.name())).collect::<StringArray>()),
+    ];
+    write_batch(out, &RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+fn write_batch<W: Write>(out: &mut W, batch: &RecordBatch) -> Result<()> {
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    // The writer needs to own a Send sink, so buffer the file in memory
+    let mut writer = ArrowWriter::try_new(Vec::new(), batch.schema(), Some(properties))?;
+    writer.write(batch)?;
+    let data = writer.into_inner()?;
+    out.write_all(&data)?;
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use super::markdown_escape;
+use crate::report::{FileRecord, Report};
+use crate::Result;
+
+fn signed(value: i64) -> String {
+    if value > 0 {
+        format!("+{}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Write a short summary suitable for posting as a single review comment.
+/// This only uses syntax that renders the same in Markdown and Remarkup, so
+/// there are no headings. When a base report is supplied the `limit` files
+/// whose suite 2 only line count increased the most are listed, otherwise
+/// the files with the most suite 2 only lines are listed.
+pub(crate) fn write_review_comment<W: Write>(out: &mut W,
+                                             report: &Report,
+                                             base: Option<&Report>,
+                                             limit: usize) -> Result<()> {
+    let metadata = &report.metadata;
+    let totals = report.totals();
+
+    writeln!(out, "**Coverage: {} vs {}** at `{}`",
+             markdown_escape(&metadata.suite_1),
+             markdown_escape(&metadata.suite_2),
+             metadata.changeset)?;
+    writeln!(out)?;
+    writeln!(out, "| | lines | % |")?;
+    writeln!(out, "|:---|---:|---:|")?;
+    writeln!(out, "| coverable | {} | |", totals.coverable)?;
+    writeln!(out, "| covered | {} | {:.1} |", totals.covered, totals.covered_percent())?;
+    writeln!(out, "| {} only | {} | {:.1} |",
+             markdown_escape(&metadata.suite_1), totals.suite_1_only, totals.suite_1_only_percent())?;
+    writeln!(out, "| {} only | {} | {:.1} |",
+             markdown_escape(&metadata.suite_2), totals.suite_2_only, totals.suite_2_only_percent())?;
+    writeln!(out)?;
+
+    match base {
+        Some(base) => {
+            let base_totals = base.totals();
+            writeln!(out, "Compared to `{}`: {} {} only lines ({:+.1} percentage points)",
+                     base.metadata.changeset,
+                     signed(totals.suite_2_only - base_totals.suite_2_only),
+                     markdown_escape(&metadata.suite_2),
+                     totals.suite_2_only_percent() - base_totals.suite_2_only_percent())?;
+            writeln!(out)?;
+
+            let base_files = base.files
+                .iter()
+                .map(|x| (x.path.as_str(), x))
+                .collect::<BTreeMap<&str, &FileRecord>>();
+            let mut regressions = report.files
+                .iter()
+                .map(|x| {
+                    let old = base_files.get(x.path.as_str()).map(|x| x.suite_2_only).unwrap_or(0);
+                    (x, old)
+                })
+                .filter(|(x, old)| x.suite_2_only > *old)
+                .collect::<Vec<_>>();
+            if regressions.is_empty() {
+                writeln!(out, "No files have more {} only lines.", markdown_escape(&metadata.suite_2))?;
+                return Ok(());
+            }
+            regressions.sort_by(|a, b| {
+                (b.0.suite_2_only - b.1).cmp(&(a.0.suite_2_only - a.1)).then_with(|| a.0.path.cmp(&b.0.path))
+            });
+            regressions.truncate(limit);
+            writeln!(out, "| path | before | after | change |")?;
+            writeln!(out, "|:---|---:|---:|---:|")?;
+            for (file, old) in regressions {
+                writeln!(out, "| `{}` | {} | {} | {} |",
+                         markdown_escape(&file.path),
+                         old,
+                         file.suite_2_only,
+                         signed(file.suite_2_only - old))?;
+            }
+        }
+        None => {
+            let mut files = report.files.iter().filter(|x| x.suite_2_only > 0).collect::<Vec<_>>();
+            files.sort_by(|a, b| b.suite_2_only.cmp(&a.suite_2_only).then_with(|| a.path.cmp(&b.path)));
+            files.truncate(limit);
+            if files.is_empty() {
+                return Ok(());
+            }
+            writeln!(out, "| path | {} only | % |", markdown_escape(&metadata.suite_2))?;
+            writeln!(out, "|:---|---:|---:|")?;
+            for file in files {
+                writeln!(out, "| `{}` | {} | {:.1} |",
+                         markdown_escape(&file.path),
+                         file.suite_2_only,
+                         file.suite_2_only_percent)?;
+            }
+        }
+    }
+    Ok(())
+}
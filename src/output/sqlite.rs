@@ -0,0 +1,106 @@
+use std::path::Path;
+
+use rusqlite::{params, Connection};
+
+use crate::report::Report;
+use crate::{log, Error, Result};
+
+/// Version of the database schema, stored as the SQLite user_version
+const SCHEMA_VERSION: i64 = 2;
+
+const SCHEMA: &str = "
+CREATE TABLE runs (
+    id INTEGER PRIMARY KEY,
+    changeset TEXT NOT NULL,
+    suite_1 TEXT NOT NULL,
+    suite_2 TEXT NOT NULL,
+    date TEXT NOT NULL,
+    base_paths TEXT NOT NULL
+);
+CREATE TABLE files (
+    id INTEGER PRIMARY KEY,
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    path TEXT NOT NULL
+);
+CREATE INDEX files_run_path ON files(run_id, path);
+CREATE TABLE counters (
+    file_id INTEGER PRIMARY KEY REFERENCES files(id),
+    suite_1_only INTEGER NOT NULL,
+    suite_2_only INTEGER NOT NULL,
+    both INTEGER NOT NULL,
+    covered INTEGER NOT NULL,
+    coverable INTEGER NOT NULL,
+    lines INTEGER NOT NULL,
+    wpt_tests INTEGER,
+    wpt_tests_failing INTEGER,
+    wpt_subtests INTEGER,
+    wpt_subtests_failing INTEGER
+);
+";
+
+/// Changes from version 1 of the schema, which had no subtest counts
+const MIGRATE_FROM_1: &str = "
+ALTER TABLE counters ADD COLUMN wpt_subtests INTEGER;
+ALTER TABLE counters ADD COLUMN wpt_subtests_failing INTEGER;
+";
+
+fn ensure_schema(conn: &Connection) -> Result<()> {
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    match version {
+        0 => {
+            conn.execute_batch(SCHEMA)?;
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+            Ok(())
+        }
+        1 => {
+            conn.execute_batch(MIGRATE_FROM_1)?;
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+            Ok(())
+        }
+        SCHEMA_VERSION => Ok(()),
+        _ => Err(Error::Data(format!("Database has schema version {}, expected {}",
+                                     version,
+                                     SCHEMA_VERSION)))
+    }
+}
+
+/// Add a report to a SQLite database as a new run, creating the database
+/// if it doesn't exist so that several runs can be queried together.
+pub(crate) fn write_sqlite(path: &Path, report: &Report) -> Result<()> {
+    let mut conn = Connection::open(path)?;
+    ensure_schema(&conn)?;
+
+    let tx = conn.transaction()?;
+    let metadata = &report.metadata;
+    tx.execute("INSERT INTO runs (changeset, suite_1, suite_2, date, base_paths) VALUES (?1, ?2, ?3, ?4, ?5)",
+               params![metadata.changeset,
+                       metadata.suite_1,
+                       metadata.suite_2,
+                       metadata.date.to_rfc3339(),
+                       metadata.base_paths.join(",")])?;
+    let run_id = tx.last_insert_rowid();
+    {
+        let mut insert_file = tx.prepare("INSERT INTO files (run_id, path) VALUES (?1, ?2)")?;
+        let mut insert_counters = tx.prepare(
+            "INSERT INTO counters (file_id, suite_1_only, suite_2_only, both, covered, coverable, lines,
+                                   wpt_tests, wpt_tests_failing, wpt_subtests, wpt_subtests_failing)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)")?;
+        for file in report.files.iter() {
+            let file_id = insert_file.insert(params![run_id, file.path])?;
+            insert_counters.execute(params![file_id,
+                                            file.suite_1_only,
+                                            file.suite_2_only,
+                                            file.both,
+                                            file.covered,
+                                            file.coverable,
+                                            file.lines,
+                                            file.wpt_tests.map(|x| x as i64),
+                                            file.wpt_tests_failing.map(|x| x as i64),
+                                            file.wpt_subtests.map(|x| x as i64),
+                                            file.wpt_subtests_failing.map(|x| x as i64)])?;
+        }
+    }
+    tx.commit()?;
+    log::info(format_args!("Wrote run {} to {}", run_id, path.display()));
+    Ok(())
+}
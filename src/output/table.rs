@@ -0,0 +1,195 @@
+use std::io::Write;
+
+use serde::Deserialize;
+
+use super::{searchfox_url, selected_files, OutputOptions};
+use crate::report::Report;
+use crate::Result;
+
+/// Percentages at which unique coverage is shown in yellow and green;
+/// anything below the first is shown in red.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub(crate) struct ColorThresholds {
+    pub yellow: f64,
+    pub green: f64,
+}
+
+impl Default for ColorThresholds {
+    fn default() -> ColorThresholds {
+        ColorThresholds {
+            yellow: 5.0,
+            green: 20.0,
+        }
+    }
+}
+
+pub(crate) fn parse_color_thresholds(value: &str) -> std::result::Result<ColorThresholds, String> {
+    let parts = value
+        .split(',')
+        .map(|x| x.trim().parse::<f64>())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid thresholds {}: {}", value, e))?;
+    match parts.as_slice() {
+        [yellow, green] if yellow <= green => Ok(ColorThresholds {
+            yellow: *yellow,
+            green: *green,
+        }),
+        _ => Err(format!("Expected two increasing comma-separated percentages, got {}", value))
+    }
+}
+
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const GREEN: &str = "\x1b[32m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Format an integer with comma thousands separators
+fn thousands(value: i64) -> String {
+    let digits = value.abs().to_string();
+    let mut rv = String::with_capacity(digits.len() + digits.len() / 3 + 1);
+    if value < 0 {
+        rv.push('-');
+    }
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            rv.push(',');
+        }
+        rv.push(c);
+    }
+    rv
+}
+
+struct Cell {
+    text: String,
+    color: Option<&'static str>,
+    link: Option<String>,
+}
+
+impl Cell {
+    fn plain(text: String) -> Cell {
+        Cell { text, color: None, link: None }
+    }
+
+    fn link(text: String, url: String) -> Cell {
+        Cell { text, color: None, link: Some(url) }
+    }
+
+    /// A colored percentage, or "-" for a file with no coverable lines
+    fn percent(value: f64, coverable: i64, thresholds: &ColorThresholds) -> Cell {
+        if coverable == 0 {
+            return Cell::plain("-".into());
+        }
+        let color = if value >= thresholds.green {
+            GREEN
+        } else if value >= thresholds.yellow {
+            YELLOW
+        } else {
+            RED
+        };
+        Cell {
+            text: format!("{:.1}%", value),
+            color: Some(color),
+            link: None,
+        }
+    }
+}
+
+/// Write the report as an aligned table for reading in a terminal
+pub(crate) fn write_table<W: Write>(out: &mut W, report: &Report, options: &OutputOptions) -> Result<()> {
+    let metadata = &report.metadata;
+    let with_wpt_tests = report.has_wpt_tests();
+    let thresholds = &options.color_thresholds;
+
+    let mut header = vec![
+        "path".to_owned(),
+        format!("{} only", metadata.suite_1),
+        format!("{} only", metadata.suite_2),
+        "both".to_owned(),
+        "covered".to_owned(),
+        "coverable".to_owned(),
+        format!("{} only %", metadata.suite_1),
+        format!("{} only %", metadata.suite_2),
+        "covered %".to_owned(),
+    ];
+    if with_wpt_tests {
+        header.push("wpt tests".to_owned());
+        header.push("failing".to_owned());
+        header.push("subtests".to_owned());
+        header.push("failing".to_owned());
+    }
+
+    let rows = selected_files(report, options)
+        .into_iter()
+        .map(|file| {
+            let mut row = vec![
+                Cell::link(file.path.clone(), searchfox_url(&metadata.changeset, &file.path)),
+                Cell::plain(thousands(file.suite_1_only)),
+                Cell::plain(thousands(file.suite_2_only)),
+                Cell::plain(thousands(file.both)),
+                Cell::plain(thousands(file.covered)),
+                Cell::plain(thousands(file.coverable)),
+                Cell::percent(file.suite_1_only_percent, file.coverable, thresholds),
+                Cell::percent(file.suite_2_only_percent, file.coverable, thresholds),
+                Cell::plain(if file.coverable > 0 { format!("{:.1}%", file.covered_percent) } else { "-".into() }),
+            ];
+            if with_wpt_tests {
+                row.push(Cell::plain(thousands(file.wpt_tests.unwrap_or(0) as i64)));
+                row.push(Cell::plain(thousands(file.wpt_tests_failing.unwrap_or(0) as i64)));
+                row.push(Cell::plain(thousands(file.wpt_subtests.unwrap_or(0) as i64)));
+                row.push(Cell::plain(thousands(file.wpt_subtests_failing.unwrap_or(0) as i64)));
+            }
+            row
+        })
+        .collect::<Vec<_>>();
+
+    let mut widths = header.iter().map(|x| x.chars().count()).collect::<Vec<_>>();
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.text.chars().count());
+        }
+    }
+
+    if options.header {
+        let cells = header
+            .iter()
+            .zip(widths.iter())
+            .enumerate()
+            .map(|(i, (text, width))| if i == 0 {
+                format!("{:<width$}", text, width = width)
+            } else {
+                format!("{:>width$}", text, width = width)
+            })
+            .collect::<Vec<_>>();
+        if options.color {
+            writeln!(out, "{}{}{}", BOLD, cells.join("  "), RESET)?;
+        } else {
+            writeln!(out, "{}", cells.join("  "))?;
+        }
+    }
+
+    for row in rows.iter() {
+        let cells = row
+            .iter()
+            .zip(widths.iter())
+            .enumerate()
+            .map(|(i, (cell, width))| {
+                let text = match cell.link {
+                    // Pad outside the hyperlink so that only the text is clickable
+                    Some(ref url) if options.hyperlinks => {
+                        let padding = width.saturating_sub(cell.text.chars().count());
+                        format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\{}", url, cell.text, " ".repeat(padding))
+                    }
+                    _ if i == 0 => format!("{:<width$}", cell.text, width = width),
+                    _ => format!("{:>width$}", cell.text, width = width)
+                };
+                match cell.color {
+                    Some(color) if options.color => format!("{}{}{}", color, text, RESET),
+                    _ => text
+                }
+            })
+            .collect::<Vec<_>>();
+        writeln!(out, "{}", cells.join("  ").trim_end())?;
+    }
+    Ok(())
+}
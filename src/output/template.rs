@@ -0,0 +1,29 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::report::Report;
+use crate::Result;
+
+/// Render a user-supplied Tera template. The template gets the report as
+/// `version`, `metadata` and `files`, the summed counters and percentages
+/// as `totals`, and the counters for every directory as `directories`.
+pub(crate) fn write_template<W: Write>(out: &mut W, template_path: &Path, report: &Report) -> Result<()> {
+    let source = fs::read_to_string(template_path)?;
+    let mut tera = tera::Tera::default();
+    let name = template_path.to_string_lossy();
+    tera.add_raw_template(&name, &source)?;
+
+    let totals = report.totals();
+    let mut totals_value = serde_json::to_value(&totals)?;
+    totals_value["suite_1_only_percent"] = totals.suite_1_only_percent().into();
+    totals_value["suite_2_only_percent"] = totals.suite_2_only_percent().into();
+    totals_value["covered_percent"] = totals.covered_percent().into();
+
+    let mut context = tera::Context::from_serialize(report)?;
+    context.insert("totals", &totals_value);
+    context.insert("directories", &crate::report::directory_totals(report.files.iter()));
+
+    out.write_all(tera.render(&name, &context)?.as_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::report::{self, Report};
+use crate::Result;
+
+/// A node in the path hierarchy. Leaves are files; the size of a node is
+/// its number of coverable lines, and its color value is the percentage of
+/// those lines that are only covered by suite 2.
+#[derive(Debug, Serialize)]
+pub(crate) struct TreemapNode {
+    pub name: String,
+    pub path: String,
+    pub coverable: i64,
+    pub suite_2_only: i64,
+    pub suite_2_only_percent: f64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TreemapNode>,
+}
+
+#[derive(Default)]
+struct Builder {
+    coverable: i64,
+    suite_2_only: i64,
+    children: BTreeMap<String, Builder>,
+}
+
+impl Builder {
+    fn into_node(self, name: String, path: String) -> TreemapNode {
+        let children = self.children
+            .into_iter()
+            .map(|(child_name, child)| {
+                let child_path = if path.is_empty() {
+                    child_name.clone()
+                } else {
+                    format!("{}/{}", path, child_name)
+                };
+                child.into_node(child_name, child_path)
+            })
+            .collect();
+        let suite_2_only_percent = report::percent(self.suite_2_only, self.coverable);
+        TreemapNode {
+            name,
+            path,
+            coverable: self.coverable,
+            suite_2_only: self.suite_2_only,
+            suite_2_only_percent,
+            children,
+        }
+    }
+}
+
+/// Build the path hierarchy for a report
+pub(crate) fn build_tree(report: &Report) -> TreemapNode {
+    let mut root = Builder::default();
+    for file in report.files.iter() {
+        let mut node = &mut root;
+        node.coverable += file.coverable;
+        node.suite_2_only += file.suite_2_only;
+        for component in file.path.split('/') {
+            node = node.children.entry(component.to_owned()).or_default();
+            node.coverable += file.coverable;
+            node.suite_2_only += file.suite_2_only;
+        }
+    }
+    root.into_node(String::new(), String::new())
+}
+
+/// Write the report as hierarchical JSON suitable for use with e.g.
+/// d3.hierarchy(data).sum(d => d.children ? 0 : d.coverable)
+pub(crate) fn write_treemap<W: Write>(out: &mut W, report: &Report) -> Result<()> {
+    serde_json::to_writer(&mut *out, &build_tree(report))?;
+    writeln!(out)?;
+    Ok(())
+}
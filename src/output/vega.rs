@@ -0,0 +1,83 @@
+use std::io::Write;
+
+use super::OutputOptions;
+use crate::report::Report;
+use crate::Result;
+
+/// Build a Vega-Lite spec with a bar chart of the directories with the most
+/// unique coverage, and a histogram of the per-file unique coverage
+/// percentages.
+fn build_spec(report: &Report, options: &OutputOptions) -> serde_json::Value {
+    let metadata = &report.metadata;
+    let suite_1_key = format!("{} only", metadata.suite_1);
+    let suite_2_key = format!("{} only", metadata.suite_2);
+
+    let directories = report.top_directories(options.top.unwrap_or(20));
+
+    // Suite names can contain characters that Vega treats as field accessors,
+    // so use long-form data rather than a field per suite
+    let directory_values = directories
+        .iter()
+        .flat_map(|(dir, totals)| {
+            let directory = if dir.is_empty() { "/" } else { dir };
+            vec![
+                serde_json::json!({ "directory": directory, "suite": suite_1_key, "lines": totals.suite_1_only }),
+                serde_json::json!({ "directory": directory, "suite": suite_2_key, "lines": totals.suite_2_only }),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let file_values = report.files
+        .iter()
+        .filter(|x| x.coverable > 0)
+        .map(|x| serde_json::json!({
+            "path": x.path,
+            "suite_1_only_percent": x.suite_1_only_percent,
+            "suite_2_only_percent": x.suite_2_only_percent,
+        }))
+        .collect::<Vec<_>>();
+
+    serde_json::json!({
+        "$schema": "https://vega.github.io/schema/vega-lite/v5.json",
+        "title": format!("Coverage difference: {} vs {} at {}", metadata.suite_1, metadata.suite_2, metadata.changeset),
+        "vconcat": [
+            {
+                "title": "Directories with the most unique coverage",
+                "data": { "values": directory_values },
+                "mark": "bar",
+                "encoding": {
+                    "y": { "field": "directory", "type": "nominal", "sort": "-x", "title": null },
+                    "x": { "field": "lines", "type": "quantitative", "aggregate": "sum", "title": "lines" },
+                    "color": { "field": "suite", "type": "nominal", "title": null },
+                    "yOffset": { "field": "suite" },
+                    "tooltip": [
+                        { "field": "directory" },
+                        { "field": "suite" },
+                        { "field": "lines", "type": "quantitative" }
+                    ]
+                }
+            },
+            {
+                "title": format!("Files by percentage of lines only covered by {}", metadata.suite_2),
+                "data": { "values": file_values },
+                "mark": "bar",
+                "encoding": {
+                    "x": {
+                        "field": "suite_2_only_percent",
+                        "type": "quantitative",
+                        "bin": { "maxbins": 20 },
+                        "title": format!("{} only %", metadata.suite_2)
+                    },
+                    "y": { "aggregate": "count", "type": "quantitative", "title": "files" }
+                }
+            }
+        ]
+    })
+}
+
+/// Write the report as a Vega-Lite chart spec
+pub(crate) fn write_vega<W: Write>(out: &mut W, report: &Report, options: &OutputOptions) -> Result<()> {
+    serde_json::to_writer_pretty(&mut *out, &build_spec(report, options))?;
+    writeln!(out)?;
+    Ok(())
+}
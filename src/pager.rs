@@ -0,0 +1,47 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+use crate::{output, Result};
+
+fn write_stdout<F: FnOnce(&mut dyn Write) -> Result<()>>(f: F) -> Result<()> {
+    let mut out = output::stdout();
+    f(&mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Run `f` with a writer that goes through $PAGER, or less, when stdout is a
+/// terminal, and straight to stdout otherwise. Setting PAGER to an empty
+/// string or "cat" turns paging off.
+pub(crate) fn page<F: FnOnce(&mut dyn Write) -> Result<()>>(f: F) -> Result<()> {
+    let pager = env::var("PAGER").unwrap_or_else(|_| "less".into());
+    let mut words = pager.split_whitespace();
+    let program = match words.next() {
+        Some(program) if program != "cat" && io::stdout().is_terminal() => program,
+        _ => return write_stdout(f)
+    };
+    let mut command = Command::new(program);
+    command.args(words).stdin(Stdio::piped());
+    if env::var_os("LESS").is_none() {
+        // Keep colors, and don't page output that fits on one screen
+        command.env("LESS", "FRX");
+    }
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(_) => return write_stdout(f)
+    };
+    let result = match child.stdin.take() {
+        Some(stdin) => {
+            let mut stdin = io::BufWriter::new(stdin);
+            f(&mut stdin).and_then(|_| Ok(stdin.flush()?))
+        }
+        None => Ok(())
+    };
+    child.wait()?;
+    match result {
+        // The pager was quit before reading everything
+        Err(crate::Error::Io(ref e)) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result
+    }
+}
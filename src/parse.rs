@@ -0,0 +1,182 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+
+use crate::timings::{self, Stage};
+use crate::{Error, Result};
+
+/// Longest part of a line shown around the position of a parse error
+const SNIPPET_LENGTH: usize = 80;
+
+/// A JSON document that couldn't be parsed, with where it came from and
+/// the content around the problem
+#[derive(Debug)]
+pub struct ParseError {
+    /// URL or file name of the document
+    pub origin: String,
+    /// Cached copy of the document, which can be deleted so that it's
+    /// fetched again
+    pub cached: Option<PathBuf>,
+    /// Line and column of the problem, counting from 1, and its offset in
+    /// bytes. Errors in the structure of the data rather than its syntax
+    /// have no position.
+    pub position: Option<(usize, usize, usize)>,
+    /// The part of the line around the problem, and the offset of the
+    /// problem in it
+    pub snippet: Option<(String, usize)>,
+    pub error: serde_json::Error,
+}
+
+impl ParseError {
+    pub(crate) fn new(origin: &str, cached: Option<&Path>, body: &str, error: serde_json::Error) -> ParseError {
+        let position = if error.line() > 0 {
+            let offset = body
+                .split_inclusive('\n')
+                .take(error.line() - 1)
+                .map(|x| x.len())
+                .sum::<usize>() + error.column().saturating_sub(1);
+            Some((error.line(), error.column(), offset))
+        } else {
+            None
+        };
+        ParseError {
+            origin: origin.to_owned(),
+            cached: cached.map(|x| x.to_owned()),
+            position,
+            snippet: position.and_then(|(line, column, _)| snippet(body, line, column)),
+            error,
+        }
+    }
+
+    /// The error without its position, which is reported separately
+    fn message(&self) -> String {
+        let message = self.error.to_string();
+        match message.strip_suffix(&format!(" at line {} column {}", self.error.line(), self.error.column())) {
+            Some(message) => message.to_owned(),
+            None => message
+        }
+    }
+
+    /// A single line description, for warnings
+    pub(crate) fn summary(&self) -> String {
+        let mut rv = self.message();
+        if let Some((line, column, _)) = self.position {
+            rv.push_str(&format!(" at line {}, column {}", line, column));
+        }
+        if let Some((ref snippet, _)) = self.snippet {
+            rv.push_str(&format!(" near `{}`", snippet.trim()));
+        }
+        rv
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Couldn't parse {}", self.origin)?;
+        if let Some((line, column, offset)) = self.position {
+            write!(f, " at line {}, column {} (byte {})", line, column, offset)?;
+        }
+        write!(f, ": {}", self.message())?;
+        if let Some((ref snippet, offset)) = self.snippet {
+            write!(f, "\n    {}\n    {}^", snippet, " ".repeat(offset))?;
+        }
+        Ok(())
+    }
+}
+
+/// The part of `line` of `body` around `column`, with control characters
+/// replaced so that it prints on one line
+fn snippet(body: &str, line: usize, column: usize) -> Option<(String, usize)> {
+    let text = body.lines().nth(line - 1)?;
+    let chars = text.char_indices().map(|(idx, _)| idx).collect::<Vec<_>>();
+    // Columns count bytes, so find the character they fall in
+    let target = chars.iter().rposition(|idx| *idx < column.max(1)).unwrap_or(0);
+    let start = target.saturating_sub(SNIPPET_LENGTH / 2);
+    let rv = text
+        .chars()
+        .skip(start)
+        .take(SNIPPET_LENGTH)
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect::<String>();
+    if rv.trim().is_empty() {
+        return None;
+    }
+    Some((rv, target - start))
+}
+
+/// Attach where a document came from to the result of parsing it
+pub(crate) fn context<T>(result: serde_json::Result<T>, origin: &str, body: &str) -> Result<T> {
+    result.map_err(|e| Error::Parse(ParseError::new(origin, None, body, e)))
+}
+
+/// Parse a JSON document fetched from `url`
+pub(crate) fn from_str<T: DeserializeOwned>(url: &str, body: &str) -> Result<T> {
+    context(timings::time(Stage::Parse, || serde_json::from_str(body)), url, body)
+}
+
+/// Parse a JSON document that's cached at `path`, whether it was just
+/// fetched or read from there. The error says where the cached copy is, so
+/// that it can be deleted and fetched again.
+pub(crate) fn from_cache<T: DeserializeOwned>(path: &Path, body: &str) -> Result<T> {
+    timings::time(Stage::Parse, || serde_json::from_str(body))
+        .map_err(|e| Error::Parse(ParseError::new(&path.display().to_string(), Some(path), body, e)))
+}
+
+/// Parse a JSON file
+pub(crate) fn from_file<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let body = fs::read_to_string(path)?;
+    timings::time(Stage::Parse, || serde_json::from_str(&body))
+        .map_err(|e| Error::Parse(ParseError::new(&path.display().to_string(), None, &body, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{context, from_cache, ParseError, SNIPPET_LENGTH};
+    use crate::Error;
+
+    fn parse_error(body: &str) -> ParseError {
+        match context(serde_json::from_str::<serde_json::Value>(body), "https://example.org/data", body) {
+            Err(Error::Parse(e)) => e,
+            _ => panic!("Expected a parse error")
+        }
+    }
+
+    #[test]
+    fn position() {
+        let e = parse_error("{\"a\": 1,\n  \"b\": [1, 2 3]\n}");
+        assert_eq!(e.position, Some((2, 14, 22)));
+        assert_eq!(e.snippet, Some(("  \"b\": [1, 2 3]".to_owned(), 13)));
+        assert_eq!(e.summary(), "expected `,` or `]` at line 2, column 14 near `\"b\": [1, 2 3]`");
+        assert_eq!(e.to_string(),
+                   "Couldn't parse https://example.org/data at line 2, column 14 (byte 22): expected `,` or `]`\n\
+                    \x20     \"b\": [1, 2 3]\n\
+                    \x20                ^");
+    }
+
+    #[test]
+    fn long_lines() {
+        // The snippet is the part of the line around the problem
+        let body = format!("[{}\t1 2{}]", "1, ".repeat(100), ", 1".repeat(100));
+        let e = parse_error(&body);
+        let (snippet, offset) = e.snippet.unwrap();
+        assert_eq!(snippet.chars().count(), SNIPPET_LENGTH);
+        assert_eq!(&snippet[offset - 3..offset + 1], " 1 2");
+        assert!(!snippet.contains('\t'));
+    }
+
+    #[test]
+    fn cached() {
+        let path = Path::new("cache/dom.json");
+        match from_cache::<serde_json::Value>(path, "{\"a\": }") {
+            Err(Error::Parse(e)) => {
+                assert_eq!(e.cached.as_deref(), Some(path));
+                assert_eq!(e.position, Some((1, 7, 6)));
+            }
+            _ => panic!("Expected a parse error")
+        }
+    }
+}
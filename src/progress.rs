@@ -0,0 +1,190 @@
+use std::io::{self, IsTerminal};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde::Serialize;
+
+/// How progress is reported while running
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ProgressMode {
+    /// A progress bar if stderr is a terminal, otherwise log lines
+    Auto,
+    /// A progress bar on stderr
+    Bar,
+    /// Periodic log lines
+    Log,
+    /// Newline-delimited JSON events on stderr
+    Json,
+    /// Only the usual log messages
+    None,
+}
+
+impl FromStr for ProgressMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ProgressMode, String> {
+        match s {
+            "auto" => Ok(ProgressMode::Auto),
+            "bar" => Ok(ProgressMode::Bar),
+            "log" => Ok(ProgressMode::Log),
+            "json" => Ok(ProgressMode::Json),
+            "none" => Ok(ProgressMode::None),
+            _ => Err(format!("Unknown progress mode {}, expected auto, bar, log, json or none", s))
+        }
+    }
+}
+
+/// Minimum time between progress lines in log mode
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+// Progress is process-wide state, so that the code doing the fetching
+// doesn't need to have a reporter passed down to it
+static MODE: OnceLock<ProgressMode> = OnceLock::new();
+static START: OnceLock<Instant> = OnceLock::new();
+static BAR: OnceLock<ProgressBar> = OnceLock::new();
+static PHASE: Mutex<&'static str> = Mutex::new("start");
+static LAST_LOG: Mutex<Option<Instant>> = Mutex::new(None);
+static DISCOVERED: AtomicU64 = AtomicU64::new(0);
+static VISITED: AtomicU64 = AtomicU64::new(0);
+static PATHS: AtomicU64 = AtomicU64::new(0);
+static BYTES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Serialize)]
+struct Event {
+    event: &'static str,
+    phase: &'static str,
+    paths_discovered: u64,
+    paths_fetched: u64,
+    bytes_downloaded: u64,
+    elapsed: f64,
+}
+
+fn mode() -> ProgressMode {
+    *MODE.get().unwrap_or(&ProgressMode::None)
+}
+
+fn phase_name() -> &'static str {
+    *PHASE.lock().unwrap()
+}
+
+fn update(event: &'static str) {
+    let paths = PATHS.load(Ordering::Relaxed);
+    let bytes = BYTES.load(Ordering::Relaxed);
+    match mode() {
+        ProgressMode::Json => {
+            let event = Event {
+                event,
+                phase: phase_name(),
+                paths_discovered: DISCOVERED.load(Ordering::Relaxed),
+                paths_fetched: paths,
+                bytes_downloaded: bytes,
+                elapsed: START.get_or_init(Instant::now).elapsed().as_secs_f64(),
+            };
+            if let Ok(line) = serde_json::to_string(&event) {
+                eprintln!("{}", line);
+            }
+        }
+        ProgressMode::Bar => {
+            if let Some(bar) = BAR.get() {
+                bar.set_prefix(phase_name());
+                bar.set_length(DISCOVERED.load(Ordering::Relaxed));
+                bar.set_position(VISITED.load(Ordering::Relaxed));
+                bar.set_message(format!("{} fetched, {}", paths, HumanBytes(bytes)));
+            }
+        }
+        ProgressMode::Log => {
+            let mut last_log = LAST_LOG.lock().unwrap();
+            let since = last_log.or_else(|| START.get().cloned()).map(|x| x.elapsed());
+            if event != "phase" && since.map(|x| x >= LOG_INTERVAL).unwrap_or(true) {
+                *last_log = Some(Instant::now());
+                eprintln!("PROGRESS: {}: {} of {} paths, {} fetched, {}",
+                          phase_name(),
+                          VISITED.load(Ordering::Relaxed),
+                          DISCOVERED.load(Ordering::Relaxed),
+                          paths,
+                          HumanBytes(bytes));
+            }
+        }
+        ProgressMode::Auto | ProgressMode::None => {}
+    }
+}
+
+pub(crate) fn init(mode: ProgressMode) {
+    START.get_or_init(Instant::now);
+    let mode = match mode {
+        ProgressMode::Auto if io::stderr().is_terminal() => ProgressMode::Bar,
+        ProgressMode::Auto => ProgressMode::Log,
+        mode => mode
+    };
+    if mode == ProgressMode::Bar {
+        let style = ProgressStyle::with_template("{prefix:>6} [{bar:40}] {pos}/{len} paths, {msg}, ETA {eta}")
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .progress_chars("=> ");
+        let bar = ProgressBar::with_draw_target(Some(0), ProgressDrawTarget::stderr()).with_style(style);
+        let _ = BAR.set(bar);
+    }
+    let _ = MODE.set(mode);
+}
+
+/// Hide the progress bar while running `f`, e.g. to prompt the user
+pub(crate) fn suspend<F: FnOnce() -> R, R>(f: F) -> R {
+    match BAR.get() {
+        Some(bar) => bar.suspend(f),
+        None => f()
+    }
+}
+
+/// Record the start of a phase of the run, e.g. fetch, diff or write
+pub(crate) fn phase(name: &'static str) {
+    *PHASE.lock().unwrap() = name;
+    DISCOVERED.store(0, Ordering::Relaxed);
+    VISITED.store(0, Ordering::Relaxed);
+    if let Some(bar) = BAR.get() {
+        bar.reset_eta();
+    }
+    update("phase");
+}
+
+/// Record that there are `count` more paths to process in this phase
+pub(crate) fn discovered(count: usize) {
+    DISCOVERED.fetch_add(count as u64, Ordering::Relaxed);
+}
+
+/// Record that a path was processed in this phase
+pub(crate) fn visited() {
+    VISITED.fetch_add(1, Ordering::Relaxed);
+    if mode() != ProgressMode::Json {
+        update("visit");
+    }
+}
+
+/// Record that a path or artifact of `bytes` bytes was downloaded
+pub(crate) fn fetched(bytes: u64) {
+    PATHS.fetch_add(1, Ordering::Relaxed);
+    BYTES.fetch_add(bytes, Ordering::Relaxed);
+    update("fetch");
+}
+
+/// The number of paths and artifacts downloaded, and their size in bytes
+pub(crate) fn fetched_totals() -> (u64, u64) {
+    (PATHS.load(Ordering::Relaxed), BYTES.load(Ordering::Relaxed))
+}
+
+/// Remove the progress bar, e.g. before taking over the terminal
+pub(crate) fn clear() {
+    if let Some(bar) = BAR.get() {
+        bar.finish_and_clear();
+    }
+}
+
+/// Record that the run finished
+pub(crate) fn done() {
+    clear();
+    *PHASE.lock().unwrap() = "done";
+    if mode() == ProgressMode::Json {
+        update("phase");
+    }
+}
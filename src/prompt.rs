@@ -0,0 +1,57 @@
+use std::io::{self, IsTerminal};
+use std::path::Path;
+
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::{Confirm, Input, MultiSelect};
+
+use crate::{progress, Error, Result};
+
+fn prompt_error(error: dialoguer::Error) -> Error {
+    match error {
+        dialoguer::Error::IO(error) => Error::Io(error),
+    }
+}
+
+/// Whether there's a user at a terminal to answer prompts
+pub(crate) fn is_interactive() -> bool {
+    io::stdin().is_terminal() && io::stderr().is_terminal()
+}
+
+/// Ask the user to pick two of `suites` to compare. Suite 1 is the first
+/// selected in list order.
+pub(crate) fn select_suites(suites: &[String]) -> Result<(String, String)> {
+    let theme = ColorfulTheme::default();
+    loop {
+        let selected = progress::suspend(|| {
+            MultiSelect::with_theme(&theme)
+                .with_prompt("Select two suites to compare (space to select, enter to confirm)")
+                .items(suites)
+                .interact()
+        }).map_err(prompt_error)?;
+        match selected.as_slice() {
+            [suite_1, suite_2] => return Ok((suites[*suite_1].clone(), suites[*suite_2].clone())),
+            _ => eprintln!("Select exactly two suites, not {}", selected.len())
+        }
+    }
+}
+
+/// Ask the user for the comma-separated base paths
+pub(crate) fn input_base_paths() -> Result<String> {
+    progress::suspend(|| {
+        Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Base paths")
+            .default("auto".to_owned())
+            .interact_text()
+    }).map_err(prompt_error)
+}
+
+/// Ask the user whether to delete a cached file that couldn't be parsed, so
+/// that it's fetched again on the next run
+pub(crate) fn confirm_delete(path: &Path) -> Result<bool> {
+    progress::suspend(|| {
+        Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("Delete the cached copy {} so that it's fetched again?", path.display()))
+            .default(false)
+            .interact()
+    }).map_err(prompt_error)
+}
@@ -0,0 +1,585 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::wptreport::WptReport;
+use crate::{log, parse, CoverageDifference, Error, PathFilter, Result};
+
+/// Version of the report document format. This must be incremented
+/// whenever a change is made that older readers can't handle.
+pub(crate) const REPORT_VERSION: u32 = 1;
+
+/// Information about the run that produced a report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RunMetadata {
+    pub changeset: String,
+    pub suite_1: String,
+    pub suite_2: String,
+    pub date: DateTime<Utc>,
+    pub base_paths: Vec<String>,
+    /// When the changeset was pushed, if that could be found
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub push_date: Option<DateTime<Utc>>,
+    /// Paths the comparison was restricted to; empty if it covers the
+    /// whole of the base paths
+    #[serde(default, skip_serializing_if = "PathFilter::is_empty")]
+    pub filters: PathFilter,
+}
+
+/// Warn if `description`, a report that this run is compared with, was made
+/// with path filters other than `current`, since its totals then cover
+/// different files
+pub(crate) fn warn_filters_differ(description: &str, filters: &PathFilter, current: &PathFilter) {
+    if filters != current {
+        log::warning(format_args!("{} was made with {}, but this run has {}, so they cover different files",
+                                  description,
+                                  filters,
+                                  current));
+    }
+}
+
+/// Percentage of `total` that `count` makes up. Files and directories with no
+/// coverable lines get 0, rather than NaN, so that every output format has a
+/// number; readable formats show these as blank instead.
+pub(crate) fn percent(count: i64, total: i64) -> f64 {
+    if total > 0 {
+        100f64 * count as f64 / total as f64
+    } else {
+        0f64
+    }
+}
+
+/// Reports written before zero coverable lines were handled have null
+/// percentages where those were NaN
+fn deserialize_percent<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<f64, D::Error> {
+    Ok(Option::<f64>::deserialize(deserializer)?.unwrap_or(0f64))
+}
+
+/// Per-file counters in a report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct FileRecord {
+    pub path: String,
+    pub suite_1_only: i64,
+    pub suite_2_only: i64,
+    pub both: i64,
+    pub covered: i64,
+    pub coverable: i64,
+    pub lines: i64,
+    #[serde(deserialize_with = "deserialize_percent")]
+    pub suite_1_only_percent: f64,
+    #[serde(deserialize_with = "deserialize_percent")]
+    pub suite_2_only_percent: f64,
+    #[serde(deserialize_with = "deserialize_percent")]
+    pub covered_percent: f64,
+    /// Tests in the --wptreport that touch the directory containing the
+    /// file, and how many of those failed, with the same counts for their
+    /// subtests; see `WptReport`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wpt_tests: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wpt_tests_failing: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wpt_subtests: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wpt_subtests_failing: Option<u64>,
+    /// Hash of the coverage in both suites that the counters were computed
+    /// from, so that a later run with --previous can reuse them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coverage_hash: Option<String>,
+}
+
+impl FileRecord {
+    pub(crate) fn new(path: &str,
+                      coverage_difference: &CoverageDifference,
+                      wpt_report: Option<&WptReport>) -> FileRecord {
+        let percent = |count: i64| percent(count, coverage_difference.coverable_count);
+        let tests = wpt_report.map(|x| x.tests_for_dir(parent_dir(path)));
+        FileRecord {
+            path: path.to_owned(),
+            suite_1_only: coverage_difference.suite_1_only_count,
+            suite_2_only: coverage_difference.suite_2_only_count,
+            both: coverage_difference.both_count,
+            covered: coverage_difference.covered_count,
+            coverable: coverage_difference.coverable_count,
+            lines: coverage_difference.line_count,
+            suite_1_only_percent: percent(coverage_difference.suite_1_only_count),
+            suite_2_only_percent: percent(coverage_difference.suite_2_only_count),
+            covered_percent: percent(coverage_difference.covered_count),
+            wpt_tests: tests.map(|x| x.total),
+            wpt_tests_failing: tests.map(|x| x.failing),
+            wpt_subtests: tests.map(|x| x.subtests),
+            wpt_subtests_failing: tests.map(|x| x.subtests_failing),
+            coverage_hash: coverage_difference.coverage_hash.clone(),
+        }
+    }
+}
+
+/// Counters summed over all the files in a report
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Totals {
+    pub files: usize,
+    pub suite_1_only: i64,
+    pub suite_2_only: i64,
+    pub both: i64,
+    pub covered: i64,
+    pub coverable: i64,
+    pub lines: i64,
+}
+
+impl Totals {
+    pub(crate) fn from_files<'a, I: IntoIterator<Item = &'a FileRecord>>(files: I) -> Totals {
+        let mut totals = Totals::default();
+        for file in files {
+            totals.add(file);
+        }
+        totals
+    }
+
+    pub(crate) fn add(&mut self, file: &FileRecord) {
+        self.files += 1;
+        self.suite_1_only += file.suite_1_only;
+        self.suite_2_only += file.suite_2_only;
+        self.both += file.both;
+        self.covered += file.covered;
+        self.coverable += file.coverable;
+        self.lines += file.lines;
+    }
+
+    fn percent(&self, count: i64) -> f64 {
+        percent(count, self.coverable)
+    }
+
+    pub(crate) fn suite_1_only_percent(&self) -> f64 {
+        self.percent(self.suite_1_only)
+    }
+
+    pub(crate) fn suite_2_only_percent(&self) -> f64 {
+        self.percent(self.suite_2_only)
+    }
+
+    pub(crate) fn covered_percent(&self) -> f64 {
+        self.percent(self.covered)
+    }
+
+    /// Names of the values that can be looked up with `metric`
+    pub(crate) const METRICS: &'static [&'static str] = &[
+        "files",
+        "suite_1_only",
+        "suite_2_only",
+        "both",
+        "covered",
+        "coverable",
+        "lines",
+        "suite_1_only_percent",
+        "suite_2_only_percent",
+        "covered_percent",
+    ];
+
+    /// Look up a counter or percentage by name
+    pub(crate) fn metric(&self, name: &str) -> Option<f64> {
+        Some(match name {
+            "files" => self.files as f64,
+            "suite_1_only" => self.suite_1_only as f64,
+            "suite_2_only" => self.suite_2_only as f64,
+            "both" => self.both as f64,
+            "covered" => self.covered as f64,
+            "coverable" => self.coverable as f64,
+            "lines" => self.lines as f64,
+            "suite_1_only_percent" => self.suite_1_only_percent(),
+            "suite_2_only_percent" => self.suite_2_only_percent(),
+            "covered_percent" => self.covered_percent(),
+            _ => return None
+        })
+    }
+}
+
+/// Get the parent directory of a path, or "" for top-level paths
+pub(crate) fn parent_dir(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(idx) => &path[..idx],
+        None => ""
+    }
+}
+
+/// Sum the counters of files into every directory that contains them,
+/// including the root directory "".
+pub(crate) fn directory_totals<'a, I: IntoIterator<Item = &'a FileRecord>>(files: I) -> BTreeMap<String, Totals> {
+    let mut rv: BTreeMap<String, Totals> = BTreeMap::new();
+    for file in files {
+        add_directory_totals(&mut rv, file);
+    }
+    rv
+}
+
+/// Add the counters of a single file to the totals of every directory
+/// that contains it
+pub(crate) fn add_directory_totals(totals: &mut BTreeMap<String, Totals>, file: &FileRecord) {
+    let mut dir = parent_dir(&file.path);
+    loop {
+        totals.entry(dir.to_owned()).or_default().add(file);
+        if dir.is_empty() {
+            break;
+        }
+        dir = parent_dir(dir);
+    }
+}
+
+/// A problem with the input data that affects the results for a file
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum Warning {
+    /// The suites had coverage for different numbers of lines, so only the
+    /// lines in both were compared
+    LineCountMismatch {
+        path: String,
+        suite_1_lines: usize,
+        suite_2_lines: usize,
+    },
+    /// A file only in one suite looked like a file only in the other under
+    /// a different name, so they were compared as the same file
+    Renamed {
+        suite_1_path: String,
+        suite_2_path: String,
+    },
+    /// The changeset was pushed longer ago than --stale-days, so the data
+    /// may not reflect the current tree
+    StaleChangeset {
+        changeset: String,
+        push_date: DateTime<Utc>,
+        age_days: i64,
+    },
+    /// The changeset being compared had no complete entry for a path, so
+    /// the entry from an earlier changeset was used
+    FallbackChangeset {
+        suite: String,
+        path: String,
+        changeset: String,
+    },
+    /// The directory structure of the coverage data didn't make sense, e.g.
+    /// a child wasn't inside its parent, so part of it was skipped
+    TraversalAnomaly {
+        suite: String,
+        path: String,
+        problem: String,
+    },
+    /// An entry in the coverage data was missing its children or coverage,
+    /// or couldn't be read at all, so results under it may be incomplete
+    IncompleteEntry {
+        suite: String,
+        path: String,
+        problem: String,
+    },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::LineCountMismatch { path, suite_1_lines, suite_2_lines } => {
+                write!(f, "{}: line counts differ ({} in suite 1, {} in suite 2)", path, suite_1_lines, suite_2_lines)
+            }
+            Warning::Renamed { suite_1_path, suite_2_path } => {
+                write!(f, "{}: compared with {} in suite 1, which looks like the same file renamed",
+                       suite_2_path,
+                       suite_1_path)
+            }
+            Warning::StaleChangeset { changeset, push_date, age_days } => {
+                write!(f,
+                       "STALE DATA: changeset {} was pushed {} days ago, on {}; the coverage data may be out of date",
+                       changeset,
+                       age_days,
+                       push_date.format("%Y-%m-%d"))
+            }
+            Warning::FallbackChangeset { suite, path, changeset } => {
+                write!(f, "{}: MIXED REVISIONS: {} coverage is from earlier changeset {}", path, suite, changeset)
+            }
+            Warning::TraversalAnomaly { suite, path, problem } => {
+                write!(f, "{}: {} in {}", path, problem, suite)
+            }
+            Warning::IncompleteEntry { suite, path, problem } => {
+                write!(f, "{}: {} in {}", path, problem, suite)
+            }
+        }
+    }
+}
+
+/// Warnings about the input data found while loading it, in the order
+/// they were found
+struct RecordedWarnings {
+    warnings: Vec<Warning>,
+    seen: BTreeSet<Warning>,
+}
+
+impl RecordedWarnings {
+    const fn new() -> RecordedWarnings {
+        RecordedWarnings {
+            warnings: Vec::new(),
+            seen: BTreeSet::new(),
+        }
+    }
+
+    /// Add a warning, returning false if it was already recorded
+    fn record(&mut self, warning: Warning) -> bool {
+        if !self.seen.insert(warning.clone()) {
+            return false;
+        }
+        self.warnings.push(warning);
+        true
+    }
+
+    fn clear(&mut self) {
+        self.warnings.clear();
+        self.seen.clear();
+    }
+}
+
+/// Warnings found while loading the data for the current comparison,
+/// before there's a report to add them to. This is shared because the
+/// suites are loaded on separate threads.
+static RECORDED: Mutex<RecordedWarnings> = Mutex::new(RecordedWarnings::new());
+
+/// Log a warning about the input data and include it in the report for the
+/// current comparison
+pub(crate) fn record_warning(warning: Warning) {
+    let message = warning.to_string();
+    if RECORDED.lock().unwrap().record(warning) {
+        log::warning(format_args!("{}", message));
+    }
+}
+
+/// Forget the warnings from any earlier comparison. This must be called
+/// before loading the data for each comparison.
+pub(crate) fn clear_warnings() {
+    RECORDED.lock().unwrap().clear();
+}
+
+/// The result of comparing two suites, in a form that can be written out
+/// in various formats and read back in later.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Report {
+    pub version: u32,
+    pub metadata: RunMetadata,
+    pub files: Vec<FileRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<Warning>,
+}
+
+impl Report {
+    pub(crate) fn new(metadata: RunMetadata,
+                      differences: &BTreeMap<String, CoverageDifference>,
+                      wpt_report: Option<&WptReport>) -> Report {
+        let files = differences
+            .iter()
+            .map(|(path, coverage_difference)| FileRecord::new(path, coverage_difference, wpt_report))
+            .collect();
+        let warnings = differences
+            .iter()
+            .filter_map(|(path, coverage_difference)| {
+                coverage_difference.line_count_mismatch.map(|(suite_1_lines, suite_2_lines)| {
+                    Warning::LineCountMismatch {
+                        path: path.clone(),
+                        suite_1_lines,
+                        suite_2_lines,
+                    }
+                })
+            })
+            .chain(RECORDED.lock().unwrap().warnings.iter().cloned())
+            .collect();
+        Report {
+            version: REPORT_VERSION,
+            metadata,
+            files,
+            warnings,
+        }
+    }
+
+    /// Read a report previously written with the JSON format
+    pub(crate) fn load(path: &Path) -> Result<Report> {
+        let report: Report = parse::from_file(path)?;
+        if report.version > REPORT_VERSION {
+            return Err(Error::Data(format!("Report {} has version {}, but only versions up to {} are supported",
+                                           path.display(),
+                                           report.version,
+                                           REPORT_VERSION)));
+        }
+        Ok(report)
+    }
+
+    pub(crate) fn totals(&self) -> Totals {
+        Totals::from_files(self.files.iter())
+    }
+
+    /// Totals for the whole report, with an empty name, and for each top-level
+    /// directory
+    pub(crate) fn top_level_totals(&self) -> BTreeMap<String, Totals> {
+        directory_totals(self.files.iter())
+            .into_iter()
+            .filter(|(dir, _)| !dir.contains('/'))
+            .collect()
+    }
+
+    /// Totals for the `limit` directories that directly contain files with the
+    /// most unique coverage in either suite. Only files directly in each
+    /// directory are counted, so nested directories aren't double counted.
+    pub(crate) fn top_directories(&self, limit: usize) -> Vec<(&str, Totals)> {
+        let mut directories: BTreeMap<&str, Totals> = BTreeMap::new();
+        for file in self.files.iter() {
+            directories.entry(parent_dir(&file.path)).or_default().add(file);
+        }
+        let mut rv = directories.into_iter().collect::<Vec<_>>();
+        rv.sort_by(|a, b| {
+            (b.1.suite_1_only + b.1.suite_2_only)
+                .cmp(&(a.1.suite_1_only + a.1.suite_2_only))
+                .then_with(|| a.0.cmp(b.0))
+        });
+        rv.truncate(limit);
+        rv
+    }
+
+    pub(crate) fn has_wpt_tests(&self) -> bool {
+        self.files.iter().any(|x| x.wpt_tests.is_some())
+    }
+}
+
+/// The files of a report from an earlier run, whose counters are reused for
+/// files with the same coverage rather than being computed again
+pub(crate) struct PreviousReport {
+    files: BTreeMap<String, FileRecord>,
+    reused: AtomicUsize,
+}
+
+impl PreviousReport {
+    /// Load the report at `path` to reuse in a run with path `filters`
+    pub(crate) fn load(path: &Path, filters: &PathFilter) -> Result<PreviousReport> {
+        let report = Report::load(path)?;
+        warn_filters_differ(&format!("--previous {}", path.display()), &report.metadata.filters, filters);
+        let files = report.files
+            .into_iter()
+            .filter(|x| x.coverage_hash.is_some())
+            .map(|x| (x.path.clone(), x))
+            .collect::<BTreeMap<_, _>>();
+        if files.is_empty() {
+            log::warning(format_args!("Report {} has no coverage hashes, so every file will be compared again",
+                                      path.display()));
+        }
+        Ok(PreviousReport {
+            files,
+            reused: AtomicUsize::new(0),
+        })
+    }
+
+    /// The earlier record for `path`, if it was computed from coverage with
+    /// the same hash
+    pub(crate) fn unchanged(&self, path: &str, coverage_hash: &str) -> Option<&FileRecord> {
+        let rv = self.files
+            .get(path)
+            .filter(|x| x.coverage_hash.as_deref() == Some(coverage_hash));
+        if rv.is_some() {
+            self.reused.fetch_add(1, Ordering::Relaxed);
+        }
+        rv
+    }
+
+    /// The number of records that have been reused
+    pub(crate) fn reused(&self) -> usize {
+        self.reused.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::path::PathBuf;
+    use std::process;
+
+    use chrono::Utc;
+
+    use super::{directory_totals, percent, FileRecord, RecordedWarnings, Report, RunMetadata, Warning, REPORT_VERSION};
+    use crate::PathFilter;
+
+    /// Metadata for a comparison of web-platform-tests with mochitest-plain
+    pub(crate) fn metadata(changeset: &str) -> RunMetadata {
+        RunMetadata {
+            changeset: changeset.to_owned(),
+            suite_1: "web-platform-tests".into(),
+            suite_2: "mochitest-plain".into(),
+            date: Utc::now(),
+            base_paths: vec!["".into()],
+            push_date: None,
+            filters: PathFilter::default(),
+        }
+    }
+
+    /// A file with the given unique lines in each suite, whose other
+    /// coverable lines are covered by both
+    pub(crate) fn file(path: &str, suite_1_only: i64, suite_2_only: i64, coverable: i64) -> FileRecord {
+        let both = coverable - suite_1_only - suite_2_only;
+        FileRecord {
+            path: path.to_owned(),
+            suite_1_only,
+            suite_2_only,
+            both,
+            covered: coverable,
+            coverable,
+            lines: coverable,
+            suite_1_only_percent: percent(suite_1_only, coverable),
+            suite_2_only_percent: percent(suite_2_only, coverable),
+            covered_percent: percent(coverable, coverable),
+            wpt_tests: None,
+            wpt_tests_failing: None,
+            wpt_subtests: None,
+            wpt_subtests_failing: None,
+            coverage_hash: None,
+        }
+    }
+
+    pub(crate) fn report(changeset: &str, files: Vec<FileRecord>) -> Report {
+        Report {
+            version: REPORT_VERSION,
+            metadata: metadata(changeset),
+            files,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// A path in the temporary directory that's unique to this process
+    pub(crate) fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("wptcoverage-{}-{}", process::id(), name))
+    }
+
+    #[test]
+    fn totals() {
+        let report = report("abc", vec![file("dom/base/a.cpp", 1, 2, 10),
+                                        file("dom/media/b.cpp", 0, 4, 10),
+                                        file("c.cpp", 3, 0, 5)]);
+        let totals = report.totals();
+        assert_eq!((totals.files, totals.suite_1_only, totals.suite_2_only, totals.coverable), (3, 4, 6, 25));
+        assert_eq!(totals.suite_2_only_percent(), 24f64);
+
+        let directories = directory_totals(report.files.iter());
+        assert_eq!(directories.keys().collect::<Vec<_>>(), vec!["", "dom", "dom/base", "dom/media"]);
+        assert_eq!(directories["dom"].suite_2_only, 6);
+        assert_eq!(report.top_level_totals().keys().collect::<Vec<_>>(), vec!["", "dom"]);
+    }
+
+    #[test]
+    fn recorded_warnings() {
+        let renamed = |path: &str| Warning::Renamed {
+            suite_1_path: path.to_owned(),
+            suite_2_path: format!("new/{}", path),
+        };
+        let mut recorded = RecordedWarnings::new();
+        assert!(recorded.record(renamed("b.cpp")));
+        assert!(recorded.record(renamed("a.cpp")));
+        assert!(!recorded.record(renamed("b.cpp")));
+        assert_eq!(recorded.warnings, vec![renamed("b.cpp"), renamed("a.cpp")]);
+
+        recorded.clear();
+        assert!(recorded.warnings.is_empty());
+        assert!(recorded.record(renamed("b.cpp")));
+        assert_eq!(recorded.warnings, vec![renamed("b.cpp")]);
+    }
+}
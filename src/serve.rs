@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, ToSocketAddrs};
+use std::path::PathBuf;
+
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::output::{self, OutputOptions};
+use crate::report::Report;
+use crate::{annotate, browser, cache, log, source, CoverageDifference, Error, Result};
+
+/// Path prefix of the annotated source pages
+const ANNOTATE_PREFIX: &str = "/annotate/";
+
+const HTML: &str = "text/html; charset=utf-8";
+const JSON: &str = "application/json";
+
+fn respond(request: Request, status: u16, content_type: &str, body: Vec<u8>) {
+    let header = Header::from_bytes("Content-Type", content_type).expect("Valid header");
+    let response = Response::from_data(body)
+        .with_status_code(status)
+        .with_header(header);
+    if let Err(e) = request.respond(response) {
+        log::warning(format_args!("Failed to send response: {}", e));
+    }
+}
+
+/// Decode %XX escapes and + in a query string value
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut rv = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|x| u8::from_str_radix(x, 16).ok()) {
+                    Some(byte) => {
+                        rv.push(byte);
+                        i += 3;
+                        continue;
+                    }
+                    None => rv.push(b'%')
+                }
+            }
+            b'+' => rv.push(b' '),
+            byte => rv.push(byte)
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&rv).into_owned()
+}
+
+/// Get the value of a query string parameter
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|x| x.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value))
+}
+
+struct Handler<'a> {
+    client: &'a reqwest::Client,
+    report: &'a Report,
+    differences: &'a BTreeMap<String, CoverageDifference>,
+    options: OutputOptions,
+    root_path: PathBuf,
+}
+
+impl<'a> Handler<'a> {
+    /// The run metadata and the summed counters and percentages
+    fn summary(&self) -> Result<serde_json::Value> {
+        let totals = self.report.totals();
+        let mut totals_value = serde_json::to_value(&totals)?;
+        totals_value["suite_1_only_percent"] = totals.suite_1_only_percent().into();
+        totals_value["suite_2_only_percent"] = totals.suite_2_only_percent().into();
+        totals_value["covered_percent"] = totals.covered_percent().into();
+        Ok(serde_json::json!({
+            "metadata": self.report.metadata,
+            "totals": totals_value,
+        }))
+    }
+
+    /// The category of each line in a file, numbered from 1
+    fn lines(&self, path: &str) -> Option<serde_json::Value> {
+        let difference = self.differences.get(path)?;
+        let lines = difference.line_differences
+            .iter()
+            .enumerate()
+            .map(|(i, coverage_type)| serde_json::json!({"line": i + 1, "category": coverage_type.name()}))
+            .collect::<Vec<_>>();
+        Some(serde_json::json!({
+            "path": path,
+            "lines": lines,
+        }))
+    }
+
+    /// Build the body and content type of the page at `url`, or None if
+    /// there's no such page
+    fn page(&self, url: &str) -> Result<Option<(Vec<u8>, &'static str)>> {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let path = percent_decode(path);
+        let mut body = Vec::new();
+        if path == "/" {
+            output::write_report(&mut body, output::Format::Html, &self.options, self.report)?;
+            return Ok(Some((body, HTML)));
+        }
+        if let Some(file) = path.strip_prefix(ANNOTATE_PREFIX) {
+            let difference = match self.differences.get(file) {
+                Some(difference) => difference,
+                None => return Ok(None)
+            };
+            let metadata = &self.report.metadata;
+            let source = source::get_source(self.client, &self.root_path, &metadata.changeset, file)?;
+            annotate::write_html(&mut body, metadata, file, &source, difference)?;
+            return Ok(Some((body, HTML)));
+        }
+
+        let value = if path == "/summary" {
+            self.summary()?
+        } else if path == "/files" {
+            let prefix = query_param(query, "prefix").unwrap_or_default();
+            let files = self.report.files
+                .iter()
+                .filter(|x| x.path.starts_with(&prefix))
+                .collect::<Vec<_>>();
+            serde_json::to_value(files)?
+        } else if let Some(file) = path.strip_prefix("/file/").and_then(|x| x.strip_suffix("/lines")) {
+            match self.lines(file) {
+                Some(value) => value,
+                None => return Ok(None)
+            }
+        } else {
+            return Ok(None);
+        };
+        serde_json::to_writer(&mut body, &value)?;
+        Ok(Some((body, JSON)))
+    }
+
+    fn handle(&self, request: Request) {
+        log::debug(format_args!("{} {}", request.method(), request.url()));
+        if *request.method() != Method::Get {
+            respond(request, 405, HTML, b"Method not allowed".to_vec());
+            return;
+        }
+        match self.page(request.url()) {
+            Ok(Some((body, content_type))) => respond(request, 200, content_type, body),
+            Ok(None) => respond(request, 404, HTML, b"Not found".to_vec()),
+            Err(e) => {
+                log::warning(format_args!("Failed to serve {}: {:?}", request.url(), e));
+                respond(request, 500, HTML, format!("Failed: {:?}", e).into_bytes())
+            }
+        }
+    }
+}
+
+/// Serve the HTML report at / and an annotated source page for each file,
+/// until interrupted. Source files are fetched when first requested. There
+/// are also JSON endpoints: /summary with the metadata and totals,
+/// /files?prefix=PATH with the records for files under a path, and
+/// /file/PATH/lines with the category of each line in a file.
+pub(crate) fn serve<A: ToSocketAddrs>(client: &reqwest::Client,
+                                      addr: A,
+                                      report: &Report,
+                                      differences: &BTreeMap<String, CoverageDifference>,
+                                      options: &OutputOptions,
+                                      open: bool) -> Result<()> {
+    let server = Server::http(addr).map_err(|e| Error::String(format!("Failed to start server: {}", e)))?;
+    if let Some(mut addr) = server.server_addr().to_ip() {
+        eprintln!("Serving on http://{}/", addr);
+        if open {
+            if addr.ip().is_unspecified() {
+                addr.set_ip(Ipv4Addr::LOCALHOST.into());
+            }
+            browser::open(&format!("http://{}/", addr));
+        }
+    }
+    let handler = Handler {
+        client,
+        report,
+        differences,
+        options: OutputOptions {
+            file_links: Some(ANNOTATE_PREFIX.trim_start_matches('/').to_owned()),
+            ..options.clone()
+        },
+        root_path: cache::changeset_root(&report.metadata.changeset),
+    };
+    for request in server.incoming_requests() {
+        handler.handle(request);
+    }
+    Ok(())
+}
@@ -0,0 +1,197 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::output::html::escape;
+use crate::report::{self, FileRecord, Report, Totals};
+use crate::{log, CoverageDifference, CoverageType, Result};
+
+const STYLE: &str = r##"
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+th, td { padding: 0.2em 0.6em; border-bottom: 1px solid #ddd; }
+th { background: #f4f4f4; text-align: left; }
+td.num { text-align: right; font-variant-numeric: tabular-nums; }
+nav { margin-bottom: 1em; }
+.not-covered { background: #fdd; }
+.suite1-only { background: #ddf; }
+.suite2-only { background: #dfd; }
+.both { background: #eee; }
+"##;
+
+/// Prefix that leads from a page in `dir` back to the site root
+fn root_prefix(dir: &str) -> String {
+    if dir.is_empty() {
+        String::new()
+    } else {
+        "../".repeat(dir.split('/').count())
+    }
+}
+
+fn dir_href(prefix: &str, dir: &str) -> String {
+    if dir.is_empty() {
+        format!("{}index.html", prefix)
+    } else {
+        format!("{}{}/index.html", prefix, dir)
+    }
+}
+
+fn file_href(prefix: &str, path: &str) -> String {
+    format!("{}{}.html", prefix, path)
+}
+
+fn create_page(path: &Path) -> Result<BufWriter<File>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(BufWriter::new(File::create(path)?))
+}
+
+fn write_header<W: Write>(out: &mut W, report: &Report, title: &str, dir: &str) -> Result<()> {
+    let prefix = root_prefix(dir);
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html lang=\"en\">")?;
+    writeln!(out, "<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>{}</title>", escape(title))?;
+    writeln!(out, "<style>{}</style>", STYLE)?;
+    write!(out, "<nav><a href=\"{}\">root</a>", dir_href(&prefix, ""))?;
+    if !dir.is_empty() {
+        let mut current = String::new();
+        for component in dir.split('/') {
+            if !current.is_empty() {
+                current.push('/');
+            }
+            current.push_str(component);
+            write!(out, " / <a href=\"{}\">{}</a>", dir_href(&prefix, &current), escape(component))?;
+        }
+    }
+    writeln!(out, "</nav>")?;
+    writeln!(out, "<h1>{}</h1>", escape(title))?;
+    writeln!(out, "<p>{} vs {} at changeset {}</p>",
+             escape(&report.metadata.suite_1),
+             escape(&report.metadata.suite_2),
+             escape(&report.metadata.changeset))?;
+    Ok(())
+}
+
+fn write_summary_row<W: Write>(out: &mut W, name: &str, href: &str, totals: &Totals) -> Result<()> {
+    writeln!(out, "<tr><td><a href=\"{}\">{}</a></td><td class=num>{}</td><td class=num>{}</td><td class=num>{}</td><td class=num>{}</td><td class=num>{}</td><td class=num>{:.1}</td><td class=num>{:.1}</td></tr>",
+             href,
+             escape(name),
+             totals.suite_1_only,
+             totals.suite_2_only,
+             totals.both,
+             totals.covered,
+             totals.coverable,
+             totals.suite_1_only_percent(),
+             totals.suite_2_only_percent())?;
+    Ok(())
+}
+
+fn write_dir_page(out_dir: &Path,
+                  report: &Report,
+                  dir: &str,
+                  dir_totals: &BTreeMap<String, Totals>,
+                  child_dirs: &BTreeSet<&str>,
+                  child_files: &[&FileRecord]) -> Result<()> {
+    let page_path = if dir.is_empty() {
+        out_dir.join("index.html")
+    } else {
+        out_dir.join(dir).join("index.html")
+    };
+    let mut out = create_page(&page_path)?;
+    let title = if dir.is_empty() { "root" } else { dir };
+    write_header(&mut out, report, title, dir)?;
+    let prefix = root_prefix(dir);
+    let metadata = &report.metadata;
+
+    writeln!(out, "<table>")?;
+    writeln!(out, "<tr><th>name</th><th>{0} only</th><th>{1} only</th><th>both</th><th>covered</th><th>coverable</th><th>{0} only %</th><th>{1} only %</th></tr>",
+             escape(&metadata.suite_1),
+             escape(&metadata.suite_2))?;
+    if let Some(totals) = dir_totals.get(dir) {
+        write_summary_row(&mut out, "(total)", &dir_href(&prefix, dir), totals)?;
+    }
+    for child in child_dirs.iter() {
+        if let Some(totals) = dir_totals.get(*child) {
+            let name = format!("{}/", child.rsplit('/').next().unwrap_or(child));
+            write_summary_row(&mut out, &name, &dir_href(&prefix, child), totals)?;
+        }
+    }
+    for file in child_files.iter() {
+        let name = file.path.rsplit('/').next().unwrap_or(&file.path);
+        write_summary_row(&mut out, name, &file_href(&prefix, &file.path), &Totals::from_files(Some(*file)))?;
+    }
+    writeln!(out, "</table>")?;
+    out.flush()?;
+    Ok(())
+}
+
+fn write_file_page(out_dir: &Path,
+                   report: &Report,
+                   file: &FileRecord,
+                   difference: &CoverageDifference) -> Result<()> {
+    let mut out = create_page(&out_dir.join(format!("{}.html", file.path)))?;
+    let dir = report::parent_dir(&file.path);
+    write_header(&mut out, report, &file.path, dir)?;
+    let metadata = &report.metadata;
+
+    writeln!(out, "<p>{} coverable lines: {} only covered by {}, {} only covered by {}, {} covered by both</p>",
+             file.coverable,
+             file.suite_1_only,
+             escape(&metadata.suite_1),
+             file.suite_2_only,
+             escape(&metadata.suite_2),
+             file.both)?;
+    writeln!(out, "<table>")?;
+    writeln!(out, "<tr><th>line</th><th>category</th></tr>")?;
+    for (i, coverage_type) in difference.line_differences.iter().enumerate() {
+        if *coverage_type == CoverageType::NotRun {
+            continue;
+        }
+        writeln!(out, "<tr class=\"{0}\"><td class=num>{1}</td><td>{0}</td></tr>",
+                 coverage_type.name(),
+                 i + 1)?;
+    }
+    writeln!(out, "</table>")?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Write a browsable static site with an index page, a page for each
+/// directory, and a page for each file listing the category of every line.
+pub(crate) fn write_site(out_dir: &Path,
+                         report: &Report,
+                         differences: &BTreeMap<String, CoverageDifference>) -> Result<()> {
+    let dir_totals = report::directory_totals(report.files.iter());
+
+    let mut child_dirs: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for dir in dir_totals.keys() {
+        if !dir.is_empty() {
+            child_dirs.entry(report::parent_dir(dir)).or_default().insert(dir);
+        }
+    }
+    let mut child_files: BTreeMap<&str, Vec<&FileRecord>> = BTreeMap::new();
+    for file in report.files.iter() {
+        child_files.entry(report::parent_dir(&file.path)).or_default().push(file);
+    }
+
+    let empty_dirs = BTreeSet::new();
+    for dir in dir_totals.keys() {
+        write_dir_page(out_dir,
+                       report,
+                       dir,
+                       &dir_totals,
+                       child_dirs.get(dir.as_str()).unwrap_or(&empty_dirs),
+                       child_files.get(dir.as_str()).map(|x| x.as_slice()).unwrap_or(&[]))?;
+    }
+
+    for file in report.files.iter() {
+        if let Some(difference) = differences.get(&file.path) {
+            write_file_page(out_dir, report, file, difference)?;
+        }
+    }
+    log::info(format_args!("Wrote site for {} files to {}", report.files.len(), out_dir.display()));
+    Ok(())
+}
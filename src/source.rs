@@ -0,0 +1,28 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{cache, get, timings, Result};
+
+pub(crate) static HG_URL: &str = "https://hg.mozilla.org/mozilla-central";
+
+/// Where the cached copy of a gecko source file for a changeset is kept
+pub(crate) fn source_path(root_path: &Path, path: &str) -> PathBuf {
+    cache::tree_path(&root_path.join("source"), path)
+}
+
+/// Get the contents of a gecko source file at a changeset, caching it
+/// under the changeset data directory.
+pub(crate) fn get_source(client: &reqwest::Client, root_path: &Path, changeset: &str, path: &str) -> Result<String> {
+    let local_path = source_path(root_path, path);
+
+    if local_path.exists() {
+        return Ok(timings::time(timings::Stage::CacheRead, || fs::read_to_string(&local_path))?);
+    }
+    if let Some(parent) = local_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let url = format!("{}/raw-file/{}/{}", HG_URL, changeset, path);
+    let resp_str = get(client, &url, None)?;
+    cache::write(&local_path, resp_str.as_bytes())?;
+    Ok(resp_str)
+}
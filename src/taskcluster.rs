@@ -0,0 +1,193 @@
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::local::{self, MergedCoverage};
+use crate::{cache, get, get_bytes, log, parse, CoverageMap, Error, Result};
+
+static TASKCLUSTER_ROOT: &str = "https://firefox-ci-tc.services.mozilla.com/api";
+static GRCOV_ARTIFACT: &str = "public/test_info/code-coverage-grcov.zip";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexedTask {
+    task_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TaskStatus {
+    task_id: String,
+    state: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TaskMetadata {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TaskDefinition {
+    metadata: TaskMetadata,
+}
+
+#[derive(Debug, Deserialize)]
+struct TaskGroupEntry {
+    status: TaskStatus,
+    task: TaskDefinition,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TaskGroupList {
+    tasks: Vec<TaskGroupEntry>,
+    continuation_token: Option<String>,
+}
+
+/// Find the task group for the decision task of a mozilla-central push
+fn task_group_for_revision(client: &reqwest::Client, revision: &str) -> Result<String> {
+    let url = format!("{}/index/v1/task/gecko.v2.mozilla-central.revision.{}.taskgraph.decision",
+                      TASKCLUSTER_ROOT,
+                      revision);
+    let resp_str = get(client, &url, None)?;
+    let task: IndexedTask = parse::from_str(&url, &resp_str)?;
+    // The decision task id is also the task group id
+    Ok(task.task_id)
+}
+
+/// The suite a coverage task is for, from its label, e.g. mochitest-plain
+/// for test-linux1804-64-ccov/opt-mochitest-plain-e10s-3: the part after
+/// the build type, without the chunk number or the e10s variant
+fn label_suite(label: &str) -> Option<&str> {
+    let (platform, test) = label.split_once('/')?;
+    if !platform.contains("ccov") {
+        return None;
+    }
+    let (_, test) = test.split_once('-')?;
+    let test = match test.rsplit_once('-') {
+        Some((rest, chunk)) if chunk.chars().all(|x| x.is_ascii_digit()) => rest,
+        _ => test
+    };
+    Some(test.strip_suffix("-e10s").unwrap_or(test))
+}
+
+/// List the completed coverage tasks in a task group that belong to a suite
+fn coverage_tasks(client: &reqwest::Client, task_group: &str, suite_name: &str) -> Result<Vec<(String, String)>> {
+    let mut rv = Vec::new();
+    let mut continuation_token: Option<String> = None;
+    loop {
+        let base_url = format!("{}/queue/v1/task-group/{}/list", TASKCLUSTER_ROOT, task_group);
+        let url = match continuation_token {
+            Some(ref token) => reqwest::Url::parse_with_params(&base_url, &[("continuationToken", token)])
+                .map_err(|e| Error::Data(format!("Invalid Taskcluster URL: {}", e)))?
+                .to_string(),
+            None => base_url
+        };
+        let resp_str = get(client, &url, None)?;
+        let data: TaskGroupList = parse::from_str(&url, &resp_str)?;
+        for entry in data.tasks.into_iter() {
+            let name = entry.task.metadata.name;
+            if entry.status.state == "completed" && label_suite(&name) == Some(suite_name) {
+                rv.push((entry.status.task_id, name));
+            }
+        }
+        continuation_token = data.continuation_token;
+        if continuation_token.is_none() {
+            break;
+        }
+    }
+    Ok(rv)
+}
+
+fn download(client: &reqwest::Client, url: &str, dest: &Path) -> Result<()> {
+    let body = get_bytes(client, url, None)?;
+    cache::write(dest, &body)?;
+    Ok(())
+}
+
+/// Read all the LCOV files contained in a grcov artifact
+fn read_grcov_zip(path: &Path) -> Result<Vec<(String, Vec<i64>)>> {
+    let f = File::open(path)?;
+    let mut archive = zip::ZipArchive::new(BufReader::new(f))
+        .map_err(|e| Error::Data(format!("Failed to read {}: {}", path.display(), e)))?;
+    let mut rv = Vec::new();
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)
+            .map_err(|e| Error::Data(format!("Failed to read {}: {}", path.display(), e)))?;
+        if !entry.name().ends_with(".info") {
+            continue;
+        }
+        let mut data = String::new();
+        entry.read_to_string(&mut data)?;
+        let name = format!("{}:{}", path.display(), entry.name());
+        rv.extend(local::parse_lcov(data.as_bytes(), &name)?);
+    }
+    Ok(rv)
+}
+
+/// Build suite data from the raw grcov artifacts of the coverage tasks
+/// for a push, rather than from the aggregated coverage API.
+pub(crate) fn get_suite_data(client: &reqwest::Client,
+                             changeset: &str,
+                             root_path: &Path,
+                             suite_name: &str,
+                             task_group: Option<&str>,
+                             gecko_roots: &[&str]) -> Result<CoverageMap> {
+    let task_group = match task_group {
+        Some(task_group) => task_group.to_owned(),
+        None => task_group_for_revision(client, changeset)?
+    };
+
+    let artifact_name = cache::component(&format!("taskcluster-{}", task_group));
+    let artifact_root = cache::long_path(&root_path.join(artifact_name));
+    if !artifact_root.exists() {
+        fs::create_dir_all(&artifact_root)?;
+    }
+
+    let tasks = coverage_tasks(client, &task_group, suite_name)?;
+    if tasks.is_empty() {
+        return Err(Error::Data(format!("No coverage tasks for {} found in task group {}",
+                                       suite_name,
+                                       task_group)));
+    }
+
+    let mut merged = MergedCoverage::new(gecko_roots);
+    for (task_id, name) in tasks.iter() {
+        let local_path = cache::file_path(&artifact_root, task_id, "zip");
+        if !local_path.exists() {
+            let url = format!("{}/queue/v1/task/{}/artifacts/{}", TASKCLUSTER_ROOT, task_id, GRCOV_ARTIFACT);
+            download(client, &url, &local_path)?;
+        }
+        log::info(format_args!("Loading artifact for {}", name));
+        merged.add(read_grcov_zip(&local_path)?);
+    }
+    Ok(merged.into_coverage_map())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{download, label_suite};
+    use crate::api;
+    use crate::report::tests::temp_path;
+
+    #[test]
+    fn labels() {
+        assert_eq!(label_suite("test-linux1804-64-ccov/opt-mochitest-plain-e10s-3"), Some("mochitest-plain"));
+        assert_eq!(label_suite("test-linux1804-64-ccov/opt-mochitest-plain"), Some("mochitest-plain"));
+        assert_eq!(label_suite("test-windows10-64-ccov/opt-web-platform-tests-12"), Some("web-platform-tests"));
+        assert_eq!(label_suite("test-linux1804-64-ccov/opt-mochitest-browser-chrome-e10s-1"),
+                   Some("mochitest-browser-chrome"));
+        assert_eq!(label_suite("test-linux1804-64/opt-mochitest-plain-e10s-3"), None);
+        assert_eq!(label_suite("build-linux64-ccov"), None);
+    }
+
+    #[test]
+    fn download_with_fixtures() {
+        api::use_fixtures();
+        let dest = temp_path("artifact.zip");
+        assert!(download(&reqwest::Client::new(), "https://example.invalid/artifact.zip", &dest).is_err());
+        assert!(!dest.exists());
+    }
+}
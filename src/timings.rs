@@ -0,0 +1,94 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use indicatif::HumanBytes;
+
+use crate::progress;
+
+/// Parts of a run that are timed for `--timings`
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Stage {
+    Network,
+    CacheRead,
+    Parse,
+    Diff,
+    Output,
+}
+
+impl Stage {
+    fn name(self) -> &'static str {
+        match self {
+            Stage::Network => "network",
+            Stage::CacheRead => "cache read",
+            Stage::Parse => "parse",
+            Stage::Diff => "diff",
+            Stage::Output => "output",
+        }
+    }
+}
+
+const STAGES: [Stage; 5] = [Stage::Network, Stage::CacheRead, Stage::Parse, Stage::Diff, Stage::Output];
+
+// Timings are process-wide state for the same reason as progress: the
+// timed code is spread across modules that don't share anything to pass a
+// recorder in. `ELAPSED` is the time spent in each stage in nanoseconds,
+// indexed by `Stage`.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static START: OnceLock<Instant> = OnceLock::new();
+static ELAPSED: [AtomicU64; 5] = [AtomicU64::new(0),
+                                  AtomicU64::new(0),
+                                  AtomicU64::new(0),
+                                  AtomicU64::new(0),
+                                  AtomicU64::new(0)];
+
+/// Times a stage until it's dropped
+pub(crate) struct Timer {
+    stage: Stage,
+    start: Instant,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        ELAPSED[self.stage as usize].fetch_add(self.start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Start timing the run; the report is only printed if `enabled`
+pub(crate) fn init(enabled: bool) {
+    START.get_or_init(Instant::now);
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Start timing `stage`; the time is recorded when the timer is dropped
+pub(crate) fn start(stage: Stage) -> Timer {
+    Timer { stage, start: Instant::now() }
+}
+
+/// Time `f` as part of `stage`
+pub(crate) fn time<F: FnOnce() -> R, R>(stage: Stage, f: F) -> R {
+    let _timer = start(stage);
+    f()
+}
+
+/// Print the time spent in each stage, and the requests made, to stderr if
+/// `--timings` was given. This is done whether or not the run succeeded.
+pub(crate) fn report() {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    progress::clear();
+    let (requests, bytes) = progress::fetched_totals();
+    let total = START.get().map(|x| x.elapsed()).unwrap_or_default();
+    eprintln!("TIMINGS:");
+    eprintln!("  {:<10} {:>9.3}s", "total", total.as_secs_f64());
+    for stage in STAGES.iter() {
+        let elapsed = Duration::from_nanos(ELAPSED[*stage as usize].load(Ordering::Relaxed));
+        eprintln!("  {:<10} {:>9.3}s", stage.name(), elapsed.as_secs_f64());
+    }
+    eprintln!("  {:<10} {:>9}", "requests", requests);
+    eprintln!("  {:<10} {:>9}", "received", HumanBytes(bytes).to_string());
+    // Suites are loaded on separate threads, and differences computed on
+    // several, so the stages can add up to more than the total
+    eprintln!("  (stage times are summed across threads)");
+}
@@ -0,0 +1,555 @@
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::report::{self, Report, Totals};
+use crate::output::markdown_escape;
+use crate::{clipboard, editor, CoverageDifference, CoverageType, Error, Result};
+
+/// Column headings for each of `Totals::METRICS`
+const HEADINGS: &[&str] = &[
+    "files", "s1 only", "s2 only", "both", "covered", "coverable", "lines", "s1 %", "s2 %", "cov %",
+];
+
+/// Lines moved by page up and page down
+const PAGE: usize = 20;
+
+/// Categories toggled as filters by the keys 1 to 4
+const FILTERS: &[CoverageType] = &[
+    CoverageType::Suite1Only, CoverageType::Suite2Only, CoverageType::Both, CoverageType::NotCovered,
+];
+
+/// A row in the directory listing
+struct Entry {
+    name: String,
+    path: String,
+    is_dir: bool,
+    totals: Totals,
+}
+
+enum View {
+    Directory,
+    /// Per-line categories for a file, scrolled to a line
+    File { path: String, scroll: usize },
+}
+
+struct App<'a> {
+    report: &'a Report,
+    differences: &'a BTreeMap<String, CoverageDifference>,
+    directory_totals: BTreeMap<String, Totals>,
+    /// Current directory, or "" for the root
+    dir: String,
+    entries: Vec<Entry>,
+    state: TableState,
+    /// Index into `Totals::METRICS` of the sort column
+    sort: usize,
+    descending: bool,
+    view: View,
+    /// Checkout that files are opened from in an editor
+    srcdir: Option<&'a Path>,
+    /// File to open in an editor once the terminal is released
+    edit: Option<String>,
+    /// Message shown in place of the help line until the next key press
+    status: Option<String>,
+    /// Substring that paths under the current directory are searched for
+    query: String,
+    /// Whether key presses are being typed into the search
+    searching: bool,
+    /// Only show entries and lines in these categories, or everything if empty
+    filters: Vec<CoverageType>,
+}
+
+fn metric_value(totals: &Totals, metric: &str) -> f64 {
+    match totals.metric(metric) {
+        Some(value) if value.is_finite() => value,
+        _ => f64::NEG_INFINITY
+    }
+}
+
+fn format_metric(totals: &Totals, metric: &str) -> String {
+    if metric.ends_with("_percent") {
+        if totals.coverable > 0 {
+            format!("{:.1}", metric_value(totals, metric))
+        } else {
+            "-".into()
+        }
+    } else {
+        format!("{}", metric_value(totals, metric))
+    }
+}
+
+/// Number of lines in a category
+fn category_count(totals: &Totals, category: CoverageType) -> i64 {
+    match category {
+        CoverageType::NotRun => totals.lines - totals.coverable,
+        CoverageType::NotCovered => totals.coverable - totals.covered,
+        CoverageType::Suite1Only => totals.suite_1_only,
+        CoverageType::Suite2Only => totals.suite_2_only,
+        CoverageType::Both => totals.both,
+    }
+}
+
+fn category_style(coverage_type: CoverageType) -> Style {
+    match coverage_type {
+        CoverageType::NotRun => Style::default().add_modifier(Modifier::DIM),
+        CoverageType::NotCovered => Style::default().fg(Color::Red),
+        CoverageType::Suite1Only => Style::default().fg(Color::Blue),
+        CoverageType::Suite2Only => Style::default().fg(Color::Green),
+        CoverageType::Both => Style::default().add_modifier(Modifier::DIM),
+    }
+}
+
+impl<'a> App<'a> {
+    fn new(report: &'a Report,
+           differences: &'a BTreeMap<String, CoverageDifference>,
+           srcdir: Option<&'a Path>) -> App<'a> {
+        let mut app = App {
+            report,
+            differences,
+            directory_totals: report::directory_totals(report.files.iter()),
+            dir: String::new(),
+            entries: Vec::new(),
+            state: TableState::default(),
+            sort: Totals::METRICS.iter().position(|x| *x == "suite_2_only").unwrap_or(0),
+            descending: true,
+            view: View::Directory,
+            srcdir,
+            edit: None,
+            status: None,
+            query: String::new(),
+            searching: false,
+            filters: Vec::new(),
+        };
+        app.load_entries(None);
+        app
+    }
+
+    /// Whether a path is listed in the current directory. While searching,
+    /// that's any path below the directory that contains the query.
+    fn in_view(&self, path: &str) -> bool {
+        if self.query.is_empty() {
+            return report::parent_dir(path) == self.dir;
+        }
+        let below = self.dir.is_empty() || path.strip_prefix(self.dir.as_str()).is_some_and(|x| x.starts_with('/'));
+        below && path.to_lowercase().contains(&self.query.to_lowercase())
+    }
+
+    /// Name shown for a path, relative to the current directory
+    fn entry_name(&self, path: &str) -> String {
+        if self.query.is_empty() {
+            path[path.rfind('/').map(|x| x + 1).unwrap_or(0)..].to_owned()
+        } else if self.dir.is_empty() {
+            path.to_owned()
+        } else {
+            path[self.dir.len() + 1..].to_owned()
+        }
+    }
+
+    fn passes_filters(&self, totals: &Totals) -> bool {
+        self.filters.is_empty() || self.filters.iter().any(|x| category_count(totals, *x) > 0)
+    }
+
+    /// Fill the listing for the current directory, selecting `select` if it's
+    /// one of the entries
+    fn load_entries(&mut self, select: Option<&str>) {
+        let mut entries = self.directory_totals
+            .iter()
+            .filter(|(path, _)| !path.is_empty() && **path != self.dir && self.in_view(path))
+            .map(|(path, totals)| Entry {
+                name: format!("{}/", self.entry_name(path)),
+                path: path.clone(),
+                is_dir: true,
+                totals: totals.clone(),
+            })
+            .collect::<Vec<_>>();
+        entries.extend(self.report.files
+            .iter()
+            .filter(|x| self.in_view(&x.path))
+            .map(|x| Entry {
+                name: self.entry_name(&x.path),
+                path: x.path.clone(),
+                is_dir: false,
+                totals: Totals::from_files(Some(x)),
+            }));
+        entries.retain(|x| self.passes_filters(&x.totals));
+        self.entries = entries;
+        self.sort_entries();
+        let index = select.and_then(|path| self.entries.iter().position(|x| x.path == path));
+        self.state.select(if self.entries.is_empty() { None } else { Some(index.unwrap_or(0)) });
+    }
+
+    fn sort_entries(&mut self) {
+        let metric = Totals::METRICS[self.sort];
+        let descending = self.descending;
+        self.entries.sort_by(|a, b| {
+            let order = metric_value(&a.totals, metric).total_cmp(&metric_value(&b.totals, metric));
+            let order = if descending { order.reverse() } else { order };
+            order.then_with(|| a.path.cmp(&b.path))
+        });
+    }
+
+    fn selected(&self) -> Option<&Entry> {
+        self.state.selected().and_then(|x| self.entries.get(x))
+    }
+
+    fn move_selection(&mut self, offset: isize) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let current = self.state.selected().unwrap_or(0) as isize;
+        let index = (current + offset).clamp(0, self.entries.len() as isize - 1);
+        self.state.select(Some(index as usize));
+    }
+
+    fn enter(&mut self) {
+        let (path, is_dir) = match self.selected() {
+            Some(entry) => (entry.path.clone(), entry.is_dir),
+            None => return
+        };
+        if is_dir {
+            self.dir = path;
+            self.query.clear();
+            self.load_entries(None);
+        } else {
+            self.view = View::File { path, scroll: 0 };
+        }
+    }
+
+    fn up(&mut self) {
+        if self.dir.is_empty() {
+            return;
+        }
+        self.query.clear();
+        let previous = std::mem::take(&mut self.dir);
+        self.dir = report::parent_dir(&previous).to_owned();
+        self.load_entries(Some(&previous));
+    }
+
+    /// Lines of a file with their categories, leaving out filtered categories
+    fn file_lines(&self, path: &str) -> Vec<(usize, CoverageType)> {
+        self.differences
+            .get(path)
+            .map(|difference| difference.line_differences
+                 .iter()
+                 .enumerate()
+                 .filter(|(_, x)| self.filters.is_empty() || self.filters.contains(x))
+                 .map(|(i, x)| (i + 1, *x))
+                 .collect())
+            .unwrap_or_default()
+    }
+
+    fn toggle_filter(&mut self, category: CoverageType) {
+        match self.filters.iter().position(|x| *x == category) {
+            Some(index) => {
+                self.filters.remove(index);
+            }
+            None => self.filters.push(category),
+        }
+        let selected = self.selected().map(|x| x.path.clone());
+        self.load_entries(selected.as_deref());
+        if let View::File { ref mut scroll, .. } = self.view {
+            *scroll = 0;
+        }
+    }
+
+    /// The rows of the current view, headings first
+    fn view_rows(&self) -> Vec<Vec<String>> {
+        match self.view {
+            View::Directory => std::iter::once(
+                std::iter::once("path".to_owned()).chain(HEADINGS.iter().map(|x| (*x).to_owned())).collect()
+            ).chain(self.entries.iter().map(|entry| {
+                std::iter::once(if entry.is_dir { format!("{}/", entry.path) } else { entry.path.clone() })
+                    .chain(Totals::METRICS.iter().map(|metric| format_metric(&entry.totals, metric)))
+                    .collect()
+            })).collect(),
+            View::File { ref path, .. } => std::iter::once(vec!["line".to_owned(), "category".to_owned()])
+                .chain(self.file_lines(path)
+                       .into_iter()
+                       .map(|(line, category)| vec![line.to_string(), category.name().to_owned()]))
+                .collect(),
+        }
+    }
+
+    /// Copy the current view to the clipboard as CSV or as a markdown table
+    fn copy_view(&mut self, markdown: bool) {
+        let rows = self.view_rows();
+        let text = if markdown {
+            let mut text = String::new();
+            for (i, row) in rows.iter().enumerate() {
+                let cells = row.iter().map(|x| markdown_escape(x)).collect::<Vec<_>>();
+                text.push_str(&format!("| {} |\n", cells.join(" | ")));
+                if i == 0 {
+                    text.push_str(&format!("|{}\n", "---|".repeat(row.len())));
+                }
+            }
+            Ok(text)
+        } else {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            rows.iter()
+                .try_for_each(|row| writer.write_record(row))
+                .map_err(Error::from)
+                .and_then(|_| writer.into_inner().map_err(|e| Error::from(e.into_error())))
+                .map(|x| String::from_utf8_lossy(&x).into_owned())
+        };
+        self.status = Some(match text.and_then(|text| clipboard::copy(&text)) {
+            Ok(()) => format!("Copied {} rows as {}", rows.len() - 1, if markdown { "markdown" } else { "CSV" }),
+            Err(e) => format!("Failed to copy: {:?}", e),
+        });
+    }
+
+    /// Handle a key press while typing a search
+    fn handle_search_key(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Char(c) => self.query.push(c),
+            KeyCode::Backspace => {
+                self.query.pop();
+            }
+            KeyCode::Esc => {
+                self.query.clear();
+                self.searching = false;
+            }
+            KeyCode::Enter => self.searching = false,
+            _ => return
+        }
+        self.load_entries(None);
+    }
+
+    /// Ask for the selected file, or the one being viewed, to be opened in an editor
+    fn request_edit(&mut self) {
+        let path = match self.view {
+            View::Directory => match self.selected() {
+                Some(entry) if !entry.is_dir => entry.path.clone(),
+                _ => {
+                    self.status = Some("Select a file to open it in an editor".into());
+                    return;
+                }
+            },
+            View::File { ref path, .. } => path.clone(),
+        };
+        if self.srcdir.is_none() {
+            self.status = Some("Pass --srcdir to open files in an editor".into());
+            return;
+        }
+        self.edit = Some(path);
+    }
+
+    /// Handle a key press, returning false when the app should exit
+    fn handle_key(&mut self, code: KeyCode) -> bool {
+        self.status = None;
+        if self.searching {
+            self.handle_search_key(code);
+            return true;
+        }
+        match code {
+            KeyCode::Char('e') => {
+                self.request_edit();
+                return true;
+            }
+            KeyCode::Char('y') | KeyCode::Char('Y') => {
+                self.copy_view(code == KeyCode::Char('Y'));
+                return true;
+            }
+            KeyCode::Char(c @ '1'..='4') => {
+                self.toggle_filter(FILTERS[c as usize - '1' as usize]);
+                return true;
+            }
+            _ => {}
+        }
+        match self.view {
+            View::Directory => match code {
+                KeyCode::Esc if !self.query.is_empty() => {
+                    self.query.clear();
+                    self.load_entries(None);
+                }
+                KeyCode::Char('q') | KeyCode::Esc => return false,
+                KeyCode::Char('/') => self.searching = true,
+                KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+                KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+                KeyCode::PageDown => self.move_selection(PAGE as isize),
+                KeyCode::PageUp => self.move_selection(-(PAGE as isize)),
+                KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => self.enter(),
+                KeyCode::Backspace | KeyCode::Left | KeyCode::Char('h') => self.up(),
+                KeyCode::Char('s') => {
+                    self.sort = (self.sort + 1) % Totals::METRICS.len();
+                    self.sort_entries();
+                }
+                KeyCode::Char('S') => {
+                    self.sort = (self.sort + Totals::METRICS.len() - 1) % Totals::METRICS.len();
+                    self.sort_entries();
+                }
+                KeyCode::Char('r') => {
+                    self.descending = !self.descending;
+                    self.sort_entries();
+                }
+                _ => {}
+            },
+            View::File { ref path, scroll } => {
+                let last = self.file_lines(path).len().saturating_sub(1);
+                let scroll = match code {
+                    KeyCode::Char('q') => return false,
+                    KeyCode::Esc | KeyCode::Backspace | KeyCode::Left | KeyCode::Char('h') => {
+                        self.view = View::Directory;
+                        return true;
+                    }
+                    KeyCode::Down | KeyCode::Char('j') => (scroll + 1).min(last),
+                    KeyCode::Up | KeyCode::Char('k') => scroll.saturating_sub(1),
+                    KeyCode::PageDown => (scroll + PAGE).min(last),
+                    KeyCode::PageUp => scroll.saturating_sub(PAGE),
+                    KeyCode::Home => 0,
+                    KeyCode::End => last,
+                    _ => scroll
+                };
+                if let View::File { scroll: ref mut current, .. } = self.view {
+                    *current = scroll;
+                }
+            }
+        }
+        true
+    }
+
+    fn title(&self, path: &str) -> String {
+        let metadata = &self.report.metadata;
+        let mut title = format!(" s1: {} s2: {} at {} | /{} ",
+                                metadata.suite_1,
+                                metadata.suite_2,
+                                metadata.changeset,
+                                path);
+        if !self.query.is_empty() && matches!(self.view, View::Directory) {
+            title.push_str(&format!("| search: {} ", self.query));
+        }
+        if !self.filters.is_empty() {
+            let names = self.filters.iter().map(|x| x.name()).collect::<Vec<_>>();
+            title.push_str(&format!("| only: {} ", names.join(", ")));
+        }
+        title
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, help] = Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        match self.view {
+            View::Directory => {
+                let header = Row::new(
+                    std::iter::once(Span::raw("name"))
+                        .chain(HEADINGS.iter().enumerate().map(|(i, heading)| {
+                            if i == self.sort {
+                                let arrow = if self.descending { "▼" } else { "▲" };
+                                Span::styled(format!("{}{}", heading, arrow),
+                                             Style::default().add_modifier(Modifier::UNDERLINED))
+                            } else {
+                                Span::raw(*heading)
+                            }
+                        }))
+                        .map(Line::from)
+                ).style(Style::default().add_modifier(Modifier::BOLD));
+                let rows = self.entries.iter().map(|entry| {
+                    let name_style = if entry.is_dir {
+                        Style::default().fg(Color::Cyan)
+                    } else {
+                        Style::default()
+                    };
+                    Row::new(
+                        std::iter::once(Line::from(Span::styled(entry.name.clone(), name_style)))
+                            .chain(Totals::METRICS.iter().map(|metric| {
+                                Line::from(format_metric(&entry.totals, metric)).right_aligned()
+                            }))
+                    )
+                });
+                let widths = std::iter::once(Constraint::Min(20))
+                    .chain(HEADINGS.iter().map(|_| Constraint::Length(10)));
+                let table = Table::new(rows, widths)
+                    .header(header)
+                    .block(Block::default().borders(Borders::ALL).title(self.title(&self.dir)))
+                    .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+                frame.render_stateful_widget(table, main, &mut self.state);
+                if self.searching {
+                    self.render_help(frame, help, &format!("/{}▏  enter done  esc clear", self.query));
+                } else {
+                    self.render_help(frame, help,
+                                     "↑↓ move  enter open  ← up  s/S sort  r reverse  / search  1-4 filter  \
+                                      y/Y copy csv/md  e edit  q quit");
+                }
+            }
+            View::File { ref path, scroll } => {
+                let lines = if self.differences.contains_key(path) {
+                    self.file_lines(path)
+                        .into_iter()
+                        .skip(scroll)
+                        .take(main.height as usize)
+                        .map(|(line, coverage_type)| {
+                            Line::from(vec![
+                                Span::raw(format!("{:>7}  ", line)),
+                                Span::styled(coverage_type.name(), category_style(coverage_type)),
+                            ])
+                        })
+                        .collect::<Vec<_>>()
+                } else {
+                    vec![Line::from("No line data for this file")]
+                };
+                frame.render_widget(
+                    Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(self.title(path))),
+                    main);
+                self.render_help(frame, help,
+                                 "↑↓ scroll  home/end  ← back  1-4 filter  y/Y copy csv/md  e edit  q quit");
+            }
+        }
+    }
+
+    fn render_help(&self, frame: &mut Frame, area: Rect, help: &str) {
+        let paragraph = match self.status {
+            Some(ref status) => Paragraph::new(status.as_str()).style(Style::default().fg(Color::Yellow)),
+            None => Paragraph::new(help)
+        };
+        frame.render_widget(paragraph, area);
+    }
+
+    /// Hand the terminal to an editor for the requested file, then take it back
+    fn open_editor(&mut self, terminal: &mut DefaultTerminal, path: &str) {
+        let srcdir = match self.srcdir {
+            Some(srcdir) => srcdir,
+            None => return
+        };
+        let line = self.differences.get(path).and_then(editor::first_suite_2_only_line);
+        ratatui::restore();
+        let result = editor::open(srcdir, path, line);
+        *terminal = ratatui::init();
+        if let Err(e) = result {
+            self.status = Some(format!("Failed to open {}: {:?}", path, e));
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !self.handle_key(key.code) {
+                    return Ok(());
+                }
+            }
+            if let Some(path) = self.edit.take() {
+                self.open_editor(terminal, &path);
+            }
+        }
+    }
+}
+
+/// Browse the report interactively, starting at the root directory. Files
+/// can be opened from `srcdir` in an editor.
+pub(crate) fn run(report: &Report,
+                  differences: &BTreeMap<String, CoverageDifference>,
+                  srcdir: Option<&Path>) -> Result<()> {
+    if !io::stdout().is_terminal() {
+        return Err(Error::Usage("The tui subcommand requires a terminal".into()));
+    }
+    let mut app = App::new(report, differences, srcdir);
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    Ok(result?)
+}
@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::{parse, Result};
+
+#[derive(Debug, Deserialize)]
+struct WptSubtest {
+    status: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WptResult {
+    test: String,
+    status: String,
+    #[serde(default)]
+    subtests: Vec<WptSubtest>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WptReportData {
+    results: Vec<WptResult>,
+}
+
+fn passed(status: &str) -> bool {
+    status == "OK" || status == "PASS"
+}
+
+/// Count of wpt tests associated with a source directory
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct TestCounts {
+    pub total: u64,
+    /// Tests that didn't pass or that have a subtest that didn't pass
+    pub failing: u64,
+    pub subtests: u64,
+    pub subtests_failing: u64,
+}
+
+/// Test results from a wptreport.json file, grouped by top-level wpt
+/// directory.
+///
+/// There's no direct mapping from wpt tests to the gecko source they
+/// exercise, so we use the heuristic that a test in e.g. /webaudio/
+/// touches the source in a directory named webaudio, such as
+/// dom/media/webaudio/. Only the name of the directory itself is matched;
+/// matching any of its parents too would credit everything under gecko's
+/// dom/ with all the tests in wpt's /dom/.
+pub(crate) struct WptReport {
+    by_dir: BTreeMap<String, TestCounts>,
+}
+
+impl WptReport {
+    pub(crate) fn load(path: &Path) -> Result<WptReport> {
+        let data: WptReportData = parse::from_file(path)?;
+        let mut by_dir: BTreeMap<String, TestCounts> = BTreeMap::new();
+        for result in data.results.iter() {
+            let dir = match result.test.trim_start_matches('/').split('/').next() {
+                Some(dir) if !dir.is_empty() => dir,
+                _ => continue
+            };
+            let counts = by_dir.entry(dir.to_owned()).or_default();
+            let subtests_failing = result.subtests.iter().filter(|x| !passed(&x.status)).count() as u64;
+            counts.total += 1;
+            counts.subtests += result.subtests.len() as u64;
+            counts.subtests_failing += subtests_failing;
+            if !passed(&result.status) || subtests_failing > 0 {
+                counts.failing += 1;
+            }
+        }
+        Ok(WptReport { by_dir })
+    }
+
+    /// Get the counts of tests that are assumed to touch a source directory
+    pub(crate) fn tests_for_dir(&self, dir: &str) -> TestCounts {
+        dir.rsplit('/')
+            .next()
+            .and_then(|name| self.by_dir.get(name))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process;
+
+    use super::WptReport;
+
+    #[test]
+    fn counts() {
+        let data = r#"{"results": [
+            {"test": "/webaudio/a.html", "status": "OK", "subtests": [{"status": "PASS"}, {"status": "FAIL"}]},
+            {"test": "/webaudio/b.html", "status": "PASS"},
+            {"test": "/dom/c.html", "status": "TIMEOUT"}
+        ]}"#;
+        let path = std::env::temp_dir().join(format!("wptcoverage-wptreport-{}.json", process::id()));
+        fs::write(&path, data).unwrap();
+        let report = WptReport::load(&path);
+        fs::remove_file(&path).unwrap();
+        let report = report.unwrap();
+
+        let tests = report.tests_for_dir("dom/media/webaudio");
+        assert_eq!((tests.total, tests.failing, tests.subtests, tests.subtests_failing), (2, 1, 2, 1));
+        let tests = report.tests_for_dir("dom");
+        assert_eq!((tests.total, tests.failing, tests.subtests, tests.subtests_failing), (1, 1, 0, 0));
+        // Only the directory's own name is matched
+        assert_eq!(report.tests_for_dir("dom/media").total, 0);
+    }
+}